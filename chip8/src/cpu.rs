@@ -0,0 +1,335 @@
+use crate::{
+    pal::{Delay, Error, Keypad, Screen},
+    ram::Ram,
+};
+
+/// Where execution starts after a reset.
+pub const PC_START: u16 = 0x200;
+
+/// Subroutine calls which can be nested.
+pub const STACK_DEPTH: usize = 16;
+
+type Result<T = ()> = core::result::Result<T, Error>;
+
+/// The registers, timers and stack, with the original CHIP-8 behaviour:
+/// 8xy6 and 8xyE shift Vy, Fx55 and Fx65 advance I, and Bnnn adds V0.
+pub struct Cpu {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    stack: [u16; STACK_DEPTH],
+    sp: usize,
+    delay_timer: u8,
+    sound_timer: u8,
+    rng: u32,
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Self {
+            v: [0; 16],
+            i: 0,
+            pc: PC_START,
+            stack: [0; STACK_DEPTH],
+            sp: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            rng: 1,
+        }
+    }
+
+    /// Seed the generator used by Cxnn. A zero seed is replaced, as the
+    /// generator would only ever produce zero from it.
+    pub fn seed(&mut self, seed: u32) {
+        self.rng = if seed == 0 { 1 } else { seed };
+    }
+
+    pub fn v(&self, reg: u8) -> u8 {
+        self.v[(reg & 0xF) as usize]
+    }
+
+    pub fn set_v(&mut self, reg: u8, value: u8) {
+        self.v[(reg & 0xF) as usize] = value;
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Decrement the delay and sound timers. Call this at 60Hz.
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    /// Fetch, decode and execute a single instruction. Fx0A never blocks:
+    /// with no key held the PC stays put, so it runs again next step.
+    pub fn step<S, K, D>(
+        &mut self,
+        ram: &mut Ram,
+        screen: &mut S,
+        keypad: &mut K,
+        delay: &mut D,
+    ) -> Result
+    where
+        S: Screen,
+        S::Error: Into<Error>,
+        K: Keypad,
+        K::Error: Into<Error>,
+        D: Delay,
+    {
+        let opcode = match ram.read_bytes(self.pc, 2) {
+            &[high, low] => u16::from_be_bytes([high, low]),
+            _ => return Err(Error::Instruction(0)),
+        };
+
+        let x = (opcode >> 8 & 0xF) as u8;
+        let y = (opcode >> 4 & 0xF) as u8;
+        let (vx, vy) = (self.v(x), self.v(y));
+        let nnn = opcode & 0xFFF;
+        let nn = opcode as u8;
+        let mut next = self.pc.wrapping_add(2);
+
+        match opcode >> 12 {
+            0x0 => match opcode {
+                0x00E0 => screen.clear().map_err(Into::<Error>::into)?,
+                0x00EE => {
+                    self.sp = self.sp.checked_sub(1).ok_or(Error::Stack)?;
+                    next = self.stack[self.sp];
+                }
+                // 0nnn calls a machine code routine, which can't be run here.
+                _ => {}
+            },
+            0x1 => next = nnn,
+            0x2 => {
+                let slot = self.stack.get_mut(self.sp).ok_or(Error::Stack)?;
+                *slot = next;
+                self.sp += 1;
+                next = nnn;
+            }
+            0x3 if vx == nn => next = next.wrapping_add(2),
+            0x4 if vx != nn => next = next.wrapping_add(2),
+            0x5 if opcode & 0xF == 0 && vx == vy => next = next.wrapping_add(2),
+            0x9 if opcode & 0xF == 0 && vx != vy => next = next.wrapping_add(2),
+            0x3 | 0x4 => {}
+            0x5 | 0x9 if opcode & 0xF == 0 => {}
+            0x6 => self.set_v(x, nn),
+            0x7 => self.set_v(x, vx.wrapping_add(nn)),
+            0x8 => self.alu(opcode, x, vx, vy)?,
+            0xA => self.i = nnn,
+            0xB => next = nnn.wrapping_add(self.v(0) as u16),
+            0xC => {
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 17;
+                self.rng ^= self.rng << 5;
+                self.set_v(x, self.rng as u8 & nn);
+            }
+            0xD => {
+                let sprite = ram.read_bytes(self.i, (opcode & 0xF) as usize);
+                let collided = screen.xor(vx, vy, sprite).map_err(Into::<Error>::into)?;
+                self.set_v(0xF, collided as u8);
+            }
+            0xE if nn == 0x9E || nn == 0xA1 => {
+                let key = keypad.read_key(delay).map_err(Into::<Error>::into)?;
+
+                if (key == Some(vx)) == (nn == 0x9E) {
+                    next = next.wrapping_add(2);
+                }
+            }
+            0xF => match nn {
+                0x07 => self.set_v(x, self.delay_timer),
+                0x0A => match keypad.read_key(delay).map_err(Into::<Error>::into)? {
+                    Some(key) => self.set_v(x, key),
+                    None => next = self.pc,
+                },
+                0x15 => self.delay_timer = vx,
+                0x18 => self.sound_timer = vx,
+                0x1E => self.i = self.i.wrapping_add(vx as u16),
+                0x29 => self.i = ram.get_sprite_addr(vx),
+                0x33 => ram.write_bytes(self.i, &[vx / 100, vx / 10 % 10, vx % 10]),
+                0x55 => {
+                    ram.write_bytes(self.i, &self.v[..=x as usize]);
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+                0x65 => {
+                    let bytes = ram.read_bytes(self.i, x as usize + 1);
+                    self.v[..bytes.len()].copy_from_slice(bytes);
+                    self.v[bytes.len()..=x as usize].fill(0);
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+                _ => return Err(Error::Instruction(opcode)),
+            },
+            _ => return Err(Error::Instruction(opcode)),
+        }
+
+        self.pc = next;
+        Ok(())
+    }
+
+    /// 8xyn, setting VF after the result so that it wins when x is F.
+    fn alu(&mut self, opcode: u16, x: u8, vx: u8, vy: u8) -> Result {
+        let (result, flag) = match opcode & 0xF {
+            0x0 => (vy, None),
+            0x1 => (vx | vy, None),
+            0x2 => (vx & vy, None),
+            0x3 => (vx ^ vy, None),
+            0x4 => {
+                let (sum, carry) = vx.overflowing_add(vy);
+                (sum, Some(carry as u8))
+            }
+            0x5 => {
+                let (diff, borrow) = vx.overflowing_sub(vy);
+                (diff, Some(!borrow as u8))
+            }
+            0x6 => (vy >> 1, Some(vy & 1)),
+            0x7 => {
+                let (diff, borrow) = vy.overflowing_sub(vx);
+                (diff, Some(!borrow as u8))
+            }
+            0xE => (vy << 1, Some(vy >> 7)),
+            _ => return Err(Error::Instruction(opcode)),
+        };
+
+        self.set_v(x, result);
+
+        if let Some(flag) = flag {
+            self.set_v(0xF, flag);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullScreen;
+
+    impl Screen for NullScreen {
+        type Error = Error;
+
+        fn xor(&mut self, _: u8, _: u8, _: &[u8]) -> Result<bool> {
+            Ok(false)
+        }
+
+        fn clear(&mut self) -> Result {
+            Ok(())
+        }
+    }
+
+    /// Holds `Some(key)` forever, or nothing for `None`.
+    struct HeldKey(Option<u8>);
+
+    impl Keypad for HeldKey {
+        type Error = Error;
+
+        fn key_is_pressed(&self) -> Result<bool> {
+            Ok(self.0.is_some())
+        }
+
+        fn read_key<D: Delay>(&mut self, _: &mut D) -> Result<Option<u8>> {
+            Ok(self.0)
+        }
+    }
+
+    struct NoDelay;
+
+    impl Delay for NoDelay {
+        type Error = Error;
+
+        fn delay_us(&mut self, _: u32) -> Result {
+            Ok(())
+        }
+    }
+
+    fn run(rom: &[u8], key: Option<u8>, steps: usize) -> (Cpu, Ram) {
+        let (mut cpu, mut ram) = (Cpu::new(), Ram::new());
+        ram.write_bytes(PC_START, rom);
+
+        for _ in 0..steps {
+            cpu.step(&mut ram, &mut NullScreen, &mut HeldKey(key), &mut NoDelay)
+                .unwrap();
+        }
+
+        (cpu, ram)
+    }
+
+    #[test]
+    fn add_sets_carry_after_the_result() {
+        let (cpu, _) = run(&[0x6F, 0xFF, 0x61, 0x02, 0x8F, 0x14], None, 3);
+
+        assert_eq!(cpu.v(0xF), 1);
+    }
+
+    #[test]
+    fn call_and_return() {
+        let (cpu, _) = run(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE], None, 2);
+
+        assert_eq!(cpu.pc(), PC_START + 2);
+    }
+
+    #[test]
+    fn return_with_empty_stack_fails() {
+        let (mut cpu, mut ram) = (Cpu::new(), Ram::new());
+        ram.write_bytes(PC_START, &[0x00, 0xEE]);
+
+        assert_eq!(
+            cpu.step(&mut ram, &mut NullScreen, &mut HeldKey(None), &mut NoDelay),
+            Err(Error::Stack)
+        );
+    }
+
+    #[test]
+    fn bcd_writes_three_digits() {
+        let (_, ram) = run(&[0x60, 0xFE, 0xA3, 0x00, 0xF0, 0x33], None, 3);
+
+        assert_eq!(ram.read_bytes(0x300, 3), &[2, 5, 4]);
+    }
+
+    #[test]
+    fn key_wait_holds_pc_until_a_key_is_held() {
+        let (cpu, _) = run(&[0xF3, 0x0A], None, 3);
+        assert_eq!(cpu.pc(), PC_START);
+
+        let (cpu, _) = run(&[0xF3, 0x0A], Some(9), 1);
+        assert_eq!((cpu.pc(), cpu.v(3)), (PC_START + 2, 9));
+    }
+
+    #[test]
+    fn delay_timer_counts_down() {
+        let (mut cpu, _) = run(&[0x60, 0x02, 0xF0, 0x15], None, 2);
+
+        cpu.tick_timers();
+        assert_eq!(cpu.delay_timer(), 1);
+        cpu.tick_timers();
+        cpu.tick_timers();
+        assert_eq!(cpu.delay_timer(), 0);
+    }
+}
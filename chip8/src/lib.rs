@@ -0,0 +1,8 @@
+//! A CHIP-8 interpreter core with no hardware of its own. The screen, keypad
+//! and delays are supplied through the `pal` traits.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod cpu;
+pub mod pal;
+pub mod ram;
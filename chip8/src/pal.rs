@@ -0,0 +1,37 @@
+//! The platform abstraction layer: what the core needs from the hardware.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Screen,
+    Keypad,
+    /// 2nnn with the stack full, or 00EE with it empty.
+    Stack,
+    /// An opcode which isn't part of CHIP-8.
+    Instruction(u16),
+}
+
+pub trait Screen {
+    type Error;
+
+    /// XOR a sprite onto the display with its top-left corner at `x`, `y`,
+    /// returning whether any lit pixel was turned off.
+    fn xor(&mut self, x: u8, y: u8, data: &[u8]) -> Result<bool, Self::Error>;
+
+    fn clear(&mut self) -> Result<(), Self::Error>;
+}
+
+pub trait Keypad {
+    type Error;
+
+    /// Whether any key is held, without scanning for which.
+    fn key_is_pressed(&self) -> Result<bool, Self::Error>;
+
+    /// The key held now, if any.
+    fn read_key<D: Delay>(&mut self, delay: &mut D) -> Result<Option<u8>, Self::Error>;
+}
+
+pub trait Delay {
+    type Error;
+
+    fn delay_us(&mut self, us: u32) -> Result<(), Self::Error>;
+}
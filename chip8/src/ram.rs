@@ -0,0 +1,97 @@
+/// Bytes of addressable memory.
+pub const RAM_SIZE: usize = 0x1000;
+
+/// Where the hex digit font starts.
+pub const FONT_START: u16 = 0x050;
+
+/// Bytes in each font sprite.
+pub const SPRITE_BYTES: u16 = 5;
+
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// 4KiB of RAM with the font loaded at `FONT_START`. Accesses which run off
+/// the end are cut short rather than wrapping or panicking.
+pub struct Ram {
+    bytes: [u8; RAM_SIZE],
+}
+
+impl Ram {
+    pub fn new() -> Self {
+        let mut ram = Self {
+            bytes: [0; RAM_SIZE],
+        };
+
+        ram.write_bytes(FONT_START, &FONT);
+        ram
+    }
+
+    /// Up to `len` bytes from `addr`, fewer if they would run past the end.
+    pub fn read_bytes(&self, addr: u16, len: usize) -> &[u8] {
+        let start = (addr as usize).min(RAM_SIZE);
+        let end = start.saturating_add(len).min(RAM_SIZE);
+        &self.bytes[start..end]
+    }
+
+    /// Copy `bytes` to `addr`, dropping any which would land past the end.
+    pub fn write_bytes(&mut self, addr: u16, bytes: &[u8]) {
+        let start = (addr as usize).min(RAM_SIZE);
+        let len = bytes.len().min(RAM_SIZE - start);
+        self.bytes[start..start + len].copy_from_slice(&bytes[..len]);
+    }
+
+    /// The address of the font sprite for the low nibble of `key`, as Fx29.
+    pub fn get_sprite_addr(&self, key: u8) -> u16 {
+        FONT_START + (key & 0xF) as u16 * SPRITE_BYTES
+    }
+}
+
+impl Default for Ram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_are_cut_short_at_the_end() {
+        let ram = Ram::new();
+
+        assert_eq!(ram.read_bytes(0xFFE, 4).len(), 2);
+        assert_eq!(ram.read_bytes(0xFFFF, 4).len(), 0);
+    }
+
+    #[test]
+    fn writes_past_the_end_are_dropped() {
+        let mut ram = Ram::new();
+        ram.write_bytes(0xFFE, &[1, 2, 3]);
+
+        assert_eq!(ram.read_bytes(0xFFE, 2), &[1, 2]);
+    }
+
+    #[test]
+    fn sprite_addr_points_at_font() {
+        let ram = Ram::new();
+
+        assert_eq!(ram.read_bytes(ram.get_sprite_addr(0xA), 5), &FONT[50..55]);
+    }
+}
@@ -1,6 +1,8 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* The last 4K sector is kept for settings, see src/settings.rs, and the
+     * one before it for a separately flashed ROM, see src/roms/flashed.rs */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 8K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
@@ -0,0 +1,88 @@
+//! The two user buttons on the Waveshare Pico-OLED-1.3, KEY0 and KEY1, for
+//! controlling the firmware without taking keys from the CHIP-8 keypad. The
+//! buttons switch to ground, so their pins need pull-ups.
+//!
+//! The board wires KEY0 to GPIO15 and KEY1 to GPIO17, but here those are the
+//! buzzer and the SD card's chip select, and the screen takes GPIO8 to 12.
+//! With the `buttons` feature the firmware expects KEY0 on GPIO20 and KEY1 on
+//! GPIO21 instead, which are free and sit on the same side of the Pico.
+
+use embedded_hal::digital::v2::InputPin;
+
+/// Consecutive polls a button must read the same before it is believed.
+/// Polled once per 60Hz tick, this rides out about 30ms of contact bounce.
+pub const DEBOUNCE_POLLS: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Button {
+    Key0,
+    Key1,
+}
+
+#[derive(Default)]
+struct State {
+    pressed: bool,
+    changed: bool,
+    reading: bool,
+    polls: u8,
+}
+
+impl State {
+    fn update(&mut self, reading: bool) {
+        self.changed = false;
+
+        if reading != self.reading {
+            self.reading = reading;
+            self.polls = 0;
+        }
+
+        if self.polls < DEBOUNCE_POLLS {
+            self.polls += 1;
+
+            if self.polls == DEBOUNCE_POLLS && reading != self.pressed {
+                self.pressed = reading;
+                self.changed = true;
+            }
+        }
+    }
+}
+
+/// KEY0 and KEY1, debounced. Call `poll` regularly, then ask about each
+/// button. A pin which can't be read counts as released.
+pub struct Buttons<P0: InputPin, P1: InputPin> {
+    key0: P0,
+    key1: P1,
+    states: [State; 2],
+}
+
+impl<P0: InputPin, P1: InputPin> Buttons<P0, P1> {
+    pub fn new(key0: P0, key1: P1) -> Self {
+        Self {
+            key0,
+            key1,
+            states: Default::default(),
+        }
+    }
+
+    /// Read both buttons. `just_pressed` reports presses from the latest poll
+    /// only.
+    pub fn poll(&mut self) {
+        self.states[0].update(self.key0.is_low().unwrap_or(false));
+        self.states[1].update(self.key1.is_low().unwrap_or(false));
+    }
+
+    /// Whether `button` is held down.
+    pub fn pressed(&self, button: Button) -> bool {
+        self.state(button).pressed
+    }
+
+    /// Whether `button` went down on the latest poll.
+    pub fn just_pressed(&self, button: Button) -> bool {
+        self.pressed(button) && self.state(button).changed
+    }
+
+    fn state(&self, button: Button) -> &State {
+        &self.states[button as usize]
+    }
+}
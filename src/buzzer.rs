@@ -0,0 +1,142 @@
+use embedded_hal::PwmPin;
+use rp_pico::hal::pwm::{FreeRunning, Slice, SliceId};
+
+pub const DEFAULT_TONE_HZ: u16 = 440;
+
+/// The audible range. The slice can represent anything down to around 8Hz
+/// with a 125MHz system clock, so the lower bound is never a constraint.
+pub const MIN_TONE_HZ: u16 = 20;
+pub const MAX_TONE_HZ: u16 = 20_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    A,
+    B,
+}
+
+/// A square wave tone on one channel of a PWM slice. The channel's output pin
+/// must already be assigned with `output_to`.
+///
+/// The divider is the smallest integer which lets the period fit in the
+/// 16-bit counter, which keeps the counter as fine-grained as possible. The
+/// frequency error is at most `hz² × divider / sys_hz`, about 3Hz at the top
+/// of the audible range with a 125MHz clock, and well under 1Hz below 5kHz.
+pub struct Buzzer<I: SliceId> {
+    slice: Slice<I, FreeRunning>,
+    channel: Channel,
+    sys_hz: u32,
+    top: u16,
+    duty_pct: u8,
+    on: bool,
+}
+
+impl<I: SliceId> Buzzer<I> {
+    /// `sys_hz` is the system clock frequency, which drives the slice.
+    pub fn new(slice: Slice<I, FreeRunning>, channel: Channel, sys_hz: u32, tone_hz: u16) -> Self {
+        let mut buzzer = Self {
+            slice,
+            channel,
+            sys_hz,
+            top: 0,
+            duty_pct: 50,
+            on: false,
+        };
+
+        buzzer.set_frequency(tone_hz);
+        buzzer.slice.enable();
+        buzzer
+    }
+
+    /// Change the tone, clamped to `MIN_TONE_HZ..=MAX_TONE_HZ`.
+    pub fn set_frequency(&mut self, hz: u16) {
+        let (div, top) = timing(self.sys_hz, hz);
+
+        self.top = top;
+        self.slice.set_div_int(div);
+        self.slice.set_div_frac(0);
+        self.slice.set_top(top);
+        self.update();
+    }
+
+    /// Lower values give a softer click, 50% a full square wave.
+    pub fn set_duty_percent(&mut self, pct: u8) {
+        self.duty_pct = pct.min(100);
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let duty = if self.on {
+            let duty = (self.top as u32 + 1) * self.duty_pct as u32 / 100;
+            duty.min(u16::MAX as u32) as u16
+        } else {
+            0
+        };
+
+        match self.channel {
+            Channel::A => self.slice.channel_a.set_duty(duty),
+            Channel::B => self.slice.channel_b.set_duty(duty),
+        }
+    }
+
+    pub fn on(&mut self) {
+        self.on = true;
+        self.update();
+    }
+
+    pub fn off(&mut self) {
+        self.on = false;
+        self.update();
+    }
+}
+
+/// The divider and top values giving a period closest to `hz`, clamped to
+/// `MIN_TONE_HZ..=MAX_TONE_HZ`, from a `sys_hz` clock. The counter runs from
+/// 0 to `top`, so the tone is `sys_hz / div / (top + 1)`.
+fn timing(sys_hz: u32, hz: u16) -> (u8, u16) {
+    let hz = hz.clamp(MIN_TONE_HZ, MAX_TONE_HZ) as u32;
+    let div = (sys_hz / hz / 0x10000 + 1).min(255);
+    let top = (sys_hz / div / hz).clamp(2, 0x10000) - 1;
+
+    (div as u8, top as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYS_HZ: u32 = 125_000_000;
+
+    fn tone_hz(sys_hz: u32, (div, top): (u8, u16)) -> f64 {
+        sys_hz as f64 / div as f64 / (top as f64 + 1.0)
+    }
+
+    #[test]
+    fn timing_is_within_tolerance_across_audible_range() {
+        for hz in [MIN_TONE_HZ, 50, 440, 1_000, 4_999, 12_345, MAX_TONE_HZ] {
+            let (div, top) = timing(SYS_HZ, hz);
+            let tolerance = (hz as f64).powi(2) * div as f64 / SYS_HZ as f64;
+            let error = (tone_hz(SYS_HZ, (div, top)) - hz as f64).abs();
+
+            assert!(
+                error <= tolerance,
+                "{}Hz came out {}Hz off with div {} and top {}",
+                hz,
+                error,
+                div,
+                top
+            );
+        }
+    }
+
+    #[test]
+    fn timing_uses_smallest_divider_which_fits() {
+        assert_eq!(timing(SYS_HZ, 440).0, 5);
+        assert_eq!(timing(SYS_HZ, 5_000).0, 1);
+    }
+
+    #[test]
+    fn timing_clamps_to_audible_range() {
+        assert_eq!(timing(SYS_HZ, 1), timing(SYS_HZ, MIN_TONE_HZ));
+        assert_eq!(timing(SYS_HZ, u16::MAX), timing(SYS_HZ, MAX_TONE_HZ));
+    }
+}
@@ -0,0 +1,120 @@
+//! Works out the keymap of a keypad wired differently from the Waveshare
+//! one, by asking for each key in turn and seeing which intersection of the
+//! matrix answers. Holding `CALIBRATE_KEY` at power-on starts it.
+
+use crate::{
+    delay::Delay, keypad::Keymap, menu::KEY_SELECT, screen::GLYPH_HEIGHT, types, Keys, Oled,
+};
+use chip8::pal::Delay as _;
+
+/// Held at power-on to calibrate the keypad. It is read before the saved
+/// keymap is loaded, so this is the key in A's place on the Waveshare
+/// keypad, bottom left.
+pub const CALIBRATE_KEY: u8 = 0xA;
+
+/// The matrix is read this often while waiting for a key.
+const POLL_US: u32 = 10_000;
+
+/// A reading must hold for this many polls to count, so bounce and the
+/// moment two keys are pressed one after the other aren't taken as a press.
+const STABLE_POLLS: u8 = 3;
+
+/// How long a complaint stays up before the prompt comes back.
+const NOTICE_US: u32 = 1_000_000;
+
+const LINE: u8 = GLYPH_HEIGHT + 2;
+
+/// Ask for each key from 0 to F and build the keymap from where each was
+/// found. A press of more than one key, or of one already given, is
+/// complained about and asked for again. The keypad is left using the new
+/// keymap, with which `KEY_SELECT` is pressed to keep it. Returns the
+/// keymap and whether it is to be saved.
+pub fn run(
+    screen: &mut Oled,
+    keypad: &mut Keys,
+    delay: &mut Delay,
+) -> types::Result<(Keymap, bool)> {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let mut keymap = [[0; 4]; 4];
+    let mut found = [[false; 4]; 4];
+    let mut key = 0;
+
+    while key < 16 {
+        let mut prompt = *b"PRESS KEY ?";
+        prompt[10] = HEX[key as usize];
+        show(screen, core::str::from_utf8(&prompt).unwrap_or(""))?;
+
+        let complaint = match wait_for_press(keypad, delay)? {
+            None => "ONE KEY ONLY",
+            Some((row, col)) if found[row][col] => "ALREADY USED",
+            Some((row, col)) => {
+                keymap[row][col] = key;
+                found[row][col] = true;
+                key += 1;
+                wait_for_release(keypad, delay)?;
+                continue;
+            }
+        };
+
+        show(screen, complaint)?;
+        wait_for_release(keypad, delay)?;
+        delay.delay_us(NOTICE_US)?;
+    }
+
+    keypad.set_keymap(keymap);
+    show(screen, "5 TO SAVE")?;
+    screen.draw_str(0, LINE, "ELSE SKIP")?;
+    let save = keypad.wait_for_key(delay)? == KEY_SELECT;
+
+    wait_for_release(keypad, delay)?;
+    screen.clear()?;
+    Ok((keymap, save))
+}
+
+fn show(screen: &mut Oled, text: &str) -> types::Result {
+    screen.clear()?;
+    screen.draw_str(0, 0, text)?;
+    Ok(())
+}
+
+/// Wait for the matrix to settle with something pressed, returning the
+/// `(row, col)` pressed, or `None` if it was more than one.
+fn wait_for_press(keypad: &mut Keys, delay: &mut Delay) -> types::Result<Option<(usize, usize)>> {
+    let mut last = [[false; 4]; 4];
+    let mut stable = 0;
+
+    loop {
+        delay.delay_us(POLL_US)?;
+        let matrix = keypad.read_matrix(delay)?;
+
+        stable = if matrix == last { stable + 1 } else { 0 };
+        last = matrix;
+
+        let mut pressed = (0..4)
+            .flat_map(|row| (0..4).map(move |col| (row, col)))
+            .filter(|&(row, col)| matrix[row][col]);
+
+        match (pressed.next(), pressed.next()) {
+            (None, _) => continue,
+            _ if stable < STABLE_POLLS => continue,
+            (Some(cell), None) => return Ok(Some(cell)),
+            (Some(_), Some(_)) => return Ok(None),
+        }
+    }
+}
+
+fn wait_for_release(keypad: &mut Keys, delay: &mut Delay) -> types::Result {
+    loop {
+        delay.delay_us(POLL_US)?;
+
+        if keypad
+            .read_matrix(delay)?
+            .iter()
+            .flatten()
+            .all(|&active| !active)
+        {
+            return Ok(());
+        }
+    }
+}
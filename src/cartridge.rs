@@ -0,0 +1,332 @@
+//! Cartridges: several ROMs in one `.c8p` file, each with the settings it
+//! runs best with, so picking a game sets everything up for it.
+//!
+//! A cartridge is read whole into memory, from the SD card or anywhere in
+//! flash, and parsed in place. All numbers are little-endian.
+//!
+//! | Offset | Size | Contents                                   |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 4    | `C8PK`                                     |
+//! | 4      | 1    | Version, currently 1                       |
+//! | 5      | 1    | Number of entries, at least one            |
+//! | 6      | 2    | Reserved, zero                             |
+//! | 8      | 42n  | The entries                                |
+//!
+//! followed by the ROMs, anywhere after the entries. Each entry is:
+//!
+//! | Offset | Size | Contents                                   |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 16   | Name, ASCII padded with zeros              |
+//! | 16     | 4    | Offset of the ROM from the start of file   |
+//! | 20     | 2    | Length of the ROM                          |
+//! | 22     | 1    | Flags: bit 0 quirks, 1 rate and 2 keymap   |
+//! | 23     | 1    | Quirks, as saved in the settings sector    |
+//! | 24     | 2    | Instructions per second                    |
+//! | 26     | 16   | Keymap, row by row                         |
+//!
+//! Each flag says whether the field it names is used. When it isn't, the
+//! ROM runs with the user's own settings for it.
+
+use crate::{
+    emulator::MAX_ROM_SIZE,
+    keypad::{validate_keymap, Keymap},
+    quirks::Quirks,
+};
+use core::fmt;
+
+/// The largest cartridge which can be read from the SD card.
+pub const MAX_SIZE: usize = 32 * 1024;
+
+const MAGIC: [u8; 4] = *b"C8PK";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = 42;
+const NAME_LEN: usize = 16;
+
+const HAS_QUIRKS: u8 = 1 << 0;
+const HAS_RATE: u8 = 1 << 1;
+const HAS_KEYMAP: u8 = 1 << 2;
+
+type Result<T = ()> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    Truncated,
+    BadMagic,
+    Version,
+    Empty,
+    Name,
+    RomSize,
+    Rate,
+    Keymap,
+}
+
+impl Error {
+    /// The message for `Display`, for drawing without formatting.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::Truncated => "file is cut short",
+            Error::BadMagic => "not a cartridge",
+            Error::Version => "unsupported version",
+            Error::Empty => "no roms in cartridge",
+            Error::Name => "bad rom name",
+            Error::RomSize => "bad rom size",
+            Error::Rate => "bad speed",
+            Error::Keymap => "bad keymap",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One ROM from a cartridge, with its recommended settings.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    pub name: &'a str,
+    pub rom: &'a [u8],
+    pub quirks: Option<Quirks>,
+    /// Instructions per second, as passed to `CycleGovernor::set_rate`.
+    pub rate: Option<u32>,
+    pub keymap: Option<Keymap>,
+}
+
+/// A parsed cartridge, borrowing the bytes it was read from.
+pub struct Cartridge<'a> {
+    data: &'a [u8],
+    len: usize,
+}
+
+impl<'a> Cartridge<'a> {
+    /// Check the header and every entry, so that a cartridge which parses
+    /// can't fail later on.
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let header = data.get(..HEADER_LEN).ok_or(Error::Truncated)?;
+
+        if header[..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        if header[4] != VERSION {
+            return Err(Error::Version);
+        }
+
+        let cartridge = Self {
+            data,
+            len: header[5] as usize,
+        };
+
+        if cartridge.is_empty() {
+            return Err(Error::Empty);
+        }
+
+        for index in 0..cartridge.len {
+            cartridge.decode(index)?;
+        }
+
+        Ok(cartridge)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Entry<'a>> {
+        (index < self.len)
+            .then(|| self.decode(index).ok())
+            .flatten()
+    }
+
+    fn decode(&self, index: usize) -> Result<Entry<'a>> {
+        let start = HEADER_LEN + index * ENTRY_LEN;
+        let entry = self
+            .data
+            .get(start..start + ENTRY_LEN)
+            .ok_or(Error::Truncated)?;
+        let word = |at: usize| u16::from_le_bytes([entry[at], entry[at + 1]]);
+        let flags = entry[22];
+
+        let name = &entry[..NAME_LEN];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(NAME_LEN)];
+        let name = core::str::from_utf8(name)
+            .ok()
+            .filter(|name| !name.is_empty())
+            .ok_or(Error::Name)?;
+
+        let offset = u32::from_le_bytes([entry[16], entry[17], entry[18], entry[19]]) as usize;
+        let len = word(20) as usize;
+
+        if len == 0 || len > MAX_ROM_SIZE {
+            return Err(Error::RomSize);
+        }
+
+        let end = offset.checked_add(len).ok_or(Error::Truncated)?;
+        let rom = self.data.get(offset..end).ok_or(Error::Truncated)?;
+
+        let rate = match (flags & HAS_RATE != 0).then(|| word(24)) {
+            Some(0) => return Err(Error::Rate),
+            rate => rate.map(u32::from),
+        };
+
+        let keymap = if flags & HAS_KEYMAP != 0 {
+            let mut keymap: Keymap = [[0; 4]; 4];
+
+            for (key, &byte) in keymap.iter_mut().flatten().zip(&entry[26..]) {
+                *key = byte;
+            }
+
+            validate_keymap(&keymap).map_err(|_| Error::Keymap)?;
+            Some(keymap)
+        } else {
+            None
+        };
+
+        Ok(Entry {
+            name,
+            rom,
+            quirks: (flags & HAS_QUIRKS != 0).then(|| Quirks::from_bits(entry[23])),
+            rate,
+            keymap,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypad::KEYMAP;
+
+    const ROM_A: &[u8] = &[0x00, 0xE0, 0x12, 0x02];
+    const ROM_B: &[u8] = &[0x12, 0x00];
+
+    fn header(entries: u8) -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+        file.extend_from_slice(&[VERSION, entries, 0, 0]);
+        file
+    }
+
+    fn entry(name: &str, offset: u32, len: u16, flags: u8) -> Vec<u8> {
+        let mut entry = vec![0; ENTRY_LEN];
+        entry[..name.len()].copy_from_slice(name.as_bytes());
+        entry[16..20].copy_from_slice(&offset.to_le_bytes());
+        entry[20..22].copy_from_slice(&len.to_le_bytes());
+        entry[22] = flags;
+        entry
+    }
+
+    /// A cartridge of `ROM_A`, with every setting, and `ROM_B`, with none.
+    fn two_entries() -> Vec<u8> {
+        let roms_at = (HEADER_LEN + 2 * ENTRY_LEN) as u32;
+        let mut first = entry("PONG", roms_at, ROM_A.len() as u16, 0b111);
+        first[23] = 0b0100_1001;
+        first[24..26].copy_from_slice(&700u16.to_le_bytes());
+
+        for (byte, &key) in first[26..].iter_mut().zip(KEYMAP.iter().rev().flatten()) {
+            *byte = key;
+        }
+
+        let mut file = header(2);
+        file.extend(first);
+        file.extend(entry(
+            "TETRIS",
+            roms_at + ROM_A.len() as u32,
+            ROM_B.len() as u16,
+            0,
+        ));
+        file.extend_from_slice(ROM_A);
+        file.extend_from_slice(ROM_B);
+        file
+    }
+
+    #[test]
+    fn parses_two_entries() {
+        let file = two_entries();
+        let cartridge = Cartridge::parse(&file).unwrap();
+        assert_eq!(cartridge.len(), 2);
+
+        let mut keymap = KEYMAP;
+        keymap.reverse();
+
+        let first = cartridge.get(0).unwrap();
+        assert_eq!(first.name, "PONG");
+        assert_eq!(first.rom, ROM_A);
+        assert_eq!(first.quirks, Some(Quirks::from_bits(0b0100_1001)));
+        assert_eq!(first.rate, Some(700));
+        assert_eq!(first.keymap, Some(keymap));
+
+        let second = cartridge.get(1).unwrap();
+        assert_eq!(second.name, "TETRIS");
+        assert_eq!(second.rom, ROM_B);
+        assert_eq!(second.quirks, None);
+        assert_eq!(second.rate, None);
+        assert_eq!(second.keymap, None);
+
+        assert!(cartridge.get(2).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut file = two_entries();
+        file[0] = b'X';
+
+        assert_eq!(Cartridge::parse(&file).err(), Some(Error::BadMagic));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut file = two_entries();
+        file[4] = VERSION + 1;
+
+        assert_eq!(Cartridge::parse(&file).err(), Some(Error::Version));
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let file = two_entries();
+
+        assert_eq!(
+            Cartridge::parse(&file[..HEADER_LEN - 1]).err(),
+            Some(Error::Truncated)
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_entry() {
+        let file = two_entries();
+        let cut = HEADER_LEN + ENTRY_LEN + ENTRY_LEN / 2;
+
+        assert_eq!(Cartridge::parse(&file[..cut]).err(), Some(Error::Truncated));
+    }
+
+    #[test]
+    fn rejects_truncated_rom() {
+        let file = two_entries();
+
+        assert_eq!(
+            Cartridge::parse(&file[..file.len() - 1]).err(),
+            Some(Error::Truncated)
+        );
+    }
+
+    #[test]
+    fn rejects_oversize_rom() {
+        let mut file = header(1);
+        file.extend(entry("BIG", 0, MAX_ROM_SIZE as u16 + 1, 0));
+        file.resize(MAX_ROM_SIZE + 1, 0);
+
+        assert_eq!(Cartridge::parse(&file).err(), Some(Error::RomSize));
+    }
+
+    #[test]
+    fn rejects_empty_cartridge() {
+        assert_eq!(Cartridge::parse(&header(0)).err(), Some(Error::Empty));
+    }
+}
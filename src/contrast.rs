@@ -0,0 +1,84 @@
+/// Raise and lower the screen contrast while paused. These are the usual
+/// up and down keys in games, so nothing is taken from a running ROM.
+pub const UP_KEY: u8 = 0x2;
+pub const DOWN_KEY: u8 = 0x8;
+
+/// How much each press changes the contrast by.
+pub const STEP: u8 = 16;
+
+/// How long the new level stays on screen after the last change.
+pub const SHOW_US: u64 = 1_000_000;
+
+/// Watches the keypad for the contrast keys and keeps the chosen level, so
+/// anything else which changes the contrast can put it back.
+pub struct ContrastKeys {
+    level: u8,
+    held: u16,
+    shown_until: Option<u64>,
+}
+
+impl ContrastKeys {
+    pub fn new(level: u8) -> Self {
+        Self {
+            level,
+            held: 0,
+            shown_until: None,
+        }
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    /// Update from a key mask as returned by `GpioKeypad::scan`, taken at
+    /// `now_us`. Each press moves one `STEP`, however long it is held, and
+    /// returns the new level. Presses at either end of the range return the
+    /// level unchanged, so they can still be shown.
+    pub fn update(&mut self, keys: u16, now_us: u64) -> Option<u8> {
+        let pressed = keys & !self.held;
+        self.held = keys;
+
+        let level = if pressed & 1 << UP_KEY != 0 {
+            self.level.saturating_add(STEP)
+        } else if pressed & 1 << DOWN_KEY != 0 {
+            self.level.saturating_sub(STEP)
+        } else {
+            return None;
+        };
+
+        self.level = level;
+        self.shown_until = Some(now_us + SHOW_US);
+
+        Some(level)
+    }
+
+    /// Whether the level is on screen and should now be taken off. True
+    /// once per change.
+    pub fn expired(&mut self, now_us: u64) -> bool {
+        match self.shown_until {
+            Some(until) if now_us >= until => {
+                self.shown_until = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Write `level` into `buf` as decimal digits, returning the digits used.
+pub fn digits(level: u8, buf: &mut [u8; 3]) -> &str {
+    let mut start = buf.len();
+    let mut rest = level;
+
+    loop {
+        start -= 1;
+        buf[start] = b'0' + rest % 10;
+        rest /= 10;
+
+        if rest == 0 {
+            break;
+        }
+    }
+
+    core::str::from_utf8(&buf[start..]).unwrap_or("")
+}
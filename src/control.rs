@@ -0,0 +1,109 @@
+/// Pauses and resumes emulation.
+pub const PAUSE_KEY: u8 = 0xF;
+
+/// Keys which restart the ROM when held together for `RESET_HOLD_US`. The
+/// defaults sit in opposite corners of the keypad so they are not pressed by
+/// accident.
+pub const RESET_COMBO: u16 = 1 << 0x1 | 1 << 0xC;
+pub const RESET_HOLD_US: u64 = 1_000_000;
+
+/// Runs the CPU `TURBO_MULTIPLIER` times faster while held, with the `turbo`
+/// feature. The running ROM never sees it, so it is lost to games.
+#[cfg(feature = "turbo")]
+pub const TURBO_KEY: u8 = 0xD;
+#[cfg(feature = "turbo")]
+pub const TURBO_MULTIPLIER: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RunState {
+    Running,
+    Paused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Control {
+    Pause,
+    Resume,
+    Reset,
+}
+
+/// Watches the keypad for the keys which control the emulator itself. Keys
+/// are only observed, so the running ROM still sees them as normal, except
+/// for `TURBO_KEY` which the keypad is set to hide.
+pub struct Controls {
+    state: RunState,
+    held: u16,
+    combo_since: Option<u64>,
+    combo_fired: bool,
+}
+
+impl Controls {
+    pub fn new() -> Self {
+        Self {
+            state: RunState::Running,
+            held: 0,
+            combo_since: None,
+            combo_fired: false,
+        }
+    }
+
+    pub fn state(&self) -> RunState {
+        self.state
+    }
+
+    /// Pause without a keypress, as when the debugger reaches a breakpoint.
+    /// `PAUSE_KEY` resumes as usual.
+    #[cfg(feature = "debugger")]
+    pub fn pause(&mut self) {
+        self.state = RunState::Paused;
+    }
+
+    /// The governor multiplier for the keys last seen by `update`.
+    #[cfg(feature = "turbo")]
+    pub fn turbo(&self) -> u32 {
+        if self.held & 1 << TURBO_KEY != 0 {
+            TURBO_MULTIPLIER
+        } else {
+            1
+        }
+    }
+
+    /// Update from a key mask as returned by `GpioKeypad::scan`, taken at
+    /// `now_us`. The reset combination fires once per hold.
+    pub fn update(&mut self, keys: u16, now_us: u64) -> Option<Control> {
+        let pressed = keys & !self.held;
+        self.held = keys;
+
+        if keys & RESET_COMBO != RESET_COMBO {
+            self.combo_since = None;
+            self.combo_fired = false;
+        } else if !self.combo_fired {
+            let since = *self.combo_since.get_or_insert(now_us);
+
+            if now_us - since >= RESET_HOLD_US {
+                self.combo_fired = true;
+                self.state = RunState::Running;
+                return Some(Control::Reset);
+            }
+        }
+
+        if pressed & 1 << PAUSE_KEY == 0 {
+            return None;
+        }
+
+        Some(self.toggle_pause())
+    }
+
+    /// Pause or resume as `PAUSE_KEY` does, returning which happened.
+    pub fn toggle_pause(&mut self) -> Control {
+        let (state, control) = match self.state {
+            RunState::Running => (RunState::Paused, Control::Pause),
+            RunState::Paused => (RunState::Running, Control::Resume),
+        };
+
+        self.state = state;
+        control
+    }
+}
@@ -0,0 +1,158 @@
+//! A single-step debugger, enabled by the `debugger` feature. While the
+//! emulator is paused, `STEP_KEY` runs one instruction and `BREAK_KEY`
+//! toggles a breakpoint at the current PC, which pauses the emulator when it
+//! is reached. The CPU state is drawn over the top of the screen in place of
+//! the usual pause banner.
+
+use crate::emulator::CpuSnapshot;
+use core::fmt::{self, Write};
+
+/// Executes one instruction while paused. The key is held while the step
+/// runs, so a ROM waiting on a keypress will see it.
+pub const STEP_KEY: u8 = 0xE;
+
+/// Sets or clears the breakpoint at the current PC while paused.
+pub const BREAK_KEY: u8 = 0xB;
+
+/// What the delay and sound timers do while paused in the debugger.
+pub const TIMERS: TimerMode = TimerMode::Frozen;
+
+/// Characters in the longest line of the state display.
+pub const LINE_CHARS: u8 = 15;
+
+/// Lines in the state display. Only the first five fit in low
+/// resolution.
+pub const LINES: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TimerMode {
+    /// Count down at 60Hz, as though the ROM were still running.
+    Realtime,
+    /// Hold their values until the emulator is resumed, so stepping through
+    /// a delay loop doesn't skip it.
+    Frozen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Action {
+    Step,
+    Breakpoint,
+}
+
+/// Watches the keypad for the debugger keys while paused and remembers the
+/// breakpoint.
+pub struct Debugger {
+    held: u16,
+    breakpoint: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            held: 0,
+            breakpoint: None,
+        }
+    }
+
+    pub fn breakpoint(&self) -> Option<u16> {
+        self.breakpoint
+    }
+
+    /// Update from a key mask as returned by `GpioKeypad::scan` while paused
+    /// at `pc`. Each key acts once per press.
+    pub fn update(&mut self, keys: u16, pc: u16) -> Option<Action> {
+        let pressed = keys & !self.held;
+        self.held = keys;
+
+        if pressed & 1 << STEP_KEY != 0 {
+            return Some(Action::Step);
+        }
+
+        if pressed & 1 << BREAK_KEY != 0 {
+            self.breakpoint = match self.breakpoint {
+                Some(addr) if addr == pc => None,
+                _ => Some(pc),
+            };
+
+            return Some(Action::Breakpoint);
+        }
+
+        None
+    }
+
+    /// Whether the instruction at `pc` should stop the emulator before it
+    /// runs.
+    pub fn hit(&self, pc: u16) -> bool {
+        self.breakpoint == Some(pc)
+    }
+
+    /// Format `state` for display, one line per entry.
+    pub fn lines(&self, state: &CpuSnapshot) -> [Line; LINES] {
+        let mut lines = [Line::new(); LINES];
+
+        write!(lines[0], "PC {:04X} OP {:04X}", state.pc, state.opcode).ok();
+
+        match self.breakpoint {
+            Some(addr) => write!(lines[1], "I  {:04X} BP {:04X}", state.i, addr),
+            None => write!(lines[1], "I  {:04X} BP ----", state.i),
+        }
+        .ok();
+
+        let m = &state.at_i;
+        write!(
+            lines[2],
+            "@I {:02X} {:02X} {:02X} {:02X}",
+            m[0], m[1], m[2], m[3]
+        )
+        .ok();
+
+        for (n, line) in lines[3..].iter_mut().enumerate() {
+            let regs = &state.v[n * 4..n * 4 + 4];
+            write!(
+                line,
+                "V{:X} {:02X} {:02X} {:02X} {:02X}",
+                n * 4,
+                regs[0],
+                regs[1],
+                regs[2],
+                regs[3]
+            )
+            .ok();
+        }
+
+        lines
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Line {
+    buf: [u8; LINE_CHARS as usize],
+    len: usize,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            buf: [0; LINE_CHARS as usize],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
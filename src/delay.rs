@@ -0,0 +1,50 @@
+use chip8::pal;
+use cortex_m::{delay::Delay as CortexDelay, peripheral::SYST};
+
+/// Busy-wait delay on the SysTick timer, for use with the `pal` traits.
+pub struct Delay(CortexDelay);
+
+impl Delay {
+    pub fn new(syst: SYST, ahb_frequency: u32) -> Self {
+        Self(CortexDelay::new(syst, ahb_frequency))
+    }
+}
+
+impl pal::Delay for Delay {
+    type Error = pal::Error;
+
+    fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+        self.0.delay_us(us);
+        Ok(())
+    }
+}
+
+/// A `Delay` which returns straight away, adding up the time it was asked to
+/// wait, for running code which delays off-target.
+#[cfg(any(test, feature = "mock"))]
+#[derive(Debug, Default)]
+pub struct MockDelay {
+    elapsed_us: u64,
+}
+
+#[cfg(any(test, feature = "mock"))]
+impl MockDelay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The total of every delay so far.
+    pub fn elapsed_us(&self) -> u64 {
+        self.elapsed_us
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+impl pal::Delay for MockDelay {
+    type Error = pal::Error;
+
+    fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+        self.elapsed_us += us as u64;
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+//! A hardware check which needs no ROM, enabled by the `demo` feature. Each
+//! key pressed clears the screen and draws that key's hex digit, and the LED
+//! goes out while a key is held.
+
+use crate::{font::font_sprite, led::StatusLed};
+use chip8::{
+    pal::{Delay, Keypad, Screen},
+    ram::Ram,
+};
+use core::convert::Infallible;
+use embedded_hal::digital::v2::OutputPin;
+
+const POLL_US: u32 = 100_000;
+
+/// Run the demo, only returning if the screen fails. Keypad errors are
+/// shown by leaving the LED on.
+pub fn run<S, K, D, P>(
+    screen: &mut S,
+    keypad: &mut K,
+    ram: &Ram,
+    delay: &mut D,
+    led: &mut StatusLed<P>,
+) -> Result<Infallible, S::Error>
+where
+    S: Screen,
+    K: Keypad,
+    D: Delay,
+    P: OutputPin,
+{
+    loop {
+        let sprite = match keypad.read_key(delay) {
+            Ok(Some(key)) => font_sprite(ram, key).ok(),
+            _ => None,
+        };
+
+        match sprite {
+            Some(sprite) => {
+                screen.clear()?;
+                screen.xor(2, 8, sprite)?;
+                led.set(false);
+            }
+            None => led.set(true),
+        }
+
+        delay.delay_us(POLL_US).ok();
+    }
+}
@@ -0,0 +1,66 @@
+//! Streams the raw keypad matrix over USB serial, for tracking down wiring
+//! faults and flaky contacts that `GpioKeypad::self_test` might miss as it
+//! only looks once. Holding `DIAG_KEY` at power-on enters it in place of the
+//! emulator, until the next reset.
+//!
+//! Each reading is sent as four lines of four `0`s and `1`s, one line per
+//! row and one character per column as in the keymap, followed by a blank
+//! line. Nothing is sent while no host has the port open.
+
+use crate::{keypad, loader::UsbLoader, types, Keys, Oled};
+use chip8::pal::Delay;
+use core::convert::Infallible;
+use usb_device::class_prelude::UsbBus;
+
+/// Held at power-on to stream the matrix instead of running a ROM.
+pub const DIAG_KEY: u8 = 0xF;
+
+/// Time between readings, so about four a second.
+const INTERVAL_US: u32 = 250_000;
+
+/// USB is serviced this often while waiting for the next reading.
+const POLL_US: u32 = 5_000;
+
+/// Stream readings until the keypad or screen fails.
+pub fn stream_keypad<B, D>(
+    screen: &mut Oled,
+    keypad: &mut Keys,
+    loader: &mut UsbLoader<'_, B>,
+    delay: &mut D,
+) -> types::Result<Infallible>
+where
+    B: UsbBus,
+    D: Delay,
+{
+    screen.clear()?;
+    screen.draw_str(0, 0, "KEYPAD DIAG")?;
+
+    loop {
+        let matrix = keypad.read_matrix(delay)?;
+
+        if loader.poll_idle() {
+            loader.send_text(&text(&matrix));
+        }
+
+        for _ in 0..INTERVAL_US / POLL_US {
+            loader.poll_idle();
+            delay.delay_us(POLL_US).map_err(|_| keypad::Error::Delay)?;
+        }
+    }
+}
+
+/// One reading, as described in the module docs.
+fn text(matrix: &[[bool; 4]; 4]) -> [u8; 26] {
+    let mut text = [b'\n'; 26];
+
+    for (line, row) in text.chunks_exact_mut(6).zip(matrix) {
+        for (cell, &active) in line.iter_mut().zip(row) {
+            *cell = if active { b'1' } else { b'0' };
+        }
+
+        line[4] = b'\r';
+    }
+
+    text[24] = b'\r';
+    text
+}
@@ -0,0 +1,564 @@
+use crate::{quirks::Quirks, screen::SchipScreen};
+use chip8::{
+    cpu::Cpu,
+    pal::{self, Delay, Keypad},
+    ram::Ram,
+};
+
+/// Address programs are loaded at and start executing from.
+pub const ROM_START: u16 = 0x200;
+
+/// The largest ROM which fits between `ROM_START` and the end of RAM.
+pub const MAX_ROM_SIZE: usize = 0x1000 - ROM_START as usize;
+
+type Result<T = ()> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    RomTooLarge,
+    Chip8(pal::Error),
+}
+
+impl From<pal::Error> for Error {
+    fn from(err: pal::Error) -> Self {
+        Error::Chip8(err)
+    }
+}
+
+/// What the CPU is doing between instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CpuState {
+    Running,
+    /// Stopped on Fx0A until a key is pressed or, with `key_wait_release`,
+    /// until the key `held` is let go. The PC stays on the Fx0A meanwhile,
+    /// so the run loop carries on ticking the timers and flushing the screen
+    /// as usual.
+    WaitKey {
+        held: Option<u8>,
+    },
+}
+
+/// A snapshot of the CPU, for the debugger.
+#[cfg(feature = "debugger")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuSnapshot {
+    pub pc: u16,
+    /// The instruction at `pc`, which runs next.
+    pub opcode: u16,
+    pub i: u16,
+    pub v: [u8; 16],
+    /// The first bytes of RAM at `i`.
+    pub at_i: [u8; 4],
+}
+
+/// A CHIP-8 CPU and its RAM, executed against the `pal` hardware traits.
+pub struct Emulator {
+    cpu: Cpu,
+    ram: Ram,
+    seed: u32,
+    rom: [u8; MAX_ROM_SIZE],
+    rom_len: usize,
+    quirks: Quirks,
+    state: CpuState,
+}
+
+impl Emulator {
+    pub fn new() -> Self {
+        Self {
+            cpu: Cpu::new(),
+            ram: Ram::new(),
+            seed: 0,
+            rom: [0; MAX_ROM_SIZE],
+            rom_len: 0,
+            quirks: Quirks::default(),
+            state: CpuState::Running,
+        }
+    }
+
+    /// Seed the random number generator used by Cxnn. The seed is kept across
+    /// calls to `load`.
+    pub fn seed_rng(&mut self, seed: u32) {
+        self.seed = seed;
+        self.cpu.seed(seed);
+    }
+
+    /// Sprite clipping is handled by the screen, so `clip_sprites` must be
+    /// passed on to it separately. `display_wait` is left to the run loop,
+    /// using `draws_next`.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Reset the CPU and RAM, then copy `rom` to `ROM_START`. A copy is kept
+    /// so `reset` can start it again.
+    pub fn load(&mut self, rom: &[u8]) -> Result {
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(Error::RomTooLarge);
+        }
+
+        self.rom[..rom.len()].copy_from_slice(rom);
+        self.rom_len = rom.len();
+        self.reset();
+
+        Ok(())
+    }
+
+    /// Reset the CPU, RAM and timers and start the last loaded ROM from the
+    /// beginning.
+    pub fn reset(&mut self) {
+        self.cpu = Cpu::new();
+        self.cpu.seed(self.seed);
+        self.ram = Ram::new();
+        self.ram.write_bytes(ROM_START, &self.rom[..self.rom_len]);
+        self.state = CpuState::Running;
+    }
+
+    /// Fetch, decode and execute a single instruction. SUPER-CHIP display
+    /// instructions are handled here, as the core only knows about CHIP-8.
+    /// Fx0A is handled here too, so that it never blocks: each step while
+    /// waiting checks the keypad once and returns.
+    pub fn step<S, K, D>(&mut self, screen: &mut S, keypad: &mut K, delay: &mut D) -> Result
+    where
+        S: SchipScreen,
+        S::Error: Into<pal::Error>,
+        K: Keypad,
+        K::Error: Into<pal::Error>,
+        D: Delay,
+    {
+        let opcode = self.opcode_at(self.cpu.pc());
+
+        #[cfg(feature = "trace")]
+        crate::trace::record(self.cpu.pc(), opcode);
+
+        if opcode & 0xF0FF == 0xF00A {
+            let key = keypad
+                .read_key(delay)
+                .map_err(|err| Error::Chip8(err.into()))?;
+
+            self.wait_key((opcode >> 8 & 0xF) as u8, key);
+            return Ok(());
+        }
+
+        if self.step_quirk(opcode) {
+            return Ok(());
+        }
+
+        let handled = self
+            .step_schip(opcode, screen)
+            .map_err(|err| Error::Chip8(err.into()))?;
+
+        if !handled {
+            self.cpu.step(&mut self.ram, screen, keypad, delay)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn state(&self) -> CpuState {
+        self.state
+    }
+
+    /// Whether the next instruction is Dxyn.
+    pub fn draws_next(&self) -> bool {
+        self.opcode_at(self.cpu.pc()) & 0xF000 == 0xD000
+    }
+
+    fn opcode_at(&self, addr: u16) -> u16 {
+        match self.ram.read_bytes(addr, 2) {
+            &[high, low] => u16::from_be_bytes([high, low]),
+            _ => 0,
+        }
+    }
+
+    #[cfg(feature = "debugger")]
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc()
+    }
+
+    #[cfg(feature = "debugger")]
+    pub fn snapshot(&self) -> CpuSnapshot {
+        let pc = self.cpu.pc();
+        let i = self.cpu.i();
+        let mut v = [0; 16];
+        let mut at_i = [0; 4];
+
+        for (reg, value) in (0..).zip(v.iter_mut()) {
+            *value = self.cpu.v(reg);
+        }
+
+        let bytes = self.ram.read_bytes(i, at_i.len());
+        at_i[..bytes.len()].copy_from_slice(bytes);
+
+        CpuSnapshot {
+            pc,
+            opcode: self.opcode_at(pc),
+            i,
+            v,
+            at_i,
+        }
+    }
+
+    /// Execute `opcode` here if it is a SUPER-CHIP display instruction.
+    /// Returns false for anything else.
+    fn step_schip<S: SchipScreen>(
+        &mut self,
+        opcode: u16,
+        screen: &mut S,
+    ) -> core::result::Result<bool, S::Error> {
+        match opcode {
+            0x00FF => screen.set_hires(true)?,
+            0x00FE => screen.set_hires(false)?,
+            0x00C0..=0x00CF => screen.scroll_down(opcode as u8 & 0xF)?,
+            0x00D0..=0x00DF => screen.scroll_up(opcode as u8 & 0xF)?,
+            0x00FB => screen.scroll_right(4)?,
+            0x00FC => screen.scroll_left(4)?,
+            _ if opcode & 0xF0FF == 0xF001 => screen.set_plane_mask((opcode >> 8) as u8 & 0x3),
+            _ if opcode & 0xF00F == 0xD000 => self.draw_large(opcode, screen)?,
+            _ if opcode & 0xF000 == 0xD000
+                && self.quirks.count_collision_rows
+                && screen.is_hires() =>
+            {
+                self.draw_counted(opcode, screen)?
+            }
+            _ => return Ok(false),
+        }
+
+        self.cpu.set_pc(self.cpu.pc() + 2);
+        Ok(true)
+    }
+
+    /// Dxy0 draws a 16x16 sprite of 32 bytes, two per row, in either mode.
+    fn draw_large<S: SchipScreen>(
+        &mut self,
+        opcode: u16,
+        screen: &mut S,
+    ) -> core::result::Result<(), S::Error> {
+        let x = self.cpu.v((opcode >> 8 & 0xF) as u8) % screen.width();
+        let y = self.cpu.v((opcode >> 4 & 0xF) as u8);
+        let (mut left, mut right) = ([0; 16], [0; 16]);
+
+        for (row, pair) in self
+            .ram
+            .read_bytes(self.cpu.i(), 32)
+            .chunks_exact(2)
+            .enumerate()
+        {
+            left[row] = pair[0];
+            right[row] = pair[1];
+        }
+
+        let mut rows = screen.xor_rows(x, y, &left)?;
+
+        if x + 8 < screen.width() || !self.quirks.clip_sprites {
+            rows |= screen.xor_rows(x.wrapping_add(8), y, &right)?;
+        }
+
+        self.set_collision(rows, screen);
+        Ok(())
+    }
+
+    /// Dxyn when `count_collision_rows` applies, which the core can't do.
+    fn draw_counted<S: SchipScreen>(
+        &mut self,
+        opcode: u16,
+        screen: &mut S,
+    ) -> core::result::Result<(), S::Error> {
+        let x = self.cpu.v((opcode >> 8 & 0xF) as u8);
+        let y = self.cpu.v((opcode >> 4 & 0xF) as u8);
+        let len = (opcode & 0xF) as usize;
+
+        // Rows which would be read from past the end of RAM are blank.
+        let mut sprite = [0; 15];
+        let bytes = self.ram.read_bytes(self.cpu.i(), len);
+        sprite[..bytes.len()].copy_from_slice(bytes);
+
+        let rows = screen.xor_rows(x, y, &sprite[..len])?;
+        self.set_collision(rows, screen);
+        Ok(())
+    }
+
+    /// Set VF from a mask of the sprite rows which collided.
+    fn set_collision<S: SchipScreen>(&mut self, rows: u16, screen: &S) {
+        let flag = if self.quirks.count_collision_rows && screen.is_hires() {
+            rows.count_ones() as u8
+        } else {
+            (rows != 0) as u8
+        };
+
+        self.cpu.set_v(0xF, flag);
+    }
+
+    /// Execute `opcode` here if its behaviour depends on the quirks, so the
+    /// core never sees it. Returns false for anything else.
+    fn step_quirk(&mut self, opcode: u16) -> bool {
+        let x = (opcode >> 8 & 0xF) as u8;
+        let y = (opcode >> 4 & 0xF) as u8;
+
+        match (opcode >> 12, opcode & 0xFF) {
+            (0x8, low) if low & 0xF == 0x6 => self.shift(x, y, true),
+            (0x8, low) if low & 0xF == 0xE => self.shift(x, y, false),
+            (0xB, _) => {
+                let reg = if self.quirks.bxnn_uses_vx { x } else { 0 };
+                self.cpu.set_pc((opcode & 0xFFF) + self.cpu.v(reg) as u16);
+                return true;
+            }
+            (0xF, 0x55) => self.store_regs(x),
+            (0xF, 0x65) => self.load_regs(x),
+            _ => return false,
+        }
+
+        self.cpu.set_pc(self.cpu.pc() + 2);
+        true
+    }
+
+    /// One step of Fx0A, with `key` as read from the keypad. The key is
+    /// stored in Vx and the PC moves on once it has been pressed, or pressed
+    /// and released with `key_wait_release`.
+    fn wait_key(&mut self, x: u8, key: Option<u8>) {
+        let held = match self.state {
+            CpuState::WaitKey { held } => held,
+            CpuState::Running => None,
+        };
+
+        let done = if self.quirks.key_wait_release {
+            held.filter(|&held| key != Some(held))
+        } else {
+            key
+        };
+
+        match done {
+            Some(key) => {
+                self.cpu.set_v(x, key);
+                self.cpu.set_pc(self.cpu.pc() + 2);
+                self.state = CpuState::Running;
+            }
+            None => self.state = CpuState::WaitKey { held: key },
+        }
+    }
+
+    fn shift(&mut self, x: u8, y: u8, right: bool) {
+        let value = self.cpu.v(if self.quirks.shift_uses_vy { y } else { x });
+
+        let (result, flag) = if right {
+            (value >> 1, value & 1)
+        } else {
+            (value << 1, value >> 7)
+        };
+
+        self.cpu.set_v(x, result);
+        self.cpu.set_v(0xF, flag);
+    }
+
+    /// Fx55.
+    fn store_regs(&mut self, x: u8) {
+        let mut regs = [0; 16];
+
+        for reg in 0..=x {
+            regs[reg as usize] = self.cpu.v(reg);
+        }
+
+        self.ram.write_bytes(self.cpu.i(), &regs[..=x as usize]);
+        self.advance_i(x);
+    }
+
+    /// Fx65. Registers which would be loaded from past the end of RAM are
+    /// zeroed.
+    fn load_regs(&mut self, x: u8) {
+        let mut regs = [0; 16];
+        let bytes = self.ram.read_bytes(self.cpu.i(), x as usize + 1);
+        regs[..bytes.len()].copy_from_slice(bytes);
+
+        for reg in 0..=x {
+            self.cpu.set_v(reg, regs[reg as usize]);
+        }
+
+        self.advance_i(x);
+    }
+
+    fn advance_i(&mut self, x: u8) {
+        if self.quirks.load_store_increments_i {
+            self.cpu.set_i(self.cpu.i() + x as u16 + 1);
+        }
+    }
+
+    /// Decrement the delay and sound timers. Call this at 60Hz.
+    pub fn tick_timers(&mut self) {
+        self.cpu.tick_timers();
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.cpu.sound_timer() > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        delay::MockDelay,
+        keypad::MockKeypad,
+        screen::{MockBus, MockPin, MockScreen, Sh1106Display},
+    };
+
+    /// The IBM logo ROM draws its six sprites in the first 20 instructions
+    /// and then jumps to itself.
+    const IBM_LOGO: &[u8] = include_bytes!("roms/ibm_logo.ch8");
+
+    /// The frame the IBM logo ROM leaves, one row per `u64` as returned by
+    /// `MockScreen::rows`.
+    const IBM_LOGO_FRAME: [u64; 32] = [
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x000F_F7FC_7C01_F000,
+        0x0000_0000_0000_0000,
+        0x000F_F7FF_7E03_F000,
+        0x0000_0000_0000_0000,
+        0x0003_C1C7_1F07_C000,
+        0x0000_0000_0000_0000,
+        0x0003_C1FC_1FDF_C000,
+        0x0000_0000_0000_0000,
+        0x0003_C1FC_1DFD_C000,
+        0x0000_0000_0000_0000,
+        0x0003_C1C7_1CF9_C000,
+        0x0000_0000_0000_0000,
+        0x000F_F7FF_7C71_F000,
+        0x0000_0000_0000_0000,
+        0x000F_F7FC_7C21_F000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+        0x0000_0000_0000_0000,
+    ];
+
+    fn run(
+        emulator: &mut Emulator,
+        screen: &mut MockScreen,
+        keypad: &mut MockKeypad,
+        steps: usize,
+    ) {
+        let mut delay = MockDelay::new();
+
+        for _ in 0..steps {
+            emulator.step(screen, keypad, &mut delay).unwrap();
+        }
+    }
+
+    #[test]
+    fn ibm_logo_draws_golden_frame() {
+        let (mut screen, mut keypad) = (MockScreen::new(), MockKeypad::new());
+        let mut emulator = Emulator::new();
+        emulator.load(IBM_LOGO).unwrap();
+
+        run(&mut emulator, &mut screen, &mut keypad, 40);
+
+        assert_eq!(screen.rows(), IBM_LOGO_FRAME);
+    }
+
+    #[test]
+    fn load_past_end_of_ram_zeroes_registers() {
+        let (mut screen, mut keypad) = (MockScreen::new(), MockKeypad::new());
+        let mut emulator = Emulator::new();
+        emulator
+            .load(&[0x65, 0x77, 0xAF, 0xFE, 0xF5, 0x65])
+            .unwrap();
+
+        run(&mut emulator, &mut screen, &mut keypad, 3);
+
+        assert_eq!(emulator.cpu.v(5), 0);
+        assert_eq!(emulator.cpu.pc(), ROM_START + 6);
+    }
+
+    #[test]
+    fn counted_draw_past_end_of_ram_blanks_missing_rows() {
+        let mut screen: Sh1106Display<MockBus, MockPin, 16, 64> =
+            Sh1106Display::with_bus(MockBus::new(), MockPin);
+        screen.init().unwrap();
+
+        let (mut keypad, mut delay) = (MockKeypad::new(), MockDelay::new());
+        let mut emulator = Emulator::new();
+        emulator.set_quirks(Quirks {
+            count_collision_rows: true,
+            ..Quirks::default()
+        });
+        emulator
+            .load(&[0x00, 0xFF, 0xAF, 0xFA, 0xD0, 0x0F])
+            .unwrap();
+        emulator.ram.write_bytes(0xFFA, &[0xFF; 6]);
+
+        for _ in 0..3 {
+            emulator.step(&mut screen, &mut keypad, &mut delay).unwrap();
+        }
+
+        for (y, row) in screen.buffer().iter().enumerate() {
+            assert_eq!(row[0], if y < 6 { 0xFF } else { 0 }, "row {}", y);
+        }
+
+        assert_eq!(emulator.cpu.pc(), ROM_START + 6);
+    }
+
+    #[test]
+    fn key_wait_keeps_timers_running_until_press_and_release() {
+        let mut screen = MockScreen::new();
+        let mut keypad = MockKeypad::new()
+            .then_release()
+            .then_release()
+            .then_press(7)
+            .then_press(7)
+            .then_release();
+        let mut delay = MockDelay::new();
+        let mut emulator = Emulator::new();
+        emulator.set_quirks(Quirks {
+            key_wait_release: true,
+            ..Quirks::default()
+        });
+        emulator
+            .load(&[0x60, 0x3C, 0xF0, 0x15, 0xF2, 0x0A, 0xF1, 0x07])
+            .unwrap();
+
+        run(&mut emulator, &mut screen, &mut keypad, 2);
+
+        let waiting = [
+            CpuState::WaitKey { held: None },
+            CpuState::WaitKey { held: None },
+            CpuState::WaitKey { held: Some(7) },
+            CpuState::WaitKey { held: Some(7) },
+        ];
+
+        for state in waiting {
+            emulator.step(&mut screen, &mut keypad, &mut delay).unwrap();
+            emulator.tick_timers();
+
+            assert_eq!(emulator.state(), state);
+            assert_eq!(emulator.cpu.pc(), ROM_START + 4);
+        }
+
+        emulator.step(&mut screen, &mut keypad, &mut delay).unwrap();
+        emulator.tick_timers();
+
+        assert_eq!(emulator.state(), CpuState::Running);
+        assert_eq!(emulator.cpu.pc(), ROM_START + 6);
+        assert_eq!(emulator.cpu.v(2), 7);
+        assert!(keypad.finished());
+
+        // Fx07 reads back the delay timer, ticked once after each of the
+        // five Fx0A steps.
+        emulator.step(&mut screen, &mut keypad, &mut delay).unwrap();
+        assert_eq!(emulator.cpu.v(1), 0x3C - 5);
+    }
+}
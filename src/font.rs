@@ -0,0 +1,52 @@
+use chip8::ram::Ram;
+
+/// Bytes in each of the built-in hex digit sprites.
+pub const SPRITE_BYTES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Only keys 0x0 to 0xF have a sprite.
+    InvalidKey(u8),
+}
+
+/// The built-in font sprite for the hex digit `key`, as drawn by Fx29.
+pub fn font_sprite(ram: &Ram, key: u8) -> Result<&[u8], Error> {
+    if key > 0xF {
+        return Err(Error::InvalidKey(key));
+    }
+
+    Ok(ram.read_bytes(ram.get_sprite_addr(key), SPRITE_BYTES))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_0_is_the_zero_glyph() {
+        let ram = Ram::new();
+
+        assert_eq!(
+            font_sprite(&ram, 0),
+            Ok(&[0xF0, 0x90, 0x90, 0x90, 0xF0][..])
+        );
+    }
+
+    #[test]
+    fn key_f_is_the_last_glyph() {
+        let ram = Ram::new();
+
+        assert_eq!(
+            font_sprite(&ram, 0xF),
+            Ok(&[0xF0, 0x80, 0xF0, 0x80, 0x80][..])
+        );
+    }
+
+    #[test]
+    fn keys_above_f_are_rejected() {
+        let ram = Ram::new();
+
+        assert_eq!(font_sprite(&ram, 0x10), Err(Error::InvalidKey(0x10)));
+        assert_eq!(font_sprite(&ram, 0xFF), Err(Error::InvalidKey(0xFF)));
+    }
+}
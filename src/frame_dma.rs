@@ -0,0 +1,177 @@
+//! DMA channel 0 feeding the screen's SPI TX FIFO, for
+//! `Sh1106Display::flush_dma`. rp2040-hal 0.4 doesn't wrap the DMA
+//! peripheral, so the channel is driven through its registers.
+//!
+//! With the `dma` feature the run loop's vsync flush goes out on this
+//! channel. How long a frame takes to flush is measured at boot, both the
+//! way the run loop flushes without DMA and with it, which is what the
+//! feature has to be worth.
+
+use crate::{
+    screen::{self, FrameDma, FRAME_BYTES},
+    timer::TickTimer,
+    Oled,
+};
+use core::sync::atomic::{compiler_fence, Ordering};
+use rp_pico::hal::pac::{DMA, RESETS, SPI1};
+
+/// DREQ_SPI1_TX, so that bytes only go as fast as the FIFO takes them.
+const TREQ_SPI1_TX: u8 = 18;
+
+/// Frames flushed each way by `measure`.
+const MEASURE_FRAMES: u32 = 16;
+
+pub struct SpiFrameDma {
+    dma: DMA,
+    frame: &'static mut [u8; FRAME_BYTES],
+}
+
+impl SpiFrameDma {
+    /// Take the DMA block out of reset and let SPI1 request bytes from it.
+    /// The screen must already have set SPI1 up.
+    pub fn new(dma: DMA, frame: &'static mut [u8; FRAME_BYTES], resets: &mut RESETS) -> Self {
+        resets.reset.modify(|_, w| w.dma().clear_bit());
+        while resets.reset_done.read().dma().bit_is_clear() {}
+
+        // Safety: only the DMA request enable is changed, which blocking
+        // writes to the FIFO from the HAL don't depend on.
+        unsafe { (*SPI1::ptr()).sspdmacr.modify(|_, w| w.txdmae().set_bit()) };
+
+        Self { dma, frame }
+    }
+}
+
+impl FrameDma for SpiFrameDma {
+    fn frame(&mut self) -> &mut [u8; FRAME_BYTES] {
+        self.frame
+    }
+
+    fn start(&mut self) {
+        let ch = &self.dma.ch[0];
+        // Safety: SPI1's registers are always mapped, only the address of
+        // the data register is taken.
+        let fifo = unsafe { &(*SPI1::ptr()).sspdr as *const _ as u32 };
+
+        // The frame was just rendered by the CPU, and must be in RAM before
+        // the channel starts reading it.
+        compiler_fence(Ordering::SeqCst);
+
+        // Safety: any address and count are valid for the registers, and
+        // the frame outlives the transfer as it is `'static`.
+        unsafe {
+            ch.ch_read_addr
+                .write(|w| w.bits(self.frame.as_ptr() as u32));
+            ch.ch_write_addr.write(|w| w.bits(fifo));
+            ch.ch_trans_count.write(|w| w.bits(FRAME_BYTES as u32));
+            ch.ch_ctrl_trig.write(|w| {
+                w.data_size()
+                    .size_byte()
+                    .incr_read()
+                    .set_bit()
+                    .incr_write()
+                    .clear_bit()
+                    .treq_sel()
+                    .bits(TREQ_SPI1_TX)
+                    .chain_to()
+                    .bits(0)
+                    .en()
+                    .set_bit()
+            });
+        }
+    }
+
+    fn wait(&mut self) {
+        while self.dma.ch[0].ch_ctrl_trig.read().busy().bit_is_set() {}
+
+        // The last few bytes are still shifting out when the channel is done.
+        // Safety: reading the status register has no side effects.
+        while unsafe { (*SPI1::ptr()).sspsr.read().bsy().bit_is_set() } {}
+    }
+}
+
+/// Microseconds per frame for each way of flushing.
+#[derive(Debug, Clone, Copy)]
+pub struct FlushTimes {
+    /// The changed columns over blocking SPI, as the run loop flushes
+    /// without DMA, all of it CPU time.
+    pub blocking_us: u32,
+    /// Rendering the frame and starting the transfer, which is all the CPU
+    /// does with DMA.
+    pub dma_us: u32,
+    /// As `dma_us`, plus waiting for the transfer to finish.
+    pub dma_wait_us: u32,
+}
+
+/// Flush `MEASURE_FRAMES` frames each way, timing each with `timer`. Each
+/// frame moves a block along the top of the screen, so both ways have the
+/// same change to send, as a game would between frames.
+pub fn measure(
+    screen: &mut Oled,
+    dma: &mut SpiFrameDma,
+    timer: &TickTimer,
+) -> Result<FlushTimes, screen::Error> {
+    let (mut blocking_us, mut dma_us, mut dma_wait_us) = (0, 0, 0);
+
+    // Whatever was there before goes out untimed.
+    screen.set_deferred(true);
+    screen.flush()?;
+
+    for frame in 0..MEASURE_FRAMES {
+        move_block(screen, frame)?;
+        let start_us = timer.now_us();
+        screen.flush()?;
+        blocking_us += timer.now_us() - start_us;
+    }
+
+    for frame in MEASURE_FRAMES..2 * MEASURE_FRAMES {
+        move_block(screen, frame)?;
+        let start_us = timer.now_us();
+        screen.flush_dma(dma)?;
+        dma_us += timer.now_us() - start_us;
+        screen.wait_flush(dma)?;
+        dma_wait_us += timer.now_us() - start_us;
+    }
+
+    screen.set_deferred(false);
+
+    let per_frame = |us: u64| (us / MEASURE_FRAMES as u64) as u32;
+
+    Ok(FlushTimes {
+        blocking_us: per_frame(blocking_us),
+        dma_us: per_frame(dma_us),
+        dma_wait_us: per_frame(dma_wait_us),
+    })
+}
+
+/// Erase the block drawn for the previous frame and draw it one place on.
+fn move_block(screen: &mut Oled, frame: u32) -> Result<(), screen::Error> {
+    let width = screen.width() as u32;
+    let x = |frame: u32| (frame * 8 % width) as u8;
+
+    if frame > 0 {
+        screen.xor_rows(x(frame - 1), 0, &[0xFF; 8])?;
+    }
+
+    screen.xor_rows(x(frame), 0, &[0xFF; 8])?;
+    Ok(())
+}
+
+/// Write `us` into `buf` as, for example, `1234US`, returning the
+/// characters used.
+pub fn us_text(us: u32, buf: &mut [u8; 12]) -> &str {
+    let mut start = buf.len() - 2;
+    let mut rest = us;
+    buf[start..].copy_from_slice(b"US");
+
+    loop {
+        start -= 1;
+        buf[start] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+
+        if rest == 0 {
+            break;
+        }
+    }
+
+    core::str::from_utf8(&buf[start..]).unwrap_or("")
+}
@@ -0,0 +1,158 @@
+use crate::timer::TICK_HZ;
+
+/// Limits CPU execution to a fixed number of instructions per second, or to
+/// a fixed number per 60Hz frame.
+///
+/// The two are related by `rate = cycles_per_frame * 60`, so 11 cycles per
+/// frame runs at much the same average speed as 660 instructions per second.
+/// The difference is in how they are spread out. By rate, instructions are
+/// evened out across the frame. By frame, they run in a burst at the start
+/// of each tick, before the timers are ticked and the frame is flushed,
+/// which is how most CHIP-8 timing is specified and how per-ROM speeds are
+/// usually given.
+pub struct CycleGovernor {
+    rate: u32,
+    cycles_per_frame: u16,
+    turbo: u32,
+    frame: u64,
+    start_us: Option<u64>,
+    executed: u64,
+}
+
+impl CycleGovernor {
+    /// Suits most classic ROMs.
+    pub const DEFAULT_RATE: u32 = 700;
+
+    pub fn new() -> Self {
+        Self {
+            rate: Self::DEFAULT_RATE,
+            cycles_per_frame: 0,
+            turbo: 1,
+            frame: 0,
+            start_us: None,
+            executed: 0,
+        }
+    }
+
+    /// Change the instructions-per-second rate, leaving cycles per frame
+    /// off. Takes effect immediately.
+    pub fn set_rate(&mut self, ihz: u32) {
+        self.rate = ihz;
+        self.cycles_per_frame = 0;
+        self.start_us = None;
+        self.executed = 0;
+    }
+
+    /// Run `cycles` instructions per 60Hz frame in place of the rate, or go
+    /// back to the rate with 0. Takes effect from the next frame.
+    pub fn set_cycles_per_frame(&mut self, cycles: u16) {
+        self.cycles_per_frame = cycles;
+        self.start_us = None;
+        self.executed = 0;
+    }
+
+    /// Run `multiplier` times as many instructions as the rate or cycles per
+    /// frame allow, or as normal with 1. Only the CPU speeds up: the timers
+    /// are ticked by the run loop at 60Hz regardless, so game logic timed by
+    /// them is unaffected.
+    pub fn set_turbo(&mut self, multiplier: u32) {
+        if multiplier != self.turbo {
+            self.turbo = multiplier.max(1);
+            self.start_us = None;
+            self.executed = 0;
+        }
+    }
+
+    /// Instructions per second, worked out from cycles per frame when set.
+    /// Turbo isn't included.
+    pub fn rate(&self) -> u32 {
+        match self.cycles_per_frame {
+            0 => self.rate,
+            cycles => cycles as u32 * TICK_HZ as u32,
+        }
+    }
+
+    pub fn cycles_per_frame(&self) -> u16 {
+        self.cycles_per_frame
+    }
+
+    /// Whether another instruction may be executed at `now_us`. Each `true`
+    /// result counts as one instruction executed. If the loop falls more than
+    /// a frame behind, the backlog is dropped rather than run in a burst.
+    pub fn ready(&mut self, now_us: u64) -> bool {
+        if self.cycles_per_frame > 0 {
+            return self.ready_in_frame(now_us);
+        }
+
+        let rate = self.rate as u64 * self.turbo as u64;
+        let start_us = *self.start_us.get_or_insert(now_us);
+        let allowed = (now_us - start_us) * rate / 1_000_000;
+
+        if allowed <= self.executed {
+            return false;
+        }
+
+        if allowed - self.executed > rate / 60 {
+            self.start_us = Some(now_us);
+            self.executed = 0;
+        } else {
+            self.executed += 1;
+        }
+
+        true
+    }
+
+    /// As `ready`, by cycles per frame. The count starts again with each
+    /// frame, so cycles left over when a frame ends are dropped.
+    fn ready_in_frame(&mut self, now_us: u64) -> bool {
+        let frame = now_us * TICK_HZ / 1_000_000;
+
+        if self.start_us.is_none() || frame != self.frame {
+            self.start_us = Some(now_us);
+            self.frame = frame;
+            self.executed = 0;
+        }
+
+        if self.executed >= self.cycles_per_frame as u64 * self.turbo as u64 {
+            return false;
+        }
+
+        self.executed += 1;
+        true
+    }
+}
+
+/// Holds a draw instruction back until the next 60Hz tick, for the
+/// `display_wait` quirk. A tick which passed before the draw was reached
+/// doesn't count, so each draw waits for a tick of its own.
+pub struct DisplayWait {
+    reached: bool,
+    released: bool,
+}
+
+impl DisplayWait {
+    pub fn new() -> Self {
+        Self {
+            reached: false,
+            released: false,
+        }
+    }
+
+    /// Call on each pass while the next instruction is a draw, with whether
+    /// a tick has passed since the last pass. Returns whether it must wait.
+    pub fn blocked(&mut self, ticked: bool) -> bool {
+        if !self.reached {
+            self.reached = true;
+            self.released = false;
+        } else if ticked {
+            self.released = true;
+        }
+
+        !self.released
+    }
+
+    /// Call once the draw has run, so the next one waits again.
+    pub fn drawn(&mut self) {
+        self.reached = false;
+    }
+}
@@ -0,0 +1,61 @@
+/// How long the keypad must go untouched before the screen is dimmed.
+pub const IDLE_TIMEOUT_US: u64 = 60_000_000;
+
+/// The contrast the screen fades to while idle.
+pub const DIM_CONTRAST: u8 = 0x08;
+
+/// Time between contrast steps while fading down. Waking is immediate.
+pub const FADE_STEP_US: u32 = 2_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Brightness {
+    Dim,
+    Wake,
+}
+
+/// Watches for keypad activity to dim the screen when nobody is playing.
+/// Keys are only observed, so the key which wakes the screen still reaches
+/// the running ROM.
+pub struct IdleDimmer {
+    active_us: Option<u64>,
+    dimmed: bool,
+}
+
+impl IdleDimmer {
+    pub fn new() -> Self {
+        Self {
+            active_us: None,
+            dimmed: false,
+        }
+    }
+
+    #[cfg(not(feature = "embassy"))]
+    pub fn dimmed(&self) -> bool {
+        self.dimmed
+    }
+
+    /// Update from a key mask as returned by `GpioKeypad::scan`, taken at
+    /// `now_us`.
+    pub fn update(&mut self, keys: u16, now_us: u64) -> Option<Brightness> {
+        if keys != 0 {
+            self.active_us = Some(now_us);
+
+            if self.dimmed {
+                self.dimmed = false;
+                return Some(Brightness::Wake);
+            }
+
+            return None;
+        }
+
+        let active_us = *self.active_us.get_or_insert(now_us);
+
+        if !self.dimmed && now_us - active_us >= IDLE_TIMEOUT_US {
+            self.dimmed = true;
+            return Some(Brightness::Dim);
+        }
+
+        None
+    }
+}
@@ -0,0 +1,190 @@
+/// Timing and keys for `GestureRecognizer`.
+#[derive(Debug, Copy, Clone)]
+pub struct GestureConfig {
+    /// The key which is tapped twice for `Gesture::DoubleTap`, or `None` to
+    /// only recognise chords. Keys above 0xF are never recognised. Games press most keys in quick succession, so
+    /// this is off by default.
+    pub double_tap_key: Option<u8>,
+    /// The longest gap between the two presses of a double tap.
+    pub double_tap_window_us: u64,
+    /// The keys which are pressed together for `Gesture::Chord`.
+    pub chord: u16,
+    /// The longest gap between the first and last key of the chord going
+    /// down. Holding one key and pressing the other later is not a chord, so
+    /// a game using both keys doesn't open anything by accident.
+    pub chord_window_us: u64,
+}
+
+impl Default for GestureConfig {
+    /// A and B together, the bottom corners either side of 0.
+    fn default() -> Self {
+        Self {
+            double_tap_key: None,
+            double_tap_window_us: 300_000,
+            chord: 1 << 0xA | 1 << 0xB,
+            chord_window_us: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Gesture {
+    DoubleTap(u8),
+    Chord(u16),
+}
+
+/// Picks out gestures from the key masks returned by `GpioKeypad::scan`.
+/// Keys are only observed, so every press still reaches the running ROM as
+/// usual and gestures are reported alongside them.
+pub struct GestureRecognizer {
+    config: GestureConfig,
+    held: u16,
+    last_tap_us: Option<u64>,
+    chord_since: Option<u64>,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            held: 0,
+            last_tap_us: None,
+            chord_since: None,
+        }
+    }
+
+    /// Update from a key mask taken at `now_us`. Each gesture is reported
+    /// once, on the press which completes it.
+    pub fn update(&mut self, keys: u16, now_us: u64) -> Option<Gesture> {
+        let pressed = keys & !self.held;
+        self.held = keys;
+
+        self.chord(keys, pressed, now_us)
+            .or_else(|| self.double_tap(pressed, now_us))
+    }
+
+    fn chord(&mut self, keys: u16, pressed: u16, now_us: u64) -> Option<Gesture> {
+        let chord = self.config.chord;
+
+        if chord == 0 || keys & chord == 0 {
+            self.chord_since = None;
+            return None;
+        }
+
+        let since = *self.chord_since.get_or_insert(now_us);
+
+        if keys & chord == chord
+            && pressed & chord != 0
+            && now_us - since <= self.config.chord_window_us
+        {
+            return Some(Gesture::Chord(chord));
+        }
+
+        None
+    }
+
+    fn double_tap(&mut self, pressed: u16, now_us: u64) -> Option<Gesture> {
+        let key = self.config.double_tap_key?;
+        // A key past the end of the mask can never be tapped.
+        let bit = 1u16.checked_shl(key as u32)?;
+
+        if pressed & bit == 0 {
+            return None;
+        }
+
+        match self.last_tap_us.take() {
+            Some(tap) if now_us - tap <= self.config.double_tap_window_us => {
+                Some(Gesture::DoubleTap(key))
+            }
+            _ => {
+                self.last_tap_us = Some(now_us);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: u16 = 1 << 0xA;
+    const B: u16 = 1 << 0xB;
+
+    fn double_tap(key: u8) -> GestureRecognizer {
+        GestureRecognizer::new(GestureConfig {
+            double_tap_key: Some(key),
+            chord: 0,
+            ..GestureConfig::default()
+        })
+    }
+
+    /// Press and release `keys` at `now_us`, returning any gesture.
+    fn tap(gestures: &mut GestureRecognizer, keys: u16, now_us: u64) -> Option<Gesture> {
+        let gesture = gestures.update(keys, now_us);
+        assert_eq!(gestures.update(0, now_us + 1_000), None);
+        gesture
+    }
+
+    #[test]
+    fn double_tap_inside_the_window() {
+        let mut gestures = double_tap(5);
+
+        assert_eq!(tap(&mut gestures, 1 << 5, 0), None);
+        assert_eq!(
+            tap(&mut gestures, 1 << 5, 300_000),
+            Some(Gesture::DoubleTap(5))
+        );
+    }
+
+    #[test]
+    fn double_tap_outside_the_window() {
+        let mut gestures = double_tap(5);
+
+        assert_eq!(tap(&mut gestures, 1 << 5, 0), None);
+        assert_eq!(tap(&mut gestures, 1 << 5, 300_001), None);
+        assert_eq!(
+            tap(&mut gestures, 1 << 5, 400_000),
+            Some(Gesture::DoubleTap(5))
+        );
+    }
+
+    #[test]
+    fn double_tap_key_out_of_range() {
+        let mut gestures = double_tap(16);
+
+        assert_eq!(tap(&mut gestures, 0xFFFF, 0), None);
+        assert_eq!(tap(&mut gestures, 0xFFFF, 10_000), None);
+    }
+
+    #[test]
+    fn single_tap_is_not_a_gesture() {
+        let mut gestures = GestureRecognizer::new(GestureConfig {
+            double_tap_key: Some(0xA),
+            ..GestureConfig::default()
+        });
+
+        assert_eq!(tap(&mut gestures, A, 0), None);
+        assert_eq!(tap(&mut gestures, B, 1_000_000), None);
+    }
+
+    #[test]
+    fn chord_inside_the_window() {
+        let mut gestures = GestureRecognizer::new(GestureConfig::default());
+
+        assert_eq!(gestures.update(A, 0), None);
+        assert_eq!(gestures.update(A | B, 100_000), Some(Gesture::Chord(A | B)));
+        assert_eq!(gestures.update(A | B, 110_000), None);
+    }
+
+    #[test]
+    fn chord_outside_the_window() {
+        let mut gestures = GestureRecognizer::new(GestureConfig::default());
+
+        assert_eq!(gestures.update(A, 0), None);
+        assert_eq!(gestures.update(A | B, 100_001), None);
+        assert_eq!(gestures.update(0, 200_000), None);
+        assert_eq!(gestures.update(A | B, 300_000), Some(Gesture::Chord(A | B)));
+    }
+}
@@ -1,15 +1,22 @@
 use chip8::pal::{self, Delay, Keypad};
+use core::fmt;
 use embedded_hal::digital::v2::{InputPin, OutputPin};
 
-pub type Keymap = [[u8; 4]; 4];
+pub type Keymap<const COLS: usize = 4, const ROWS: usize = 4> = [[u8; COLS]; ROWS];
 
 type Result<T = ()> = core::result::Result<T, Error>;
 
-#[derive(Debug, Clone, Copy)]
+/// Pin errors are reduced to whether a column or a row failed, for the same
+/// reasons as the screen's `Error`, and the original error type is likewise
+/// logged with the `defmt` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     WritePin,
     ReadPin,
     Delay,
+    /// A key has been held for longer than the stuck key timeout.
+    StuckKey(u8),
 }
 
 impl Into<pal::Error> for Error {
@@ -18,151 +25,706 @@ impl Into<pal::Error> for Error {
     }
 }
 
-pub struct GpioKeypad<C1, C2, C3, C4, R1, R2, R3, R4>
-where
-    C1: OutputPin,
-    C2: OutputPin,
-    C3: OutputPin,
-    C4: OutputPin,
-    R1: InputPin,
-    R2: InputPin,
-    R3: InputPin,
-    R4: InputPin,
-{
-    col1: C1,
-    col2: C2,
-    col3: C3,
-    col4: C4,
-    row1: R1,
-    row2: R2,
-    row3: R3,
-    row4: R4,
-    keymap: Keymap,
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Error::WritePin => "failed to drive column pin",
+            Error::ReadPin => "failed to read row pin",
+            Error::Delay => "delay failed",
+            Error::StuckKey(_) => "key held down for too long",
+        })
+    }
 }
 
-macro_rules! set {
-    (1 = $($pin: expr),+) => {
-        $($pin.set_high().map_err(|_| Error::WritePin)?);+
-    };
+/// Layout of the 4x4 Waveshare keypad.
+pub const KEYMAP: Keymap = [
+    [0x1, 0x2, 0x3, 0xF],
+    [0x4, 0x5, 0x6, 0xE],
+    [0x7, 0x8, 0x9, 0xD],
+    [0xA, 0x0, 0xB, 0xC],
+];
 
-    (0 = $($pin: expr),+) => {
-        $($pin.set_low().map_err(|_| Error::WritePin)?);+
-    };
-}
+const DUPLICATE: [&str; 16] = [
+    "duplicate key 0x0",
+    "duplicate key 0x1",
+    "duplicate key 0x2",
+    "duplicate key 0x3",
+    "duplicate key 0x4",
+    "duplicate key 0x5",
+    "duplicate key 0x6",
+    "duplicate key 0x7",
+    "duplicate key 0x8",
+    "duplicate key 0x9",
+    "duplicate key 0xA",
+    "duplicate key 0xB",
+    "duplicate key 0xC",
+    "duplicate key 0xD",
+    "duplicate key 0xE",
+    "duplicate key 0xF",
+];
 
-macro_rules! try_col {
-    ($self: ident: $pin: ident, $delay: expr, $col: literal) => {
-        set!(1 = $self.$pin);
-        $self.wait($delay);
+const MISSING: [&str; 16] = [
+    "missing key 0x0",
+    "missing key 0x1",
+    "missing key 0x2",
+    "missing key 0x3",
+    "missing key 0x4",
+    "missing key 0x5",
+    "missing key 0x6",
+    "missing key 0x7",
+    "missing key 0x8",
+    "missing key 0x9",
+    "missing key 0xA",
+    "missing key 0xB",
+    "missing key 0xC",
+    "missing key 0xD",
+    "missing key 0xE",
+    "missing key 0xF",
+];
 
-        if let Some(key) = $self.try_rows($col)? {
-            return Ok(Some(key));
+/// Check that a keymap reports each key 0x0 to 0xF exactly once, so no two
+/// positions are mistaken for each other. Keypads with fewer than 16 keys
+/// may leave keys out, but still may not repeat them.
+pub fn validate_keymap<const COLS: usize, const ROWS: usize>(
+    keymap: &Keymap<COLS, ROWS>,
+) -> core::result::Result<(), &'static str> {
+    let mut seen = 0u16;
+
+    for &key in keymap.iter().flatten() {
+        let bit = 1u16
+            .checked_shl(key as u32)
+            .ok_or("key out of range 0x0 to 0xF")?;
+
+        if seen & bit != 0 {
+            return Err(DUPLICATE[key as usize]);
         }
 
-        set!(0 = $self.$pin);
-    };
+        seen |= bit;
+    }
+
+    match (COLS * ROWS >= 16).then(|| (!seen).trailing_zeros()) {
+        Some(key) if key < 16 => Err(MISSING[key as usize]),
+        _ => Ok(()),
+    }
+}
+
+/// Translate a key mask read through keymap `from` into the keys the same
+/// positions have in keymap `to`, such as to recognise the firmware's own
+/// key combinations on the user's layout while a ROM has its own. Keys in
+/// `keys` which `from` doesn't map are dropped.
+pub fn remap_keys<const COLS: usize, const ROWS: usize>(
+    keys: u16,
+    from: &Keymap<COLS, ROWS>,
+    to: &Keymap<COLS, ROWS>,
+) -> u16 {
+    from.iter()
+        .flatten()
+        .zip(to.iter().flatten())
+        .filter(|(&from, _)| keys & key_bit(from) != 0)
+        .fold(0, |mask, (_, &to)| mask | key_bit(to))
 }
 
-macro_rules! try_cols {
-    ($self: ident, $delay: expr => $($col: literal = $pin: ident),+) => {
-        $(try_col!($self: $pin, $delay, $col));+
-    };
+/// The bit for `key` in a key mask, or nothing for a key above 0xF, which
+/// a keymap for a keypad with more than 16 keys may hold.
+fn key_bit(key: u8) -> u16 {
+    1u16.checked_shl(key as u32).unwrap_or(0)
 }
 
-impl<C1, C2, C3, C4, R1, R2, R3, R4> GpioKeypad<C1, C2, C3, C4, R1, R2, R3, R4>
+/// Wraps a `Delay`, totalling the time spent waiting.
+struct Timed<'a, D: Delay> {
+    delay: &'a mut D,
+    us: u64,
+}
+
+impl<D: Delay> Delay for Timed<'_, D> {
+    type Error = D::Error;
+
+    fn delay_us(&mut self, us: u32) -> core::result::Result<(), Self::Error> {
+        self.us += us as u64;
+        self.delay.delay_us(us)
+    }
+}
+
+/// A row input which can latch an edge and raise an interrupt, so that a
+/// key press can wake a sleeping core.
+pub trait WakePin {
+    /// Listen for a `rising` or falling edge, forgetting any edge latched
+    /// before now.
+    fn listen_edge(&mut self, rising: bool);
+
+    /// Stop listening, clearing anything latched.
+    fn unlisten(&mut self);
+}
+
+/// A matrix keypad with `COLS` driven column lines and `ROWS` sensed row
+/// lines. Pins of different types can be stored together by first converting
+/// them into `DynPin`s.
+pub struct GpioKeypad<C, R, const COLS: usize, const ROWS: usize>
 where
-    C1: OutputPin,
-    C2: OutputPin,
-    C3: OutputPin,
-    C4: OutputPin,
-    R1: InputPin,
-    R2: InputPin,
-    R3: InputPin,
-    R4: InputPin,
+    C: OutputPin,
+    R: InputPin,
 {
-    const KEYMAP: Keymap = [
-        [0x1, 0x2, 0x3, 0xF],
-        [0x4, 0x5, 0x6, 0xE],
-        [0x7, 0x8, 0x9, 0xD],
-        [0xA, 0x0, 0xB, 0xC],
-    ];
-
-    pub fn new(
-        col1: C1,
-        col2: C2,
-        col3: C3,
-        col4: C4,
-        row1: R1,
-        row2: R2,
-        row3: R3,
-        row4: R4,
-    ) -> Self {
+    cols: [C; COLS],
+    rows: [R; ROWS],
+    keymap: Keymap<COLS, ROWS>,
+    debounce: u8,
+    settle_us: u32,
+    held: Option<u8>,
+    active_low: bool,
+    reject_ghosting: bool,
+    repeat_delay_ms: u32,
+    repeat_rate_ms: u32,
+    repeat: Option<(u8, u64, u64)>,
+    poll_col: usize,
+    poll_mask: u16,
+    stuck_us: Option<u64>,
+    stuck_since: Option<(u8, u64)>,
+    hidden: u16,
+}
+
+pub type GpioKeypad4x4<C, R> = GpioKeypad<C, R, 4, 4>;
+
+impl<C, R, const COLS: usize, const ROWS: usize> GpioKeypad<C, R, COLS, ROWS>
+where
+    C: OutputPin,
+    R: InputPin,
+{
+    const POLL_US: u32 = 1_000;
+
+    pub fn new(cols: [C; COLS], rows: [R; ROWS]) -> Self {
         Self {
-            col1,
-            col2,
-            col3,
-            col4,
-            row1,
-            row2,
-            row3,
-            row4,
-            keymap: Self::KEYMAP,
+            cols,
+            rows,
+            keymap: Self::default_keymap(),
+            debounce: 3,
+            settle_us: 500,
+            held: None,
+            active_low: false,
+            reject_ghosting: false,
+            repeat_delay_ms: 500,
+            repeat_rate_ms: 100,
+            repeat: None,
+            poll_col: 0,
+            poll_mask: 0,
+            stuck_us: None,
+            stuck_since: None,
+            hidden: 0,
+        }
+    }
+
+    /// The Waveshare layout for a 4x4 keypad, otherwise keys are numbered
+    /// from zero along each row. On a keypad with more than 16 keys, those
+    /// numbered above 0xF are never reported, as CHIP-8 has no such keys.
+    fn default_keymap() -> Keymap<COLS, ROWS> {
+        let mut keymap = [[0; COLS]; ROWS];
+
+        for (y, row) in keymap.iter_mut().enumerate() {
+            for (x, key) in row.iter_mut().enumerate() {
+                *key = if COLS == 4 && ROWS == 4 {
+                    KEYMAP[y][x]
+                } else {
+                    (y * COLS + x) as u8
+                };
+            }
         }
+
+        keymap
+    }
+
+    pub fn with_keymap(mut self, keymap: Keymap<COLS, ROWS>) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    /// As `with_keymap`, but rejects keymaps which fail `validate_keymap`.
+    pub fn with_checked_keymap(
+        self,
+        keymap: Keymap<COLS, ROWS>,
+    ) -> core::result::Result<Self, &'static str> {
+        validate_keymap(&keymap)?;
+        Ok(self.with_keymap(keymap))
     }
 
-    pub fn with_keymap(mut self, keymap: Keymap) -> Self {
+    /// Change the keymap of a keypad already in use, such as for a ROM which
+    /// comes with its own. Positions mapped to a key above 0xF are ignored.
+    pub fn set_keymap(&mut self, keymap: Keymap<COLS, ROWS>) {
         self.keymap = keymap;
+    }
+
+    pub fn keymap(&self) -> &Keymap<COLS, ROWS> {
+        &self.keymap
+    }
+
+    /// Keep the keys in `mask` from `read_key` and everything built on it,
+    /// as for a key the firmware uses for itself. `scan` and `poll_scan`
+    /// still report them.
+    pub fn set_hidden_keys(&mut self, mask: u16) {
+        self.hidden = mask;
+    }
+
+    /// Keys above 0xF count as hidden, so `read_key` never reports them.
+    fn hidden(&self, key: u8) -> bool {
+        self.hidden & key_bit(key) != 0 || key_bit(key) == 0
+    }
+
+    /// Number of consecutive row samples which must agree before a key is
+    /// accepted.
+    pub fn with_debounce(mut self, samples: u8) -> Self {
+        self.debounce = samples;
+        self
+    }
+
+    /// Time to wait after driving a column before the rows are read.
+    pub fn with_settle_us(mut self, us: u32) -> Self {
+        self.settle_us = us;
+        self
+    }
+
+    /// Drive columns low and sense rows low, for keypads wired with pull-up
+    /// row inputs.
+    pub fn with_active_low(mut self, active_low: bool) -> Self {
+        self.active_low = active_low;
+        self
+    }
+
+    /// Scan the whole matrix on every read and ignore it unless exactly one
+    /// key is down, so phantom keys from ghosting are never reported.
+    pub fn with_reject_ghosting(mut self, reject: bool) -> Self {
+        self.reject_ghosting = reject;
+        self
+    }
+
+    /// How long a key must be held before `read_key_repeat` repeats it.
+    pub fn with_repeat_delay_ms(mut self, ms: u32) -> Self {
+        self.repeat_delay_ms = ms;
+        self
+    }
+
+    /// Interval between repeats once a held key has started repeating.
+    pub fn with_repeat_rate_ms(mut self, ms: u32) -> Self {
+        self.repeat_rate_ms = ms;
+        self
+    }
+
+    /// How long one key may be held before `check_stuck` reports it. There
+    /// is no limit by default, as some games hold a key for a long time.
+    pub fn with_stuck_timeout_ms(mut self, ms: u32) -> Self {
+        self.stuck_us = Some(ms as u64 * 1_000);
         self
     }
 
     pub fn init(&mut self) -> Result {
-        set!(1 = self.col1, self.col2, self.col3, self.col4);
-        Ok(())
+        self.set_cols(true)
     }
 
-    fn wait<D: Delay>(&self, delay: &mut D) {
-        delay.delay_us(500);
+    fn wait<D: Delay>(&self, delay: &mut D) -> Result {
+        delay.delay_us(self.settle_us).map_err(|_| Error::Delay)
     }
 
-    fn read(&self) -> Result<(bool, bool, bool, bool)> {
-        Ok((
-            self.row1.is_high().map_err(|_| Error::ReadPin)?,
-            self.row2.is_high().map_err(|_| Error::ReadPin)?,
-            self.row3.is_high().map_err(|_| Error::ReadPin)?,
-            self.row4.is_high().map_err(|_| Error::ReadPin)?,
-        ))
+    fn set_col(&mut self, col: usize, active: bool) -> Result {
+        let pin = &mut self.cols[col];
+
+        let result = if active != self.active_low {
+            pin.set_high()
+        } else {
+            pin.set_low()
+        };
+
+        result.map_err(|_| {
+            #[cfg(feature = "defmt")]
+            defmt::debug!(
+                "column {} failed: {=str}",
+                col,
+                core::any::type_name::<C::Error>()
+            );
+            Error::WritePin
+        })
     }
 
-    fn try_rows(&self, col: usize) -> Result<Option<u8>> {
-        let key = match self.read()? {
-            (true, false, false, false) => Some(0),
-            (false, true, false, false) => Some(1),
-            (false, false, true, false) => Some(2),
-            (false, false, false, true) => Some(3),
-            _ => None,
+    fn set_cols(&mut self, active: bool) -> Result {
+        for col in 0..COLS {
+            self.set_col(col, active)?;
         }
-        .map(|row| self.keymap[row][col]);
+
+        Ok(())
+    }
+
+    fn read(&self) -> Result<[bool; ROWS]> {
+        let mut rows = [false; ROWS];
+
+        for (active, pin) in rows.iter_mut().zip(&self.rows) {
+            let high = pin.is_high().map_err(|_| {
+                #[cfg(feature = "defmt")]
+                defmt::debug!(
+                    "row read failed: {=str}",
+                    core::any::type_name::<R::Error>()
+                );
+                Error::ReadPin
+            })?;
+
+            *active = high != self.active_low;
+        }
+
+        Ok(rows)
+    }
+
+    fn sample<D: Delay>(&self, delay: &mut D) -> Result<Option<[bool; ROWS]>> {
+        let rows = self.read()?;
+
+        for _ in 1..self.debounce {
+            self.wait(delay)?;
+
+            if self.read()? != rows {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(rows))
+    }
+
+    fn try_rows<D: Delay>(&self, col: usize, delay: &mut D) -> Result<Option<u8>> {
+        let key = self.sample(delay)?.and_then(|rows| {
+            let mut active = rows
+                .iter()
+                .zip(&self.keymap)
+                .filter(|(&active, keys)| active && !self.hidden(keys[col]));
+
+            match (active.next(), active.next()) {
+                (Some((_, keys)), None) => Some(keys[col]),
+                _ => None,
+            }
+        });
 
         Ok(key)
     }
+
+    fn try_cols<D: Delay>(&mut self, delay: &mut D) -> Result<Option<u8>> {
+        for col in 0..COLS {
+            self.set_col(col, true)?;
+            self.wait(delay)?;
+
+            if let Some(key) = self.try_rows(col, delay)? {
+                return Ok(Some(key));
+            }
+
+            self.set_col(col, false)?;
+        }
+
+        Ok(None)
+    }
+
+    fn try_single<D: Delay>(&mut self, delay: &mut D) -> Result<Option<u8>> {
+        let mut found = None;
+
+        for col in 0..COLS {
+            self.set_col(col, true)?;
+            self.wait(delay)?;
+            let rows = self.sample(delay)?;
+            self.set_col(col, false)?;
+
+            let rows = match rows {
+                Some(rows) => rows,
+                None => return Ok(None),
+            };
+
+            for (row, _) in rows.iter().enumerate().filter(|(_, &active)| active) {
+                let key = self.keymap[row][col];
+
+                if self.hidden(key) {
+                    continue;
+                }
+
+                if found.is_some() {
+                    return Ok(None);
+                }
+
+                found = Some(key);
+            }
+        }
+
+        Ok(found)
+    }
+
+    fn scan_rows(&self, col: usize) -> Result<u16> {
+        let mask = self
+            .read()?
+            .iter()
+            .zip(&self.keymap)
+            .filter(|(&active, _)| active)
+            .fold(0, |mask, (_, keys)| mask | key_bit(keys[col]));
+
+        Ok(mask)
+    }
+
+    fn scan_cols<D: Delay>(&mut self, delay: &mut D) -> Result<u16> {
+        let mut mask = 0;
+
+        for col in 0..COLS {
+            self.set_col(col, true)?;
+            self.wait(delay)?;
+            mask |= self.scan_rows(col)?;
+            self.set_col(col, false)?;
+        }
+
+        Ok(mask)
+    }
+
+    /// A bring-up check for shorted lines or wrong pin assignments, to be run
+    /// with no keys held. Each column is driven in turn and any row which
+    /// responds is marked in the result, indexed `[row][col]` as in the
+    /// keymap. A row which is active with no column driven is marked in every
+    /// column. A healthy board returns all `false`.
+    pub fn self_test<D: Delay>(&mut self, delay: &mut D) -> Result<[[bool; COLS]; ROWS]> {
+        let mut stuck = [[false; COLS]; ROWS];
+
+        self.set_cols(false)?;
+        self.wait(delay)?;
+        let idle = self.read();
+
+        let result = idle.and_then(|idle| {
+            for col in 0..COLS {
+                self.set_col(col, true)?;
+                self.wait(delay)?;
+                let rows = self.read();
+                self.set_col(col, false)?;
+
+                for ((cells, &idle), active) in stuck.iter_mut().zip(&idle).zip(rows?) {
+                    cells[col] = idle || active;
+                }
+            }
+
+            Ok(stuck)
+        });
+
+        self.set_cols(true)?;
+        result
+    }
+
+    /// Read every intersection of the matrix once, indexed `[row][col]` as
+    /// in the keymap, with no debouncing or ghost rejection. For watching
+    /// the wiring, where `scan` would hide a flaky contact.
+    pub fn read_matrix<D: Delay>(&mut self, delay: &mut D) -> Result<[[bool; COLS]; ROWS]> {
+        let mut matrix = [[false; COLS]; ROWS];
+
+        self.set_cols(false)?;
+
+        for col in 0..COLS {
+            self.set_col(col, true)?;
+            let rows = self.wait(delay).and_then(|_| self.read());
+            self.set_col(col, false)?;
+
+            match rows {
+                Ok(rows) => {
+                    for (cells, active) in matrix.iter_mut().zip(rows) {
+                        cells[col] = active;
+                    }
+                }
+                Err(err) => {
+                    self.set_cols(true)?;
+                    return Err(err);
+                }
+            }
+        }
+
+        self.set_cols(true)?;
+        Ok(matrix)
+    }
+
+    /// Time how long the lowest held key in `keys`, a mask from `scan` or
+    /// `poll_scan` taken at `now_us`, has stayed down. Fails with
+    /// `Error::StuckKey` once that passes the stuck key timeout, so a shorted
+    /// switch or a cut row shows up as a fault rather than a game which no
+    /// longer responds. Other keys coming and going don't restart the timer.
+    pub fn check_stuck(&mut self, keys: u16, now_us: u64) -> Result {
+        let limit = match self.stuck_us {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        match self.stuck_since {
+            Some((key, since)) if keys & key_bit(key) != 0 => {
+                if now_us - since > limit {
+                    return Err(Error::StuckKey(key));
+                }
+            }
+            _ => {
+                self.stuck_since = (keys != 0).then(|| (keys.trailing_zeros() as u8, now_us));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the state of every key, with bit `n` set when key `n` is held.
+    pub fn scan<D: Delay>(&mut self, delay: &mut D) -> Result<u16> {
+        self.set_cols(false)?;
+        let result = self.scan_cols(delay);
+        self.set_cols(true)?;
+        result
+    }
+
+    /// As `scan`, but reading one column per call so other work can be done
+    /// in between. Returns the key mask once every column has been read, and
+    /// `None` until then. Each call waits for the settle time once, where
+    /// `scan` waits once per column.
+    pub fn poll_scan<D: Delay>(&mut self, delay: &mut D) -> Result<Option<u16>> {
+        let col = self.poll_col;
+
+        self.set_cols(false)?;
+        self.set_col(col, true)?;
+        let rows = self.wait(delay).and_then(|_| self.scan_rows(col));
+        self.set_cols(true)?;
+
+        match rows {
+            Ok(mask) => self.poll_mask |= mask,
+            Err(err) => {
+                self.poll_col = 0;
+                self.poll_mask = 0;
+                return Err(err);
+            }
+        }
+
+        if col + 1 < COLS {
+            self.poll_col = col + 1;
+            return Ok(None);
+        }
+
+        self.poll_col = 0;
+        Ok(Some(core::mem::take(&mut self.poll_mask)))
+    }
+
+    /// Unlike `read_key`, which reports a key as soon as it is pressed, this
+    /// reports a key once it has been released, as required by Fx0A. The
+    /// held key is tracked between calls, so this must be polled.
+    pub fn read_key_on_release<D: Delay>(&mut self, delay: &mut D) -> Result<Option<u8>> {
+        let key = self.read_key(delay)?;
+        let released = self.held.filter(|&held| key != Some(held));
+
+        #[cfg(feature = "defmt")]
+        if key != self.held {
+            if let Some(key) = released {
+                defmt::trace!("key {=u8:X} released", key);
+            }
+            if let Some(key) = key {
+                defmt::trace!("key {=u8:X} pressed", key);
+            }
+        }
+
+        self.held = key;
+        Ok(released)
+    }
+
+    fn poll_key<D: Delay>(&mut self, delay: &mut D) -> Result<Option<u8>> {
+        if self.key_is_pressed()? {
+            if let Some(key) = self.read_key(delay)? {
+                return Ok(Some(key));
+            }
+        }
+
+        delay.delay_us(Self::POLL_US).map_err(|_| Error::Delay)?;
+        Ok(None)
+    }
+
+    /// Report a key when it is first pressed, then repeatedly while it is
+    /// held. Time is measured by the delays spent scanning, so this should be
+    /// polled in a loop which does little else, such as a menu.
+    pub fn read_key_repeat<D: Delay>(&mut self, delay: &mut D) -> Result<Option<u8>> {
+        let mut timed = Timed { delay, us: 0 };
+
+        let key = match self.poll_key(&mut timed)? {
+            Some(key) => key,
+            None => {
+                #[cfg(feature = "defmt")]
+                if let Some((key, _, _)) = self.repeat {
+                    defmt::trace!("key {=u8:X} released", key);
+                }
+                self.repeat = None;
+                return Ok(None);
+            }
+        };
+
+        match &mut self.repeat {
+            Some((held, elapsed, next)) if *held == key => {
+                *elapsed += timed.us;
+
+                if *elapsed < *next {
+                    return Ok(None);
+                }
+
+                *next += self.repeat_rate_ms as u64 * 1_000;
+            }
+            _ => {
+                #[cfg(feature = "defmt")]
+                defmt::trace!("key {=u8:X} pressed", key);
+                self.repeat = Some((key, 0, self.repeat_delay_ms as u64 * 1_000));
+            }
+        }
+
+        Ok(Some(key))
+    }
+
+    /// Block until a key is pressed.
+    pub fn wait_for_key<D: Delay>(&mut self, delay: &mut D) -> Result<u8> {
+        loop {
+            if let Some(key) = self.poll_key(delay)? {
+                return Ok(key);
+            }
+        }
+    }
+
+    /// Block until a key is pressed, giving up after at least `timeout_ms`.
+    pub fn wait_for_key_timeout<D: Delay>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<Option<u8>> {
+        let polls = timeout_ms as u64 * 1_000 / Self::POLL_US as u64;
+
+        for _ in 0..polls {
+            if let Some(key) = self.poll_key(delay)? {
+                return Ok(Some(key));
+            }
+        }
+
+        Ok(None)
+    }
 }
 
-impl<C1, C2, C3, C4, R1, R2, R3, R4> Keypad for GpioKeypad<C1, C2, C3, C4, R1, R2, R3, R4>
+impl<C, R, const COLS: usize, const ROWS: usize> GpioKeypad<C, R, COLS, ROWS>
 where
-    C1: OutputPin,
-    C2: OutputPin,
-    C3: OutputPin,
-    C4: OutputPin,
-    R1: InputPin,
-    R2: InputPin,
-    R3: InputPin,
-    R4: InputPin,
+    C: OutputPin,
+    R: InputPin + WakePin,
+{
+    /// Drive every column and listen on every row for the edge a key press
+    /// makes, so that any key raises an interrupt. A key already held makes
+    /// no edge, so check `key_is_pressed` after arming and before sleeping.
+    pub fn arm_wake_interrupt(&mut self) -> Result {
+        self.set_cols(true)?;
+
+        for row in &mut self.rows {
+            row.listen_edge(!self.active_low);
+        }
+
+        Ok(())
+    }
+
+    /// Stop the rows raising interrupts, as scanning makes edges of its own.
+    pub fn disarm_wake_interrupt(&mut self) {
+        for row in &mut self.rows {
+            row.unlisten();
+        }
+    }
+}
+
+impl<C, R, const COLS: usize, const ROWS: usize> Keypad for GpioKeypad<C, R, COLS, ROWS>
+where
+    C: OutputPin,
+    R: InputPin,
 {
     type Error = Error;
 
     fn key_is_pressed(&self) -> Result<bool> {
-        let (row1, row2, row3, row4) = self.read()?;
-        Ok(row1 || row2 || row3 || row4)
+        Ok(self.read()?.contains(&true))
     }
 
     fn read_key<D: Delay>(&mut self, delay: &mut D) -> Result<Option<u8>> {
@@ -170,15 +732,138 @@ where
             return Ok(None);
         }
 
-        set!(0 = self.col1, self.col2, self.col3, self.col4);
+        self.set_cols(false)?;
 
-        let mut read_key = || -> Result<Option<u8>> {
-            try_cols!(self, delay => 0 = col1, 1 = col2, 2 = col3, 3 = col4);
-            Ok(None)
+        let result = if self.reject_ghosting {
+            self.try_single(delay)
+        } else {
+            self.try_cols(delay)
         };
 
-        let result = (read_key)();
-        set!(1 = self.col1, self.col2, self.col3, self.col4);
+        self.set_cols(true)?;
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{delay::MockDelay, keypad::MockMatrix};
+
+    #[test]
+    fn scan_reports_every_held_key() {
+        let matrix = MockMatrix::<4, 4>::new();
+        let mut keypad = GpioKeypad::new(matrix.cols(), matrix.rows());
+        let mut delay = MockDelay::new();
+        keypad.init().unwrap();
+
+        // 1, 6 and C on the Waveshare layout.
+        matrix.set(0, 0, true);
+        matrix.set(1, 2, true);
+        matrix.set(3, 3, true);
+
+        assert_eq!(keypad.scan(&mut delay), Ok(1 << 0x1 | 1 << 0x6 | 1 << 0xC));
+    }
+
+    #[test]
+    fn scan_reports_keys_sharing_a_row_and_column() {
+        let matrix = MockMatrix::<4, 4>::new();
+        let mut keypad = GpioKeypad::new(matrix.cols(), matrix.rows());
+        let mut delay = MockDelay::new();
+        keypad.init().unwrap();
+
+        // 4, 5 and 8: two on one row, two in one column.
+        matrix.set(1, 0, true);
+        matrix.set(1, 1, true);
+        matrix.set(2, 1, true);
+
+        assert_eq!(keypad.scan(&mut delay), Ok(1 << 0x4 | 1 << 0x5 | 1 << 0x8));
+    }
+
+    #[test]
+    fn remap_keys_follows_positions() {
+        let mut rom = KEYMAP;
+        rom[0].rotate_left(1);
+
+        // 1 and 2 on the Waveshare layout, held through the ROM's keymap.
+        let keys = 1 << rom[0][0] | 1 << rom[0][1] | 1 << rom[3][3];
+
+        assert_eq!(
+            remap_keys(keys, &rom, &KEYMAP),
+            1 << 0x1 | 1 << 0x2 | 1 << 0xC
+        );
+        assert_eq!(remap_keys(keys, &KEYMAP, &KEYMAP), keys);
+    }
+
+    #[test]
+    fn read_key_repeat_takes_the_longest_delays() {
+        let matrix = MockMatrix::<4, 4>::new();
+        let mut keypad = GpioKeypad::new(matrix.cols(), matrix.rows())
+            .with_repeat_delay_ms(u32::MAX)
+            .with_repeat_rate_ms(u32::MAX);
+        let mut delay = MockDelay::new();
+        keypad.init().unwrap();
+
+        matrix.set(0, 0, true);
+        let keys: Vec<_> = (0..20)
+            .map(|_| keypad.read_key_repeat(&mut delay).unwrap())
+            .collect();
+
+        assert_eq!(keys.iter().flatten().collect::<Vec<_>>(), [&0x1]);
+    }
+
+    #[test]
+    fn scan_with_nothing_held_is_empty() {
+        let matrix = MockMatrix::<4, 4>::new();
+        let mut keypad = GpioKeypad::new(matrix.cols(), matrix.rows());
+        keypad.init().unwrap();
+
+        assert_eq!(keypad.scan(&mut MockDelay::new()), Ok(0));
+    }
+
+    #[test]
+    fn poll_scan_matches_scan() {
+        let matrix = MockMatrix::<4, 4>::new();
+        let mut keypad = GpioKeypad::new(matrix.cols(), matrix.rows());
+        let mut delay = MockDelay::new();
+        keypad.init().unwrap();
+
+        matrix.set(0, 3, true);
+        matrix.set(3, 1, true);
+
+        for _ in 1..4 {
+            assert_eq!(keypad.poll_scan(&mut delay), Ok(None));
+        }
+
+        assert_eq!(keypad.poll_scan(&mut delay), Ok(Some(1 << 0xF | 1 << 0x0)));
+        assert_eq!(keypad.scan(&mut delay), Ok(1 << 0xF | 1 << 0x0));
+    }
+
+    #[test]
+    fn wait_for_key_timeout_gives_up_after_timeout() {
+        let matrix = MockMatrix::<4, 4>::new();
+        let mut keypad = GpioKeypad::new(matrix.cols(), matrix.rows());
+        let mut delay = MockDelay::new();
+        keypad.init().unwrap();
+
+        assert_eq!(keypad.wait_for_key_timeout(&mut delay, 5), Ok(None));
+        assert!(delay.elapsed_us() >= 5_000);
+    }
+
+    #[test]
+    fn keys_above_f_are_skipped() {
+        let matrix = MockMatrix::<5, 4>::new();
+        let mut keypad = GpioKeypad::new(matrix.cols(), matrix.rows());
+        let mut delay = MockDelay::new();
+        keypad.init().unwrap();
+
+        // Keys 0x13 and 0x2 in the default numbering.
+        matrix.set(3, 4, true);
+        matrix.set(0, 2, true);
+        assert_eq!(keypad.scan(&mut delay), Ok(1 << 0x2));
+
+        matrix.set(0, 2, false);
+        assert_eq!(keypad.scan(&mut delay), Ok(0));
+        assert_eq!(keypad.read_key(&mut delay), Ok(None));
+    }
+}
@@ -0,0 +1,153 @@
+use chip8::pal::{self, Delay, Keypad};
+use core::{cell::Cell, convert::Infallible};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// The longest script `MockKeypad` can hold.
+pub const MAX_STEPS: usize = 64;
+
+/// A testing utility: a `Keypad` which plays back a scripted sequence of
+/// keypad states, one per call to `read_key`, for exercising Ex9E, ExA1 and
+/// Fx0A off-target.
+///
+/// `key_is_pressed` reports the state `read_key` is about to return without
+/// consuming it. Once the script runs out, no key is pressed.
+pub struct MockKeypad {
+    steps: [Option<u8>; MAX_STEPS],
+    len: usize,
+    next: usize,
+}
+
+impl MockKeypad {
+    pub fn new() -> Self {
+        Self {
+            steps: [None; MAX_STEPS],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    /// Append a step with `key` held, or nothing held for `None`.
+    ///
+    /// # Panics
+    ///
+    /// If the script already holds `MAX_STEPS` steps.
+    pub fn then(mut self, key: Option<u8>) -> Self {
+        assert!(self.len < MAX_STEPS, "MockKeypad script is full");
+
+        self.steps[self.len] = key;
+        self.len += 1;
+        self
+    }
+
+    pub fn then_press(self, key: u8) -> Self {
+        self.then(Some(key))
+    }
+
+    pub fn then_release(self) -> Self {
+        self.then(None)
+    }
+
+    /// The number of steps played back so far.
+    pub fn reads(&self) -> usize {
+        self.next
+    }
+
+    /// Whether every step has been played back.
+    pub fn finished(&self) -> bool {
+        self.next >= self.len
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.steps[..self.len].get(self.next).copied().flatten()
+    }
+}
+
+impl Keypad for MockKeypad {
+    type Error = pal::Error;
+
+    fn key_is_pressed(&self) -> Result<bool, pal::Error> {
+        Ok(self.peek().is_some())
+    }
+
+    fn read_key<D: Delay>(&mut self, _delay: &mut D) -> Result<Option<u8>, pal::Error> {
+        let key = self.peek();
+        self.next = (self.next + 1).min(self.len);
+        Ok(key)
+    }
+}
+
+/// A testing utility: a key matrix with no hardware behind it, for exercising
+/// `GpioKeypad` off-target. A driven column pin connects each row pin to it
+/// through whichever keys are held, with columns driven high as by default.
+pub struct MockMatrix<const COLS: usize, const ROWS: usize> {
+    held: Cell<[[bool; COLS]; ROWS]>,
+    driven: Cell<[bool; COLS]>,
+}
+
+impl<const COLS: usize, const ROWS: usize> MockMatrix<COLS, ROWS> {
+    pub fn new() -> Self {
+        Self {
+            held: Cell::new([[false; COLS]; ROWS]),
+            driven: Cell::new([false; COLS]),
+        }
+    }
+
+    /// Hold or let go of the key at `row`, `col`.
+    pub fn set(&self, row: usize, col: usize, held: bool) {
+        let mut keys = self.held.get();
+        keys[row][col] = held;
+        self.held.set(keys);
+    }
+
+    pub fn cols(&self) -> [MockColumn<'_, COLS, ROWS>; COLS] {
+        core::array::from_fn(|col| MockColumn { matrix: self, col })
+    }
+
+    pub fn rows(&self) -> [MockRow<'_, COLS, ROWS>; ROWS] {
+        core::array::from_fn(|row| MockRow { matrix: self, row })
+    }
+}
+
+pub struct MockColumn<'a, const COLS: usize, const ROWS: usize> {
+    matrix: &'a MockMatrix<COLS, ROWS>,
+    col: usize,
+}
+
+impl<const COLS: usize, const ROWS: usize> OutputPin for MockColumn<'_, COLS, ROWS> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        let mut driven = self.matrix.driven.get();
+        driven[self.col] = false;
+        self.matrix.driven.set(driven);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        let mut driven = self.matrix.driven.get();
+        driven[self.col] = true;
+        self.matrix.driven.set(driven);
+        Ok(())
+    }
+}
+
+pub struct MockRow<'a, const COLS: usize, const ROWS: usize> {
+    matrix: &'a MockMatrix<COLS, ROWS>,
+    row: usize,
+}
+
+impl<const COLS: usize, const ROWS: usize> InputPin for MockRow<'_, COLS, ROWS> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        let (held, driven) = (self.matrix.held.get(), self.matrix.driven.get());
+        Ok(held[self.row]
+            .iter()
+            .zip(driven)
+            .any(|(&held, driven)| held && driven))
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        self.is_high().map(|high| !high)
+    }
+}
@@ -1,3 +1,12 @@
+mod gesture;
 mod keypad;
 
-pub use keypad::{Error, GpioKeypad, Keymap};
+pub use gesture::{Gesture, GestureConfig, GestureRecognizer};
+pub use keypad::{
+    remap_keys, validate_keymap, Error, GpioKeypad, GpioKeypad4x4, Keymap, WakePin, KEYMAP,
+};
+
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+#[cfg(any(test, feature = "mock"))]
+pub use mock::{MockKeypad, MockMatrix};
@@ -0,0 +1,71 @@
+use core::marker::PhantomData;
+use embedded_hal::adc::{Channel, OneShot};
+
+/// The governor rates at either end of the knob's travel.
+pub const MIN_RATE: u32 = 200;
+pub const MAX_RATE: u32 = 2_000;
+
+/// Rates are rounded to this step, so small changes in the reading don't
+/// keep resetting the governor.
+const RATE_STEP: u32 = 10;
+
+/// Readings are averaged over this many frames.
+const WINDOW: usize = 8;
+
+/// Full scale of the RP2040's 12-bit ADC.
+const ADC_MAX: u32 = 4_095;
+
+/// A potentiometer on an ADC channel which sets the CPU speed.
+pub struct SpeedKnob<A, ADC, PIN>
+where
+    A: OneShot<ADC, u16, PIN>,
+    PIN: Channel<ADC>,
+{
+    adc: A,
+    channel: PIN,
+    samples: [u16; WINDOW],
+    index: usize,
+    _adc: PhantomData<ADC>,
+}
+
+impl<A, ADC, PIN> SpeedKnob<A, ADC, PIN>
+where
+    A: OneShot<ADC, u16, PIN>,
+    PIN: Channel<ADC>,
+{
+    pub fn new(adc: A, channel: PIN) -> Self {
+        let mut knob = Self {
+            adc,
+            channel,
+            samples: [0; WINDOW],
+            index: 0,
+            _adc: PhantomData,
+        };
+
+        let sample = knob.sample();
+        knob.samples = [sample; WINDOW];
+        knob
+    }
+
+    /// The ADC, for reading its other channels between calls to `rate`.
+    #[cfg(feature = "temp-overlay")]
+    pub fn adc_mut(&mut self) -> &mut A {
+        &mut self.adc
+    }
+
+    fn sample(&mut self) -> u16 {
+        self.adc.read(&mut self.channel).unwrap_or(0)
+    }
+
+    /// Take a reading and return the averaged rate in instructions per
+    /// second. Call this once per frame.
+    pub fn rate(&mut self) -> u32 {
+        self.samples[self.index] = self.sample();
+        self.index = (self.index + 1) % WINDOW;
+
+        let average = self.samples.iter().map(|&s| s as u32).sum::<u32>() / WINDOW as u32;
+        let rate = MIN_RATE + average.min(ADC_MAX) * (MAX_RATE - MIN_RATE) / ADC_MAX;
+
+        (rate + RATE_STEP / 2) / RATE_STEP * RATE_STEP
+    }
+}
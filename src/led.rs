@@ -0,0 +1,90 @@
+use chip8::pal::Delay;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Frames between heartbeat toggles, a 1Hz blink at 60 frames per second.
+const HEARTBEAT_FRAMES: u32 = 30;
+
+const DOT_MS: u32 = 150;
+const DASH_MS: u32 = 3 * DOT_MS;
+
+/// Shows what the board is doing on a single LED, for when there is no
+/// screen to look at or the screen is what has failed.
+pub struct StatusLed<P: OutputPin> {
+    pin: P,
+    on: bool,
+    frames: u32,
+}
+
+impl<P: OutputPin> StatusLed<P> {
+    pub fn new(pin: P) -> Self {
+        Self {
+            pin,
+            on: false,
+            frames: 0,
+        }
+    }
+
+    pub fn set(&mut self, on: bool) {
+        let result = if on {
+            self.pin.set_high()
+        } else {
+            self.pin.set_low()
+        };
+
+        // There is nowhere left to report a broken status LED.
+        result.ok();
+        self.on = on;
+    }
+
+    /// Call with the frames elapsed since the last call while running
+    /// normally, to blink slowly. The blink is counted in frames rather than
+    /// calls, so it keeps time however often the loop gets round to it, and
+    /// stops dead if the loop hangs. It can't be mistaken for `error_blink`,
+    /// which never holds the LED on for as long.
+    pub fn heartbeat(&mut self, frames: u64) {
+        self.frames = self.frames.saturating_add(frames as u32);
+
+        if self.frames >= HEARTBEAT_FRAMES {
+            self.frames %= HEARTBEAT_FRAMES;
+            self.set(!self.on);
+        }
+    }
+
+    /// Hold the LED on while a ROM is being received.
+    pub fn rom_loading(&mut self) {
+        self.frames = 0;
+        self.set(true);
+    }
+
+    /// Blink SOS followed by `code` short flashes, once. Call this in a loop
+    /// to repeat the code for as long as it needs to be seen.
+    pub fn error_blink<D: Delay>(&mut self, code: u8, delay: &mut D) {
+        const SOS: [u32; 9] = [
+            DOT_MS, DOT_MS, DOT_MS, DASH_MS, DASH_MS, DASH_MS, DOT_MS, DOT_MS, DOT_MS,
+        ];
+
+        let mut wait = |ms: u32| {
+            delay.delay_us(ms * 1_000).ok();
+        };
+
+        self.set(false);
+
+        for ms in SOS {
+            self.set(true);
+            wait(ms);
+            self.set(false);
+            wait(DOT_MS);
+        }
+
+        wait(DASH_MS);
+
+        for _ in 0..code {
+            self.set(true);
+            wait(DOT_MS);
+            self.set(false);
+            wait(DOT_MS);
+        }
+
+        wait(7 * DOT_MS);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::emulator::{Emulator, MAX_ROM_SIZE};
+use core::fmt::Write;
+use usb_device::UsbError;
+use usb_device::{
+    class_prelude::{UsbBus, UsbBusAllocator},
+    device::{UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
+};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+/// Sent back to the host once a ROM has been loaded.
+const ACK: u8 = b'K';
+
+/// Sent back to the host when the declared length is zero or too large.
+const NAK: u8 = b'E';
+
+/// A length prefix which asks for a screenshot instead of sending a ROM.
+const SCREENSHOT: u16 = 0xFFFF;
+
+/// How many times in a row `send` services the device without the host
+/// taking anything before it gives up, so a host which stops reading can't
+/// hold up the run loop until the watchdog resets the board.
+const SEND_POLLS: u32 = 10_000;
+
+/// Something `poll` needs the caller to act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A ROM was loaded into the emulator, which has been reset.
+    RomLoaded,
+    /// The host asked for a screenshot, to be sent with `send_pbm`.
+    Screenshot,
+}
+
+enum State {
+    /// Waiting for the two byte, big-endian length prefix.
+    Length { buf: [u8; 2], received: usize },
+    /// Receiving `len` bytes of ROM.
+    Rom { len: usize, received: usize },
+}
+
+/// Receives ROMs over USB CDC serial. A transfer is a two byte big-endian
+/// length followed by that many bytes of ROM. The emulator is only touched
+/// once the whole ROM has arrived, so an aborted transfer leaves the running
+/// program alone.
+///
+/// Sending the length `FF FF` requests a screenshot instead, which is
+/// answered with a binary PBM image: the ASCII header `P4\n<width>
+/// <height>\n`, then one row after another from the top, each `width / 8`
+/// bytes with the leftmost pixel in the MSB and lit pixels as 1s. Any image
+/// viewer which reads PBM can show it as it is.
+pub struct UsbLoader<'a, B: UsbBus> {
+    serial: SerialPort<'a, B>,
+    device: UsbDevice<'a, B>,
+    state: State,
+    rom: [u8; MAX_ROM_SIZE],
+}
+
+impl<'a, B: UsbBus> UsbLoader<'a, B> {
+    pub fn new(bus: &'a UsbBusAllocator<B>) -> Self {
+        let serial = SerialPort::new(bus);
+        let device = UsbDeviceBuilder::new(bus, UsbVidPid(0x16c0, 0x27dd))
+            .manufacturer("traverse1984")
+            .product("CHIP-8 Pico")
+            .serial_number("CHIP8")
+            .device_class(USB_CLASS_CDC)
+            .build();
+
+        Self {
+            serial,
+            device,
+            state: State::idle(),
+            rom: [0; MAX_ROM_SIZE],
+        }
+    }
+
+    /// Service the USB peripheral, loading a ROM into `emulator` once one has
+    /// been received in full. This must be called at least every 10ms to keep
+    /// the host happy.
+    pub fn poll(&mut self, emulator: &mut Emulator) -> Option<Event> {
+        if !self.device.poll(&mut [&mut self.serial]) {
+            return None;
+        }
+
+        // A partial transfer is dropped if the host goes away or closes the
+        // port part way through.
+        if self.device.state() != UsbDeviceState::Configured || !self.serial.dtr() {
+            self.state = State::idle();
+            return None;
+        }
+
+        let mut buf = [0; 64];
+        let count = match self.serial.read(&mut buf) {
+            Ok(count) => count,
+            Err(_) => return None,
+        };
+
+        let mut event = None;
+        for &byte in &buf[..count] {
+            event = self.receive(byte, emulator).or(event);
+        }
+
+        event
+    }
+
+    /// Answer a screenshot request with the top-left `width` by `rows.len()`
+    /// pixels of a framebuffer, in the format described on `UsbLoader`.
+    /// Blocks until the whole image has been queued, unless the host stops
+    /// reading, when the rest is dropped.
+    pub fn send_pbm<const COLS: usize>(&mut self, width: u8, rows: &[[u8; COLS]]) {
+        let mut header = Header {
+            buf: [0; 16],
+            len: 0,
+        };
+        write!(header, "P4\n{} {}\n", width, rows.len()).ok();
+
+        let bytes = (width as usize / 8).min(COLS);
+        let mut result = self.send(&header.buf[..header.len]);
+
+        for row in rows {
+            result = result.and_then(|_| self.send(&row[..bytes]));
+        }
+
+        result.ok();
+    }
+
+    /// Service the USB peripheral without reading anything, for when there
+    /// is no emulator to load into. Returns whether a host has the port
+    /// open. As with `poll`, call this at least every 10ms.
+    pub fn poll_idle(&mut self) -> bool {
+        self.device.poll(&mut [&mut self.serial]);
+        self.device.state() == UsbDeviceState::Configured && self.serial.dtr()
+    }
+
+    /// Send `text` as it is, blocking until it has all been queued or the
+    /// host stops reading.
+    pub fn send_text(&mut self, text: &[u8]) {
+        self.send(text).ok();
+    }
+
+    /// Whether a ROM is part way through being received.
+    pub fn receiving(&self) -> bool {
+        match self.state {
+            State::Length { received, .. } => received > 0,
+            State::Rom { .. } => true,
+        }
+    }
+
+    /// Write all of `data`, servicing the device while the host catches up.
+    /// Fails with `UsbError::WouldBlock` once it has gone `SEND_POLLS` polls
+    /// without the host taking anything.
+    fn send(&mut self, mut data: &[u8]) -> Result<(), UsbError> {
+        let mut polls = 0;
+
+        while !data.is_empty() {
+            match self.serial.write(data) {
+                Ok(count) => {
+                    data = &data[count..];
+                    polls = 0;
+                }
+                Err(UsbError::WouldBlock) if polls < SEND_POLLS => {
+                    self.device.poll(&mut [&mut self.serial]);
+                    polls += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self, byte: u8, emulator: &mut Emulator) -> Option<Event> {
+        match &mut self.state {
+            State::Length { buf, received } => {
+                buf[*received] = byte;
+                *received += 1;
+
+                if *received < buf.len() {
+                    return None;
+                }
+
+                let len = u16::from_be_bytes(*buf);
+
+                if len == SCREENSHOT {
+                    self.state = State::idle();
+                    return Some(Event::Screenshot);
+                }
+
+                self.state = if len == 0 || len as usize > MAX_ROM_SIZE {
+                    self.reply(NAK);
+                    State::idle()
+                } else {
+                    State::Rom {
+                        len: len as usize,
+                        received: 0,
+                    }
+                };
+
+                None
+            }
+            State::Rom { len, received } => {
+                self.rom[*received] = byte;
+                *received += 1;
+
+                if *received < *len {
+                    return None;
+                }
+
+                let len = *len;
+                self.state = State::idle();
+
+                match emulator.load(&self.rom[..len]) {
+                    Ok(()) => {
+                        self.reply(ACK);
+                        Some(Event::RomLoaded)
+                    }
+                    Err(_) => {
+                        self.reply(NAK);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    fn reply(&mut self, byte: u8) {
+        self.serial.write(&[byte]).ok();
+    }
+}
+
+impl State {
+    fn idle() -> Self {
+        State::Length {
+            buf: [0; 2],
+            received: 0,
+        }
+    }
+}
+
+/// Room for the PBM header, which is at most `P4\n255 255\n`.
+struct Header {
+    buf: [u8; 16],
+    len: usize,
+}
+
+impl Write for Header {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(core::fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
@@ -1,52 +1,285 @@
-#![no_std]
-#![no_main]
+// Tests run on the host, with std and the test harness, as
+// `cargo test --target <host triple>`.
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(test, allow(dead_code))]
+#![cfg_attr(feature = "embassy", feature(type_alias_impl_trait))]
 
-use cortex_m::delay::Delay as CortexDelay;
+use cortex_m::singleton;
+#[cfg(not(test))]
 use cortex_m_rt::entry;
 use embedded_time::rate::*;
 use rp_pico::{
     hal::{
         clocks,
-        gpio::{FunctionSpi, Pin},
-        pac::{CorePeripherals, Peripherals},
+        gpio::{
+            bank0::{Gpio12, Gpio20, Gpio21, Gpio25, Gpio26, Gpio8, Gpio9},
+            DynPin, FloatingInput, FunctionSpi, Pin, PullUpInput, PushPullOutput,
+        },
+        pac::{CorePeripherals, Peripherals, RESETS, SPI1, USBCTRL_DPRAM, USBCTRL_REGS},
         prelude::*,
-        Sio, Spi, Watchdog,
+        pwm,
+        spi::Enabled,
+        usb, Adc, Sio, Spi, Watchdog,
     },
     Pins,
 };
 
-use embedded_hal::spi;
+use embedded_hal::{
+    spi,
+    watchdog::{Watchdog as _, WatchdogDisable as _, WatchdogEnable as _},
+};
+use embedded_time::duration::Microseconds;
+use usb_device::class_prelude::UsbBusAllocator;
 
+#[cfg(all(not(test), not(feature = "panic-screen")))]
 use panic_halt as _;
 
-use embedded_hal::adc::OneShot;
-use embedded_hal::PwmPin;
+#[cfg(all(not(test), feature = "panic-screen"))]
+mod panic;
 
-use embedded_hal::digital::v2::OutputPin;
+#[cfg(feature = "defmt")]
+use defmt_rtt as _;
 
 mod screen;
-use screen::Sh1106;
+use screen::{Orientation, Sh1106HiRes, CHAR_ADVANCE, GLYPH_HEIGHT, PLANE_0};
 
 use chip8::pal::*;
 mod keypad;
 
-use keypad::GpioKeypad;
+use keypad::{remap_keys, GestureConfig, GestureRecognizer, GpioKeypad, GpioKeypad4x4};
+
+mod buzzer;
+use buzzer::Buzzer;
+
+mod delay;
+use delay::Delay;
+
+#[cfg(feature = "demo")]
+mod demo;
+
+#[cfg(feature = "debugger")]
+mod debug;
+#[cfg(feature = "debugger")]
+use debug::{Action, Debugger, TimerMode};
+
+mod control;
+use control::{Control, Controls, RunState};
+
+mod emulator;
+use emulator::Emulator;
+
+mod menu;
+
+mod quirks;
+
+mod rng;
+
+mod roms;
+
+mod sdcard;
+use sdcard::{RomName, SdCard};
+
+mod cartridge;
+use cartridge::Cartridge;
+
+mod led;
+use led::StatusLed;
+
+mod loader;
+use loader::{Event, UsbLoader};
+
+mod diag;
+
+mod calibrate;
+
+mod font;
+
+mod governor;
+use governor::{CycleGovernor, DisplayWait};
+
+mod settings;
+use settings::Settings;
+
+mod splash;
+
+mod idle;
+use idle::{Brightness, IdleDimmer};
+
+#[cfg(not(feature = "embassy"))]
+mod contrast;
+#[cfg(not(feature = "embassy"))]
+use contrast::ContrastKeys;
+
+#[cfg(not(feature = "embassy"))]
+mod wake;
+
+#[cfg(feature = "perf")]
+mod perf;
+#[cfg(feature = "perf")]
+use perf::PerfCounter;
+
+#[cfg(feature = "buttons")]
+mod buttons;
+#[cfg(feature = "buttons")]
+use buttons::{Button, Buttons};
+
+#[cfg(feature = "speed-knob")]
+mod knob;
+#[cfg(feature = "speed-knob")]
+use knob::SpeedKnob;
+
+#[cfg(feature = "temp-overlay")]
+mod temp;
+#[cfg(feature = "temp-overlay")]
+use temp::TempSensor;
+
+mod timer;
+use timer::TickTimer;
+
+#[cfg(feature = "trace")]
+mod trace;
+
+#[cfg(feature = "dma")]
+mod frame_dma;
+#[cfg(feature = "dma")]
+use frame_dma::SpiFrameDma;
+
+#[cfg(feature = "embassy")]
+mod tasks;
 
 pub mod types;
+use types::AppError;
+
+/// Consecutive screen errors to try recovering from before giving up.
+const SCREEN_RETRIES: u8 = 3;
+
+/// Most instructions the run loop runs in one pass before going round to poll
+/// USB, scan the keypad and update the buzzer. The governor still sets the
+/// rate, so this only matters when it has fallen behind or is set fast. Each
+/// pass costs several microseconds of servicing, so more instructions per
+/// pass gets a higher top speed, while fewer keeps USB, keys and sound more
+/// responsive: a burst of draws without `vsync` sends a frame each, around a
+/// millisecond apiece at `SCREEN_SPI_SLOW_HZ`, and USB wants polling every
+/// 10ms or so. At the default rates it rarely runs more than one.
+#[cfg(not(feature = "embassy"))]
+const SERVICE_EVERY: u32 = 8;
+
+/// SPI clock for the screen. Long wires and some panels garble pixels at this
+/// rate, which can't be detected as the SH1106 is write-only. Scattered or
+/// shifted pixels on the ROM menu are the usual sign; holding `SLOW_SPI_KEY`
+/// at power-on uses `SCREEN_SPI_SLOW_HZ` instead.
+const SCREEN_SPI_HZ: u32 = 30_000_000;
+const SCREEN_SPI_SLOW_HZ: u32 = 8_000_000;
+const SLOW_SPI_KEY: u8 = 0x0;
+
+/// How long the boot splash is held between fading in and out.
+const SPLASH_US: u32 = 1_000_000;
+
+/// How long a single key may be held in the run loop before it is taken to
+/// be stuck, and a warning goes up until it is let go. Long enough for any
+/// game.
+const STUCK_KEY_MS: u32 = 120_000;
+
+/// The run loop must come back round within this long or the board resets,
+/// so a hang in the firmware doesn't leave a dead board. A ROM stuck in a
+/// loop of its own still runs the loop, so it isn't affected. The slowest
+/// pass is dimming the screen, which blocks for up to half a second. Full
+/// frame flushes take around a millisecond even at `SCREEN_SPI_SLOW_HZ`.
+/// The watchdog is only armed once setup has finished, as the menus wait on
+/// the keypad for as long as they need to.
+const WATCHDOG_TIMEOUT_US: u32 = 1_000_000;
+
+/// How long the notice after a watchdog reset stays up before the menu.
+const WATCHDOG_NOTICE_US: u32 = 2_000_000;
+
+/// With the `vsync` feature, drawing only updates the framebuffer and the
+/// run loop sends it once per 60Hz tick, so a frame is never shown half drawn.
+/// Without it each draw is sent as it happens, which shows changes up to a
+/// frame sooner but can tear when a ROM draws across a refresh.
+const VSYNC: bool = cfg!(feature = "vsync");
+
+/// How the panel is mounted. Change this for a board fitted upside down or
+/// seen through a mirror.
+const SCREEN_ORIENTATION: Orientation = Orientation::Normal;
+
+type Oled = Sh1106HiRes<
+    Spi<Enabled, SPI1, 8>,
+    Pin<Gpio9, PushPullOutput>,
+    Pin<Gpio8, PushPullOutput>,
+    Pin<Gpio12, PushPullOutput>,
+>;
+
+type Keys = GpioKeypad4x4<DynPin, DynPin>;
+
+/// The channel the vsync flush goes out on, with the `dma` feature.
+#[cfg(feature = "dma")]
+type FlushDma = SpiFrameDma;
+#[cfg(not(feature = "dma"))]
+type FlushDma = ();
 
-// impl Delay for CortexDelay {
-//     fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
-//         self.delay_us(us);
-//         Ok(())
-//     }
-// }
+#[cfg(feature = "buttons")]
+type BoardButtons = Buttons<Pin<Gpio20, PullUpInput>, Pin<Gpio21, PullUpInput>>;
 
-#[entry]
+#[cfg(feature = "speed-knob")]
+type Knob = SpeedKnob<Adc, Adc, Pin<Gpio26, FloatingInput>>;
+
+/// Everything the run loop needs, as built by `setup`.
+struct App {
+    screen: Oled,
+    keypad: Keys,
+    buzzer: Buzzer<pwm::Pwm7>,
+    delay: Delay,
+    timer: TickTimer,
+    emulator: Emulator,
+    loader: UsbLoader<'static, usb::UsbBus>,
+    led: StatusLed<Pin<Gpio25, PushPullOutput>>,
+    watchdog: Watchdog,
+    dma: FlushDma,
+    settings: Settings,
+    /// Instructions per second, from the settings unless a cartridge says
+    /// otherwise.
+    rate: u32,
+    /// Instructions per frame in place of `rate`, unless 0.
+    cycles_per_frame: u16,
+    #[cfg(feature = "buttons")]
+    buttons: BoardButtons,
+    #[cfg(feature = "speed-knob")]
+    knob: Knob,
+    #[cfg(feature = "temp-overlay")]
+    temp: TempSensor,
+    /// Shared with the knob when it is fitted.
+    #[cfg(all(feature = "temp-overlay", not(feature = "speed-knob")))]
+    adc: Adc,
+}
+
+#[cfg_attr(not(test), entry)]
 fn main() -> ! {
-    let mut pac = Peripherals::take().unwrap();
+    let pac = Peripherals::take().unwrap();
     let core = CorePeripherals::take().unwrap();
+
+    let err = match setup(pac, core) {
+        #[cfg(not(feature = "embassy"))]
+        Ok(app) => run(app).unwrap_err(),
+        #[cfg(feature = "embassy")]
+        Ok(app) => tasks::run(app),
+        Err(err) => err,
+    };
+
+    halt_with_code(err.code())
+}
+
+fn setup(mut pac: Peripherals, core: CorePeripherals) -> types::Result<App> {
+    // The reason is kept until the next reset, so it has to be read before
+    // anything can go wrong.
+    let watchdog_reset = pac.WATCHDOG.reason.read().timer().bit_is_set();
     let mut watchdog = Watchdog::new(pac.WATCHDOG);
 
+    // Likewise for what the last run was doing, which is only of interest
+    // after a watchdog reset.
+    #[cfg(feature = "trace")]
+    let last_trace = trace::start().filter(|_| watchdog_reset);
+
     let clocks = clocks::init_clocks_and_plls(
         rp_pico::XOSC_CRYSTAL_FREQ,
         pac.XOSC,
@@ -56,10 +289,10 @@ fn main() -> ! {
         &mut pac.RESETS,
         &mut watchdog,
     )
-    .ok()
-    .unwrap();
+    .map_err(|_| AppError::Clocks)?;
 
     let mut delay = Delay::new(core.SYST, clocks.system_clock.freq().integer());
+    let timer = TickTimer::new(pac.TIMER, &mut pac.RESETS);
 
     let sio = Sio::new(pac.SIO);
     let pins = Pins::new(
@@ -69,11 +302,40 @@ fn main() -> ! {
         &mut pac.RESETS,
     );
 
+    let mut keypad = {
+        let cols: [DynPin; 4] = [
+            pins.gpio0.into_push_pull_output().into(),
+            pins.gpio1.into_push_pull_output().into(),
+            pins.gpio2.into_push_pull_output().into(),
+            pins.gpio3.into_push_pull_output().into(),
+        ];
+
+        let rows: [DynPin; 4] = [
+            pins.gpio4.into_pull_down_input().into(),
+            pins.gpio5.into_pull_down_input().into(),
+            pins.gpio13.into_pull_down_input().into(),
+            pins.gpio14.into_pull_down_input().into(),
+        ];
+
+        let mut keypad = GpioKeypad::new(cols, rows).with_stuck_timeout_ms(STUCK_KEY_MS);
+
+        keypad.init()?;
+        keypad
+    };
+
+    let boot_key = keypad.read_key(&mut delay)?;
+    let spi_hz = match boot_key {
+        Some(SLOW_SPI_KEY) => SCREEN_SPI_SLOW_HZ,
+        _ => SCREEN_SPI_HZ,
+    };
+
+    let mut led = StatusLed::new(pins.led.into_push_pull_output());
+
     let mut screen = {
         let spi: Spi<_, _, 8> = Spi::new(pac.SPI1).init(
             &mut pac.RESETS,
             clocks.peripheral_clock.freq(),
-            30_000_000u32.Hz(),
+            spi_hz.Hz(),
             &spi::MODE_0,
         );
 
@@ -84,59 +346,946 @@ fn main() -> ! {
         let _: Pin<_, FunctionSpi> = pins.gpio10.into_mode();
         let _: Pin<_, FunctionSpi> = pins.gpio11.into_mode();
 
-        let mut screen = Sh1106::new(spi, cs, dcmd, reset);
-        screen.init().ok().unwrap();
+        // Full panel resolution for SUPER-CHIP, dropping to 64x32 mode once
+        // the splash has been shown.
+        let mut screen = Sh1106HiRes::new(spi, cs, dcmd, reset);
+
+        #[cfg(not(feature = "init-verify"))]
+        screen.init()?;
+
+        #[cfg(feature = "init-verify")]
+        verify_screen(&mut screen, &mut led, &mut delay, &timer)?;
+
+        screen.set_orientation(SCREEN_ORIENTATION)?;
         screen
     };
 
-    let mut keypad = {
-        let mut keypad = GpioKeypad::new(
-            pins.gpio0.into_push_pull_output(),
-            pins.gpio1.into_push_pull_output(),
-            pins.gpio2.into_push_pull_output(),
-            pins.gpio3.into_push_pull_output(),
-            pins.gpio4.into_pull_down_input(),
-            pins.gpio5.into_pull_down_input(),
-            pins.gpio13.into_pull_down_input(),
-            pins.gpio14.into_pull_down_input(),
+    #[cfg(feature = "dma")]
+    let dma = {
+        let frame = singleton!(: [u8; screen::FRAME_BYTES] = [0; screen::FRAME_BYTES])
+            .ok_or(AppError::Dma)?;
+        let mut dma = SpiFrameDma::new(pac.DMA, frame, &mut pac.RESETS);
+        show_flush_times(&mut screen, &mut dma, &mut delay, &timer)?;
+        dma
+    };
+    #[cfg(not(feature = "dma"))]
+    let dma = ();
+
+    led.set(true);
+
+    let mut settings = settings::load();
+    keypad.set_keymap(settings.keymap);
+    screen.set_contrast(settings.contrast)?;
+    splash::show(&mut screen, &mut delay, SPLASH_US)?;
+
+    if watchdog_reset {
+        screen.draw_str(0, 0, "WATCHDOG RESET")?;
+
+        #[cfg(feature = "trace")]
+        if let Some(last) = &last_trace {
+            #[cfg(feature = "defmt")]
+            for entry in last.iter() {
+                defmt::warn!("trace: {:04X} {:04X}", entry.pc, entry.opcode);
+            }
+
+            show_trace(&mut screen, GLYPH_HEIGHT + 2, last)?;
+        }
+
+        delay.delay_us(WATCHDOG_NOTICE_US)?;
+        screen.clear()?;
+    }
+
+    screen.set_hires(false)?;
+
+    if boot_key == Some(diag::DIAG_KEY) {
+        let mut loader = usb_loader(
+            pac.USBCTRL_REGS,
+            pac.USBCTRL_DPRAM,
+            clocks.usb_clock,
+            &mut pac.RESETS,
+        )?;
+
+        match diag::stream_keypad(&mut screen, &mut keypad, &mut loader, &mut delay)? {}
+    }
+
+    if boot_key == Some(calibrate::CALIBRATE_KEY) {
+        let (keymap, save) = calibrate::run(&mut screen, &mut keypad, &mut delay)?;
+
+        if save {
+            settings.keymap = keymap;
+            settings::save(&settings);
+        }
+    }
+
+    // GPIO15 is PWM slice 7, channel B
+    let buzzer = {
+        let slices = pwm::Slices::new(pac.PWM, &mut pac.RESETS);
+        let mut slice = slices.pwm7;
+        slice.channel_b.output_to(pins.gpio15);
+
+        Buzzer::new(
+            slice,
+            buzzer::Channel::B,
+            clocks.system_clock.freq().integer(),
+            buzzer::DEFAULT_TONE_HZ,
+        )
+    };
+
+    // See the buttons module for why these aren't the board's own pins.
+    #[cfg(feature = "buttons")]
+    let buttons = Buttons::new(
+        pins.gpio20.into_pull_up_input(),
+        pins.gpio21.into_pull_up_input(),
+    );
+
+    #[cfg(feature = "demo")]
+    match demo::run(
+        &mut screen,
+        &mut keypad,
+        &chip8::ram::Ram::new(),
+        &mut delay,
+        &mut led,
+    )? {};
+
+    let mut adc = Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut temp_sensor = adc.enable_temp_sensor();
+    let seed = rng::seed_from_adc(&mut adc, &mut temp_sensor);
+
+    #[cfg(feature = "temp-overlay")]
+    let temp = TempSensor::new(temp_sensor);
+
+    // A potentiometer between 3V3 and GND, with its wiper on GPIO26 (ADC0).
+    #[cfg(feature = "speed-knob")]
+    let knob = SpeedKnob::new(adc, pins.gpio26.into_floating_input());
+
+    // ROMs on the SD card take priority over those in flash. See the sdcard
+    // module for the wiring.
+    let mut rom_buf = [0; emulator::MAX_ROM_SIZE];
+    let mut cartridge_buf = [0; cartridge::MAX_SIZE];
+    let sd_file = {
+        let spi: Spi<_, _, 8> = Spi::new(pac.SPI0).init(
+            &mut pac.RESETS,
+            clocks.peripheral_clock.freq(),
+            400_000u32.Hz(),
+            &spi::MODE_0,
         );
 
-        keypad.init().ok().unwrap();
-        keypad
+        let cs = pins.gpio17.into_push_pull_output();
+
+        let _: Pin<_, FunctionSpi> = pins.gpio16.into_mode();
+        let _: Pin<_, FunctionSpi> = pins.gpio18.into_mode();
+        let _: Pin<_, FunctionSpi> = pins.gpio19.into_mode();
+
+        SdCard::mount(spi, cs).ok().and_then(|mut card| {
+            let roms = card.list_roms().ok().filter(|roms| !roms.is_empty())?;
+            let index = menu::select(
+                &mut screen,
+                &mut keypad,
+                &mut delay,
+                roms.len(),
+                0,
+                |index| roms.get(index).map_or("", RomName::as_str),
+            )
+            .ok()?;
+
+            let name = roms.get(index)?;
+            let buf = if name.is_cartridge() {
+                &mut cartridge_buf[..]
+            } else {
+                &mut rom_buf[..]
+            };
+
+            let len = card.load_rom(name, buf).ok()?;
+            Some((name.is_cartridge(), len))
+        })
+    };
+
+    let picked = match sd_file {
+        Some((false, len)) => Some((&rom_buf[..len], None)),
+        Some((true, len)) => match Cartridge::parse(&cartridge_buf[..len]) {
+            Ok(cartridge) => {
+                let index = menu::select(
+                    &mut screen,
+                    &mut keypad,
+                    &mut delay,
+                    cartridge.len(),
+                    0,
+                    |index| cartridge.get(index).map_or("", |entry| entry.name),
+                )?;
+
+                cartridge.get(index).map(|entry| (entry.rom, Some(entry)))
+            }
+            Err(err) => {
+                show_cartridge_error(&mut screen, &mut keypad, &mut delay, err)?;
+                None
+            }
+        },
+        None => None,
     };
 
-    let sq = [1, 2, 4, 8, 16, 32, 64, 128];
+    // A ROM flashed on its own is booted straight away, unless one was
+    // picked from the SD card.
+    let (rom, entry) = match picked.or_else(|| roms::embedded_rom().map(|rom| (rom, None))) {
+        Some(picked) => picked,
+        None => {
+            let start = settings.rom as usize;
+            let index =
+                menu::select_rom(&mut screen, &mut keypad, &mut delay, roms::ROM_TABLE, start)?;
+
+            settings.rom = index as u8;
+            settings::save(&settings);
+            (roms::ROM_TABLE[index].1, None)
+        }
+    };
+
+    let mut emulator = Emulator::new();
+    emulator.seed_rng(seed);
+    let (rate, cycles_per_frame) =
+        apply_rom_settings(entry, &settings, &mut emulator, &mut screen, &mut keypad);
+    emulator.load(rom)?;
+
+    let loader = usb_loader(
+        pac.USBCTRL_REGS,
+        pac.USBCTRL_DPRAM,
+        clocks.usb_clock,
+        &mut pac.RESETS,
+    )?;
+
+    Ok(App {
+        screen,
+        keypad,
+        buzzer,
+        delay,
+        timer,
+        emulator,
+        loader,
+        led,
+        watchdog,
+        dma,
+        settings,
+        rate,
+        cycles_per_frame,
+        #[cfg(feature = "buttons")]
+        buttons,
+        #[cfg(feature = "speed-knob")]
+        knob,
+        #[cfg(feature = "temp-overlay")]
+        temp,
+        #[cfg(all(feature = "temp-overlay", not(feature = "speed-knob")))]
+        adc,
+    })
+}
+
+/// How long the `init-verify` border stays up.
+#[cfg(feature = "init-verify")]
+const VERIFY_US: u32 = 1_000_000;
+
+/// Initialise the screen with `init_verify` and leave its border up for a
+/// moment, flashing the LED three times so that a dark panel can be told
+/// from a board which didn't get this far.
+#[cfg(feature = "init-verify")]
+fn verify_screen(
+    screen: &mut Oled,
+    led: &mut StatusLed<Pin<Gpio25, PushPullOutput>>,
+    delay: &mut Delay,
+    timer: &TickTimer,
+) -> types::Result {
+    let init_us = screen.init_verify(&screen::Sh1106Config::default(), || timer.now_us())?;
+
+    #[cfg(feature = "defmt")]
+    defmt::info!("screen init took {}us", init_us);
+    #[cfg(not(feature = "defmt"))]
+    let _ = init_us;
+
+    for _ in 0..3 {
+        led.set(true);
+        delay.delay_us(VERIFY_US / 6)?;
+        led.set(false);
+        delay.delay_us(VERIFY_US / 6)?;
+    }
+
+    screen.clear_overlay()?;
+    Ok(())
+}
+
+/// How long the `dma` flush timings stay up.
+#[cfg(feature = "dma")]
+const FLUSH_TIMES_US: u32 = 3_000_000;
 
-    screen.xor(2, 0, &sq);
-    screen.xor(48, 23, &sq);
+/// Time the run loop's flush with and without DMA, and show the microseconds
+/// each frame took. With DMA the transfer goes on while the CPU is free, so
+/// the first DMA figure is the CPU time a flush costs and the second is how
+/// long the frame takes to reach the panel.
+#[cfg(feature = "dma")]
+fn show_flush_times(
+    screen: &mut Oled,
+    dma: &mut SpiFrameDma,
+    delay: &mut Delay,
+    timer: &TickTimer,
+) -> types::Result {
+    let times = frame_dma::measure(screen, dma, timer)?;
 
-    let mut led = pins.led.into_push_pull_output();
-    led.set_high().ok();
+    #[cfg(feature = "defmt")]
+    defmt::info!(
+        "flush per frame: {}us blocking, {}us with DMA, {}us until sent",
+        times.blocking_us,
+        times.dma_us,
+        times.dma_wait_us
+    );
+
+    screen.clear()?;
+
+    let lines = [
+        ("SPI", times.blocking_us),
+        ("DMA", times.dma_us),
+        ("DMA+WAIT", times.dma_wait_us),
+    ];
+
+    for ((label, us), y) in lines
+        .into_iter()
+        .zip((0..).step_by(GLYPH_HEIGHT as usize + 2))
+    {
+        let mut buf = [0; 12];
+        screen.draw_str(0, y, label)?;
+        screen.draw_str(9 * CHAR_ADVANCE, y, frame_dma::us_text(us, &mut buf))?;
+    }
+
+    delay.delay_us(FLUSH_TIMES_US)?;
+    screen.clear()?;
+    Ok(())
+}
+
+/// The USB serial loader. The bus allocator is a singleton, so this can
+/// only be called once.
+fn usb_loader(
+    regs: USBCTRL_REGS,
+    dpram: USBCTRL_DPRAM,
+    clock: clocks::UsbClock,
+    resets: &mut RESETS,
+) -> types::Result<UsbLoader<'static, usb::UsbBus>> {
+    let usb_bus = singleton!(: UsbBusAllocator<usb::UsbBus> = UsbBusAllocator::new(
+        usb::UsbBus::new(regs, dpram, clock, true, resets)
+    ))
+    .ok_or(AppError::Usb)?;
+
+    Ok(UsbLoader::new(usb_bus))
+}
+
+/// Spins the core, for when nothing else can be relied on. Assumes the
+/// 125MHz system clock.
+struct SpinDelay;
+
+impl chip8::pal::Delay for SpinDelay {
+    type Error = chip8::pal::Error;
+
+    fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+        cortex_m::asm::delay(us * 125);
+        Ok(())
+    }
+}
+
+/// Blink SOS on the onboard LED followed by `code` flashes, forever. The LED
+/// is set up from scratch, as `setup` may have failed before reaching it.
+/// Everything is about 20 times slower if the clocks failed to start.
+fn halt_with_code(code: u8) -> ! {
+    // Safety: whatever failed has returned, dropping everything it took.
+    let mut pac = unsafe { Peripherals::steal() };
+    disarm_watchdog(&pac);
+
+    let sio = Sio::new(pac.SIO);
+    let pins = Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let mut led = StatusLed::new(pins.led.into_push_pull_output());
+
+    loop {
+        led.error_blink(code, &mut SpinDelay);
+    }
+}
+
+/// Stop the watchdog, so a board halted on an error stays that way with the
+/// error showing.
+fn disarm_watchdog(pac: &Peripherals) {
+    pac.WATCHDOG.ctrl.modify(|_, w| w.enable().clear_bit());
+}
+
+/// Set up everything a ROM runs with: the user's own settings, with any that
+/// `entry` recommends in their place. A cartridge's recommendations apply to
+/// that ROM only, so they aren't saved over the user's settings, and every
+/// setting is applied each time so that none carry over to the next ROM.
+/// Returns the rate and instructions per frame to run it at.
+fn apply_rom_settings(
+    entry: Option<cartridge::Entry>,
+    settings: &Settings,
+    emulator: &mut Emulator,
+    screen: &mut Oled,
+    keypad: &mut Keys,
+) -> (u32, u16) {
+    let quirks = entry
+        .and_then(|entry| entry.quirks)
+        .unwrap_or(settings.quirks);
+    let keymap = entry
+        .and_then(|entry| entry.keymap)
+        .unwrap_or(settings.keymap);
+
+    emulator.set_quirks(quirks);
+    screen.set_clip_sprites(quirks.clip_sprites);
+    keypad.set_keymap(keymap);
+
+    match entry.and_then(|entry| entry.rate) {
+        Some(rate) => (rate, 0),
+        None => (settings.rate, settings.cycles_per_frame),
+    }
+}
+
+/// Run the emulator until something fails which can't be recovered from.
+#[cfg(not(feature = "embassy"))]
+fn run(app: App) -> types::Result<core::convert::Infallible> {
+    let App {
+        mut screen,
+        mut keypad,
+        mut buzzer,
+        mut delay,
+        timer,
+        mut emulator,
+        mut loader,
+        mut led,
+        mut watchdog,
+        mut dma,
+        mut settings,
+        rate,
+        cycles_per_frame,
+        #[cfg(feature = "buttons")]
+        mut buttons,
+        #[cfg(feature = "speed-knob")]
+        mut knob,
+        #[cfg(feature = "temp-overlay")]
+        mut temp,
+        #[cfg(all(feature = "temp-overlay", not(feature = "speed-knob")))]
+        mut adc,
+    } = app;
+
+    let mut governor = CycleGovernor::new();
+    governor.set_rate(rate);
+    governor.set_cycles_per_frame(cycles_per_frame);
+    let mut display_wait = DisplayWait::new();
+    let mut controls = Controls::new();
+    let mut dimmer = IdleDimmer::new();
+    let mut gestures = GestureRecognizer::new(GestureConfig::default());
+    #[cfg(feature = "debugger")]
+    let mut debugger = Debugger::new();
+    let mut contrast = ContrastKeys::new(settings.contrast);
+    let mut paused_frame = *screen.buffer();
+    let mut tick = timer.ticks();
+    let mut screen_retries = SCREEN_RETRIES;
+    let mut stuck_shown = false;
+    screen.set_deferred(VSYNC);
+    #[cfg(feature = "turbo")]
+    keypad.set_hidden_keys(1 << control::TURBO_KEY);
+    watchdog.pause_on_debug(true);
+    watchdog.start(Microseconds(WATCHDOG_TIMEOUT_US));
+
+    #[cfg(feature = "perf")]
+    let mut perf = PerfCounter::new(timer.now_us(), screen.flush_count());
+
+    loop {
+        watchdog.feed();
+        wait_flush(&mut screen, &mut dma)?;
+
+        match loader.poll(&mut emulator) {
+            Some(Event::RomLoaded) => {
+                #[cfg(feature = "defmt")]
+                defmt::debug!("run loop: ROM loaded over USB");
+                let (rate, cycles_per_frame) =
+                    apply_rom_settings(None, &settings, &mut emulator, &mut screen, &mut keypad);
+                governor.set_rate(rate);
+                governor.set_cycles_per_frame(cycles_per_frame);
+                screen.set_plane_mask(PLANE_0);
+                screen.set_hires(false)?;
+                screen.clear_fast();
+                controls = Controls::new();
+            }
+            Some(Event::Screenshot) => {
+                let rows = &screen.buffer()[..screen.height() as usize];
+                loader.send_pbm(screen.width(), rows);
+            }
+            None => {}
+        }
+
+        let now = timer.ticks();
+        let elapsed = now - tick;
+        tick = now;
+
+        // With DMA the frame goes out later, while instructions run.
+        #[cfg(not(feature = "dma"))]
+        if VSYNC && elapsed > 0 {
+            screen.flush()?;
+        }
+
+        #[cfg(feature = "speed-knob")]
+        if elapsed > 0 {
+            let rate = knob.rate();
+
+            if rate != governor.rate() {
+                governor.set_rate(rate);
+            }
+        }
+
+        // The overlay is composited as rows are sent rather than drawn into
+        // the framebuffer, so sprites passing under it don't collide.
+        #[cfg(feature = "perf")]
+        if let Some(report) = perf.update(timer.now_us(), screen.flush_count()) {
+            let text = report.text();
+            let width = text.as_str().len() as u8 * CHAR_ADVANCE + 1;
+            let x = screen.width().saturating_sub(width);
+
+            screen.set_deferred(true);
+            screen.overlay_str(x, 0, text.as_str())?;
+            screen.flush()?;
+            screen.set_deferred(VSYNC);
+        }
+
+        // In the bottom corner, clear of the perf overlay and the contrast
+        // banner. Like the perf overlay it is composited as rows are sent, so
+        // it can't cause collisions.
+        #[cfg(feature = "temp-overlay")]
+        {
+            #[cfg(feature = "speed-knob")]
+            let adc = knob.adc_mut();
+            #[cfg(not(feature = "speed-knob"))]
+            let adc = &mut adc;
+
+            if let Some(celsius) = temp.update(adc, timer.now_us()) {
+                let mut buf = [0; temp::TEXT_LEN];
+                let text = temp::text(celsius, &mut buf);
+                let width = text.len() as u8 * CHAR_ADVANCE + 1;
+                let x = screen.width().saturating_sub(width);
+                let y = screen.height() - (GLYPH_HEIGHT + 2);
+
+                screen.set_deferred(true);
+                screen.overlay_str(x, y, text)?;
+                screen.flush()?;
+                screen.set_deferred(VSYNC);
+            }
+        }
+
+        if elapsed > 0 {
+            if loader.receiving() {
+                led.rom_loading();
+            } else {
+                led.heartbeat(elapsed);
+            }
+        }
+
+        // The keypad is read one column per pass, so scanning it never holds
+        // up the emulator for long, and nothing is scanned unless a key is
+        // held. Releases are picked up once per tick.
+        let keys = match keypad.key_is_pressed()? {
+            true => keypad.poll_scan(&mut delay)?,
+            false if elapsed > 0 => Some(0),
+            false => None,
+        };
+
+        // The firmware's own keys stay where the user has them, whatever
+        // keymap the ROM brought.
+        let keys = keys.map(|keys| remap_keys(keys, keypad.keymap(), &settings.keymap));
+
+        let mut control = None;
+        let mut open_menu = false;
+
+        if let Some(keys) = keys {
+            // The ROM carries on under the warning, as it may not need the
+            // key at all.
+            match keypad.check_stuck(keys, timer.now_us()) {
+                Err(err) if !stuck_shown => {
+                    show_stuck_key(&mut screen, err)?;
+                    stuck_shown = true;
+                }
+                Ok(()) if stuck_shown => {
+                    screen.clear_overlay()?;
+                    stuck_shown = false;
+                }
+                _ => {}
+            }
+
+            match dimmer.update(keys, timer.now_us()) {
+                Some(Brightness::Dim) => {
+                    screen.fade_to(idle::DIM_CONTRAST, &mut delay, idle::FADE_STEP_US)?
+                }
+                Some(Brightness::Wake) => screen.set_contrast(contrast.level())?,
+                None => {}
+            }
+
+            // Any gesture opens the ROM menu.
+            open_menu = gestures.update(keys, timer.now_us()).is_some();
+            control = controls.update(keys, timer.now_us());
+
+            #[cfg(feature = "turbo")]
+            governor.set_turbo(controls.turbo());
+        }
+
+        // KEY0 opens the ROM menu and KEY1 pauses, as with the keypad.
+        #[cfg(feature = "buttons")]
+        if elapsed > 0 {
+            buttons.poll();
+            open_menu |= buttons.just_pressed(Button::Key0);
+
+            if buttons.just_pressed(Button::Key1) {
+                control = Some(controls.toggle_pause());
+            }
+        }
+
+        // The menu waits on the keypad for as long as it takes, so the
+        // watchdog is stopped meanwhile.
+        if open_menu {
+            #[cfg(feature = "defmt")]
+            defmt::debug!("run loop: opening the ROM menu");
+            buzzer.off();
+            watchdog.disable();
+
+            screen.clear_overlay()?;
+            screen.set_plane_mask(PLANE_0);
+            screen.set_hires(false)?;
+
+            // The menu is driven on the user's own keymap, and the built-in
+            // ROMs run with the user's own settings.
+            keypad.set_keymap(settings.keymap);
+            let start = settings.rom as usize;
+            let index =
+                menu::select_rom(&mut screen, &mut keypad, &mut delay, roms::ROM_TABLE, start)?;
+            let (rate, cycles_per_frame) =
+                apply_rom_settings(None, &settings, &mut emulator, &mut screen, &mut keypad);
+            governor.set_rate(rate);
+            governor.set_cycles_per_frame(cycles_per_frame);
+            emulator.load(roms::ROM_TABLE[index].1)?;
+
+            settings.rom = index as u8;
+            settings::save(&settings);
+
+            watchdog.start(Microseconds(WATCHDOG_TIMEOUT_US));
+            screen.clear_fast();
+            screen.set_deferred(VSYNC);
+            controls = Controls::new();
+            gestures = GestureRecognizer::new(GestureConfig::default());
+            continue;
+        }
+
+        #[cfg(feature = "defmt")]
+        if let Some(control) = control {
+            defmt::debug!("run loop: {}", control);
+        }
+
+        match control {
+            Some(Control::Pause) => {
+                paused_frame = *screen.buffer();
+                buzzer.off();
+
+                #[cfg(not(feature = "debugger"))]
+                {
+                    screen.set_deferred(true);
+                    screen.fill_rect(0, 0, 5 * CHAR_ADVANCE + 1, GLYPH_HEIGHT + 2, false)?;
+                    screen.draw_str(1, 1, "PAUSE")?;
+                    screen.flush()?;
+                    screen.set_deferred(VSYNC);
+                }
+
+                #[cfg(feature = "debugger")]
+                show_debugger(&mut screen, &debugger, &emulator)?;
+            }
+            Some(Control::Resume) => {
+                *screen.buffer_mut() = paused_frame;
+                screen.flush()?;
+
+                // Saved on the way out of the pause, so stepping through
+                // the levels doesn't write to flash each time.
+                settings.contrast = contrast.level();
+                settings::save(&settings);
+            }
+            Some(Control::Reset) => {
+                emulator.reset();
+                screen.set_plane_mask(PLANE_0);
+                screen.set_hires(false)?;
+                screen.clear_fast();
+            }
+            None => {}
+        }
+
+        // Once the screen has dimmed, a ROM waiting on Fx0A or a paused one
+        // can't do anything until a key is pressed, so the core sleeps until
+        // then. Ticks missed while asleep are caught up on waking.
+        let stalled = controls.state() == RunState::Paused
+            || matches!(emulator.state(), emulator::CpuState::WaitKey { .. })
+                && !emulator.sound_active();
+
+        if dimmer.dimmed() && stalled && !loader.receiving() {
+            watchdog.disable();
+            wake::sleep(&mut keypad)?;
+            watchdog.start(Microseconds(WATCHDOG_TIMEOUT_US));
+        }
+
+        if controls.state() == RunState::Paused {
+            if let Some(level) = keys.and_then(|keys| contrast.update(keys, timer.now_us())) {
+                screen.set_contrast(level)?;
+                show_contrast(&mut screen, level)?;
+            }
+
+            if contrast.expired(timer.now_us()) {
+                hide_contrast(&mut screen, &paused_frame)?;
+
+                #[cfg(feature = "debugger")]
+                show_debugger(&mut screen, &debugger, &emulator)?;
+            }
+
+            #[cfg(feature = "debugger")]
+            {
+                if debug::TIMERS == TimerMode::Realtime {
+                    for _ in 0..elapsed {
+                        emulator.tick_timers();
+                    }
+                }
+
+                let action = keys.and_then(|keys| debugger.update(keys, emulator.pc()));
+
+                // The banner is taken off the frame first, so the stepped
+                // instruction can't collide with it.
+                if action == Some(Action::Step) {
+                    *screen.buffer_mut() = paused_frame;
+                    step(
+                        &mut emulator,
+                        &mut screen,
+                        &mut dma,
+                        &mut keypad,
+                        &mut delay,
+                        &mut screen_retries,
+                    )?;
+                    paused_frame = *screen.buffer();
+                }
+
+                if action.is_some() {
+                    show_debugger(&mut screen, &debugger, &emulator)?;
+                }
+            }
+
+            continue;
+        }
+
+        // The transfer runs alongside the instructions, which only draw into
+        // the framebuffer. It is waited for before anything else is sent.
+        #[cfg(feature = "dma")]
+        if elapsed > 0 {
+            screen.flush_dma(&mut dma)?;
+        }
+
+        // Up to `SERVICE_EVERY` instructions run back to back, after which
+        // the loop goes round to poll USB, scan the keypad and update the
+        // buzzer.
+        for burst in 0..SERVICE_EVERY {
+            let draw = emulator.quirks().display_wait && emulator.draws_next();
+
+            // While waiting on Fx0A, each step scans the keypad, so it is only
+            // stepped once per pass, after the run loop has finished a scan of
+            // its own, rather than at the instruction rate. The timers carry
+            // on regardless.
+            let blocked = draw && display_wait.blocked(elapsed > 0);
+            let ready = !blocked
+                && match emulator.state() {
+                    emulator::CpuState::WaitKey { .. } => burst == 0 && keys.is_some(),
+                    emulator::CpuState::Running => governor.ready(timer.now_us()),
+                };
+
+            if !ready {
+                break;
+            }
+
+            step(
+                &mut emulator,
+                &mut screen,
+                &mut dma,
+                &mut keypad,
+                &mut delay,
+                &mut screen_retries,
+            )?;
+
+            if draw {
+                display_wait.drawn();
+            }
+
+            #[cfg(feature = "perf")]
+            perf.instruction();
+
+            #[cfg(feature = "debugger")]
+            if debugger.hit(emulator.pc()) {
+                controls.pause();
+                paused_frame = *screen.buffer();
+                buzzer.off();
+                wait_flush(&mut screen, &mut dma)?;
+                show_debugger(&mut screen, &debugger, &emulator)?;
+                break;
+            }
+        }
+
+        for _ in 0..elapsed {
+            emulator.tick_timers();
+        }
+
+        if emulator.sound_active() {
+            buzzer.on();
+        } else {
+            buzzer.off();
+        }
+    }
+}
+
+/// Run one instruction. A few screen errors in a row are recovered from by
+/// resending the frame, as the panel can't be read back to check it.
+#[cfg(not(feature = "embassy"))]
+fn step(
+    emulator: &mut Emulator,
+    screen: &mut Oled,
+    dma: &mut FlushDma,
+    keypad: &mut Keys,
+    delay: &mut Delay,
+    retries: &mut u8,
+) -> types::Result {
+    match emulator.step(screen, keypad, delay) {
+        Ok(()) => *retries = SCREEN_RETRIES,
+        Err(emulator::Error::Chip8(chip8::pal::Error::Screen)) if *retries > 0 => {
+            *retries -= 1;
+            wait_flush(screen, dma)?;
+            screen.recover().ok();
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    Ok(())
+}
+
+/// Wait for a frame still going out over DMA, so that the panel can be
+/// written to. Without the `dma` feature there is never one.
+#[cfg(not(feature = "embassy"))]
+fn wait_flush(screen: &mut Oled, dma: &mut FlushDma) -> types::Result {
+    #[cfg(feature = "dma")]
+    screen.wait_flush(dma)?;
+    #[cfg(not(feature = "dma"))]
+    let _ = (screen, dma);
+
+    Ok(())
+}
+
+/// Say why a cartridge was rejected and wait for a key, after which the
+/// built-in ROMs are offered instead.
+fn show_cartridge_error(
+    screen: &mut Oled,
+    keypad: &mut Keys,
+    delay: &mut Delay,
+    err: cartridge::Error,
+) -> types::Result {
+    screen.clear()?;
+    screen.draw_str(0, 0, "BAD CARTRIDGE")?;
+    screen.draw_str(0, GLYPH_HEIGHT + 2, err.as_str())?;
+    keypad.wait_for_key(delay)?;
+    screen.clear()?;
+
+    Ok(())
+}
+
+/// Lines taken by `show_trace`.
+#[cfg(feature = "trace")]
+const TRACE_LINES: u8 = ((trace::LEN + TRACE_PER_LINE - 1) / TRACE_PER_LINE) as u8;
+
+#[cfg(feature = "trace")]
+const TRACE_PER_LINE: usize = 3;
+
+/// Draw the traced instructions from `y` down, oldest first and three to a
+/// line, which needs the full 128 pixel width.
+#[cfg(feature = "trace")]
+fn show_trace(screen: &mut Oled, y: u8, trace: &trace::Trace) -> types::Result {
+    const PITCH: u8 = (trace::Entry::TEXT_LEN as u8 + 1) * CHAR_ADVANCE;
+
+    for (n, entry) in trace.iter().enumerate() {
+        let text = entry.text();
+        let x = (n % TRACE_PER_LINE) as u8 * PITCH;
+        let y = y + (n / TRACE_PER_LINE) as u8 * (GLYPH_HEIGHT + 1);
+
+        screen.draw_str(x, y, core::str::from_utf8(&text).unwrap_or(""))?;
+    }
+
+    Ok(())
+}
+
+/// Say which key is stuck across the middle of the screen. It goes in the
+/// overlay, so the ROM's own pixels are left alone.
+#[cfg(not(feature = "embassy"))]
+fn show_stuck_key(screen: &mut Oled, err: keypad::Error) -> types::Result {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    if let keypad::Error::StuckKey(key) = err {
+        let mut text = *b"KEY ? STUCK";
+        text[4] = HEX[key as usize & 0xF];
+        let y = (screen.height() - (GLYPH_HEIGHT + 2)) / 2;
+
+        screen.set_deferred(true);
+        screen.overlay_str(0, y, core::str::from_utf8(&text).unwrap_or(""))?;
+        screen.flush()?;
+        screen.set_deferred(VSYNC);
+    }
+
+    Ok(())
+}
+
+/// Height of the contrast banner along the bottom of the screen.
+#[cfg(not(feature = "embassy"))]
+const CONTRAST_BANNER: u8 = GLYPH_HEIGHT + 2;
+
+#[cfg(not(feature = "embassy"))]
+fn show_contrast(screen: &mut Oled, level: u8) -> types::Result {
+    const LABEL: &str = "CONTRAST ";
+
+    let mut buf = [0; 3];
+    let digits = contrast::digits(level, &mut buf);
+    let width = (LABEL.len() + digits.len()) as u8 * CHAR_ADVANCE + 1;
+    let y = screen.height() - CONTRAST_BANNER;
+
+    screen.set_deferred(true);
+    screen.fill_rect(0, y, width, CONTRAST_BANNER, false)?;
+    screen.draw_str(1, y + 1, LABEL)?;
+    screen.draw_str(1 + LABEL.len() as u8 * CHAR_ADVANCE, y + 1, digits)?;
+    screen.flush()?;
+    screen.set_deferred(VSYNC);
+    Ok(())
+}
+
+/// Put back the part of the paused frame under the contrast banner.
+#[cfg(not(feature = "embassy"))]
+fn hide_contrast(screen: &mut Oled, frame: &[[u8; 16]; 64]) -> types::Result {
+    let end = screen.height() as usize;
+    let start = end - CONTRAST_BANNER as usize;
+
+    screen.buffer_mut()[start..end].copy_from_slice(&frame[start..end]);
+    screen.flush()
+}
 
-    // let ram = chip8::ram::Ram::new();
+/// Draw the CPU state over the top of the screen in place of the pause
+/// banner.
+#[cfg(all(feature = "debugger", not(feature = "embassy")))]
+fn show_debugger(screen: &mut Oled, debugger: &Debugger, emulator: &Emulator) -> types::Result {
+    const PITCH: u8 = GLYPH_HEIGHT + 1;
 
-    // let sprite = |key: u8| -> &[u8] {
-    //     let sprite = ram.get_sprite_addr(key);
-    //     ram.read_bytes(sprite, 5)
-    // };
+    let lines = debugger.lines(&emulator.snapshot());
+    let width = debug::LINE_CHARS * CHAR_ADVANCE + 1;
 
-    // loop {
-    //     match keypad.read_key(&mut delay) {
-    //         Ok(Some(key)) => {
-    //             screen.clear();
-    //             screen.xor(2, 8, sprite(key));
-    //             led.set_low().ok();
-    //         }
-    //         Ok(None) => {
-    //             led.set_high().ok();
-    //         }
-    //         _ => {
-    //             led.set_high().ok();
-    //         }
-    //     }
+    screen.set_deferred(true);
+    screen.fill_rect(0, 0, width, debug::LINES as u8 * PITCH + 1, false)?;
 
-    //     delay.delay_ms(100);
-    // }
+    for (line, y) in lines.iter().zip((0u8..).map(|n| 1 + n * PITCH)) {
+        screen.draw_str(1, y, line.as_str())?;
+    }
 
-    loop {}
+    screen.flush()?;
+    screen.set_deferred(VSYNC);
+    Ok(())
 }
@@ -0,0 +1,154 @@
+use crate::keypad::{self, GpioKeypad4x4};
+use crate::screen::{self, DisplayBus, Sh1106Display, GLYPH_HEIGHT};
+use chip8::pal::Delay;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+pub const KEY_UP: u8 = 0x2;
+pub const KEY_DOWN: u8 = 0x8;
+pub const KEY_SELECT: u8 = 0x5;
+
+const LINE_HEIGHT: u8 = GLYPH_HEIGHT + 1;
+
+type Result<T = ()> = core::result::Result<T, Error>;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    NoRoms,
+    Screen(screen::Error),
+    Keypad(keypad::Error),
+}
+
+impl From<screen::Error> for Error {
+    fn from(err: screen::Error) -> Self {
+        Error::Screen(err)
+    }
+}
+
+impl From<keypad::Error> for Error {
+    fn from(err: keypad::Error) -> Self {
+        Error::Keypad(err)
+    }
+}
+
+/// Show a scrollable list of ROM names and return the index of the one picked
+/// with the keypad, starting with `start` highlighted. `KEY_UP` and
+/// `KEY_DOWN` move the highlight, `KEY_SELECT` chooses.
+pub fn select_rom<BUS, RS, C, R, D, const COLS: usize, const ROWS: usize>(
+    screen: &mut Sh1106Display<BUS, RS, COLS, ROWS>,
+    keypad: &mut GpioKeypad4x4<C, R>,
+    delay: &mut D,
+    roms: &[(&str, &[u8])],
+    start: usize,
+) -> Result<usize>
+where
+    BUS: DisplayBus,
+    RS: OutputPin,
+    C: OutputPin,
+    R: InputPin,
+    D: Delay,
+{
+    select(screen, keypad, delay, roms.len(), start, |index| {
+        roms[index].0
+    })
+}
+
+/// Like `select_rom`, but for a list of `count` entries named by `name`. A
+/// `start` past the end of the list highlights the first entry.
+pub fn select<'n, BUS, RS, C, R, D, F, const COLS: usize, const ROWS: usize>(
+    screen: &mut Sh1106Display<BUS, RS, COLS, ROWS>,
+    keypad: &mut GpioKeypad4x4<C, R>,
+    delay: &mut D,
+    count: usize,
+    start: usize,
+    name: F,
+) -> Result<usize>
+where
+    BUS: DisplayBus,
+    RS: OutputPin,
+    C: OutputPin,
+    R: InputPin,
+    D: Delay,
+    F: Fn(usize) -> &'n str,
+{
+    if count == 0 {
+        return Err(Error::NoRoms);
+    }
+
+    let mut selected = if start < count { start } else { 0 };
+    let mut top = (selected + 1).saturating_sub(visible_lines(screen));
+
+    screen.set_deferred(true);
+    draw(screen, count, &name, selected, top)?;
+
+    loop {
+        match keypad.read_key_repeat(delay)? {
+            Some(KEY_UP) if selected > 0 => selected -= 1,
+            Some(KEY_DOWN) if selected + 1 < count => selected += 1,
+            Some(KEY_SELECT) => break,
+            _ => continue,
+        }
+
+        top = top
+            .min(selected)
+            .max((selected + 1).saturating_sub(visible_lines(screen)));
+        draw(screen, count, &name, selected, top)?;
+    }
+
+    clear(screen)?;
+    screen.set_deferred(false);
+
+    Ok(selected)
+}
+
+fn draw<'n, BUS, RS, F, const COLS: usize, const ROWS: usize>(
+    screen: &mut Sh1106Display<BUS, RS, COLS, ROWS>,
+    count: usize,
+    name: &F,
+    selected: usize,
+    top: usize,
+) -> Result
+where
+    BUS: DisplayBus,
+    RS: OutputPin,
+    F: Fn(usize) -> &'n str,
+{
+    let (width, height) = (screen.width(), screen.height());
+    screen.fill_rect(0, 0, width, height, false)?;
+
+    for (index, line) in (top..count).zip(0..visible_lines(screen)) {
+        let y = line as u8 * LINE_HEIGHT;
+        let highlight = index == selected;
+
+        if highlight {
+            screen.fill_rect(0, y, width, LINE_HEIGHT, true)?;
+        }
+
+        screen.draw_text(1, y + 1, name(index), !highlight)?;
+    }
+
+    screen.flush()?;
+    Ok(())
+}
+
+fn clear<BUS, RS, const COLS: usize, const ROWS: usize>(
+    screen: &mut Sh1106Display<BUS, RS, COLS, ROWS>,
+) -> Result
+where
+    BUS: DisplayBus,
+    RS: OutputPin,
+{
+    let (width, height) = (screen.width(), screen.height());
+    screen.fill_rect(0, 0, width, height, false)?;
+    screen.flush()?;
+    Ok(())
+}
+
+fn visible_lines<BUS, RS, const COLS: usize, const ROWS: usize>(
+    screen: &Sh1106Display<BUS, RS, COLS, ROWS>,
+) -> usize
+where
+    BUS: DisplayBus,
+    RS: OutputPin,
+{
+    (screen.height() / LINE_HEIGHT) as usize
+}
@@ -0,0 +1,123 @@
+//! A panic handler which shows the panic message on the OLED before halting,
+//! used in place of `panic_halt` when the `panic-screen` feature is enabled.
+//!
+//! Nothing can be assumed about the state of the SPI bus or the screen
+//! driver when a panic happens, and neither can be borrowed from `main`. The
+//! handler steals the peripherals and sets up SPI1, its pins and a new SH1106
+//! driver from scratch, exactly as `main` does. The clocks are assumed to
+//! have been initialised already; a panic before that point is not shown.
+//! The watchdog is stopped so the message stays up, where with `panic_halt`
+//! it resets the board.
+//!
+//! With the `trace` feature the last instructions executed are shown along
+//! the bottom, leaving fewer lines for the message.
+
+use crate::screen::{Sh1106HiRes, CHAR_ADVANCE, GLYPH_HEIGHT};
+use core::{fmt::Write, panic::PanicInfo};
+use embedded_hal::spi;
+use embedded_time::rate::*;
+use rp_pico::{
+    hal::{
+        gpio::{FunctionSpi, Pin},
+        pac::Peripherals,
+        Sio, Spi,
+    },
+    Pins,
+};
+
+/// The peripheral clock frequency set up by `init_clocks_and_plls`.
+const PERIPHERAL_HZ: u32 = 125_000_000;
+
+const LINE_HEIGHT: u8 = GLYPH_HEIGHT + 1;
+const LINE_CHARS: usize = 128 / CHAR_ADVANCE as usize;
+#[cfg(not(feature = "trace"))]
+const LINES: usize = 64 / LINE_HEIGHT as usize - 1;
+#[cfg(feature = "trace")]
+const LINES: usize = 64 / LINE_HEIGHT as usize - 1 - crate::TRACE_LINES as usize;
+
+/// The panic message, truncated to what fits on screen.
+struct Message {
+    buf: [u8; LINE_CHARS * LINES],
+    len: usize,
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            if self.len == self.buf.len() {
+                break;
+            }
+
+            self.buf[self.len] = if byte.is_ascii() { byte } else { b'?' };
+            self.len += 1;
+        }
+
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    let mut message = Message {
+        buf: [0; LINE_CHARS * LINES],
+        len: 0,
+    };
+    write!(message, "{}", info).ok();
+
+    show(&message);
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+fn show(message: &Message) -> Option<()> {
+    // Safety: interrupts are disabled and `main` never runs again.
+    let mut pac = unsafe { Peripherals::steal() };
+    crate::disarm_watchdog(&pac);
+
+    let sio = Sio::new(pac.SIO);
+    let pins = Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let spi: Spi<_, _, 8> = Spi::new(pac.SPI1).init(
+        &mut pac.RESETS,
+        PERIPHERAL_HZ.Hz(),
+        crate::SCREEN_SPI_SLOW_HZ.Hz(),
+        &spi::MODE_0,
+    );
+
+    let cs = pins.gpio9.into_push_pull_output();
+    let dcmd = pins.gpio8.into_push_pull_output();
+    let reset = pins.gpio12.into_push_pull_output();
+
+    let _: Pin<_, FunctionSpi> = pins.gpio10.into_mode();
+    let _: Pin<_, FunctionSpi> = pins.gpio11.into_mode();
+
+    let mut screen = Sh1106HiRes::new(spi, cs, dcmd, reset);
+    screen.init().ok()?;
+    screen.set_deferred(true);
+    screen.draw_str(0, 0, "PANIC").ok()?;
+
+    for (line, y) in message.buf[..message.len]
+        .chunks(LINE_CHARS)
+        .zip((LINE_HEIGHT..).step_by(LINE_HEIGHT as usize))
+    {
+        let line = core::str::from_utf8(line).ok()?;
+        screen.draw_str(0, y, line).ok()?;
+    }
+
+    #[cfg(feature = "trace")]
+    if let Some(trace) = crate::trace::snapshot() {
+        let y = (LINES as u8 + 1) * LINE_HEIGHT;
+        crate::show_trace(&mut screen, y, &trace).ok()?;
+    }
+
+    screen.flush().ok()
+}
@@ -0,0 +1,91 @@
+use core::fmt::{self, Write};
+
+/// How often `PerfCounter::update` produces a report.
+pub const INTERVAL_US: u64 = 1_000_000;
+
+/// Instructions and screen flushes per second over one reporting interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub ips: u32,
+    pub fps: u32,
+}
+
+impl Report {
+    /// Format as `<ips> <fps>`, ready to draw on screen.
+    pub fn text(&self) -> ReportText {
+        let mut text = ReportText {
+            buf: [0; 16],
+            len: 0,
+        };
+
+        write!(text, "{} {}", self.ips, self.fps).ok();
+        text
+    }
+}
+
+pub struct ReportText {
+    buf: [u8; 16],
+    len: usize,
+}
+
+impl ReportText {
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for ReportText {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Measures how fast the run loop is really going. Count each instruction
+/// with `instruction`, and call `update` regularly with the time and the
+/// screen's flush count.
+pub struct PerfCounter {
+    start_us: u64,
+    instructions: u32,
+    start_flushes: u32,
+}
+
+impl PerfCounter {
+    pub fn new(now_us: u64, flushes: u32) -> Self {
+        Self {
+            start_us: now_us,
+            instructions: 0,
+            start_flushes: flushes,
+        }
+    }
+
+    pub fn instruction(&mut self) {
+        self.instructions = self.instructions.wrapping_add(1);
+    }
+
+    /// Once a full interval has passed, return the rates measured over it and
+    /// start the next interval. Both counters are compared with wrapping
+    /// arithmetic, so a timer or flush count which wraps gives a correct
+    /// report rather than a huge one.
+    pub fn update(&mut self, now_us: u64, flushes: u32) -> Option<Report> {
+        let elapsed_us = now_us.wrapping_sub(self.start_us);
+
+        if elapsed_us < INTERVAL_US {
+            return None;
+        }
+
+        let per_second = |count: u32| (count as u64 * 1_000_000 / elapsed_us) as u32;
+        let report = Report {
+            ips: per_second(self.instructions),
+            fps: per_second(flushes.wrapping_sub(self.start_flushes)),
+        };
+
+        *self = Self::new(now_us, flushes);
+        Some(report)
+    }
+}
@@ -0,0 +1,94 @@
+/// Behaviours which differ between CHIP-8 interpreters. ROMs written for one
+/// interpreter often misbehave on another unless these match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8xy6 and 8xyE shift Vy into Vx, rather than shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// Fx55 and Fx65 leave I pointing past the last register stored or
+    /// loaded.
+    pub load_store_increments_i: bool,
+    /// Bnnn jumps to nnn + Vx, where x is the high nibble of nnn, rather than
+    /// nnn + V0.
+    pub bxnn_uses_vx: bool,
+    /// Sprites are clipped at the edges of the display instead of wrapping.
+    pub clip_sprites: bool,
+    /// In 128x64 mode, Dxyn sets VF to the number of sprite rows which
+    /// collided rather than to 1, as SUPER-CHIP 1.1 does.
+    pub count_collision_rows: bool,
+    /// Dxyn waits for the next 60Hz tick before drawing, as the COSMAC VIP
+    /// waited for vertical blank. This stops the flicker some ROMs written
+    /// for it show elsewhere, at a cost: a ROM can draw at most 60 sprites a
+    /// second, and one which draws several per frame runs that many times
+    /// slower.
+    pub display_wait: bool,
+    /// Fx0A waits for the key to be released before storing it, as the
+    /// COSMAC VIP did, rather than taking it as soon as it is pressed.
+    pub key_wait_release: bool,
+}
+
+impl Quirks {
+    /// One bit per quirk, in the order they are declared, as stored in the
+    /// settings sector and in cartridges.
+    pub fn bits(&self) -> u8 {
+        [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.bxnn_uses_vx,
+            self.clip_sprites,
+            self.count_collision_rows,
+            self.display_wait,
+            self.key_wait_release,
+        ]
+        .iter()
+        .zip(0..)
+        .fold(0, |bits, (&set, bit)| bits | (set as u8) << bit)
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        let bit = |n: u8| bits & 1 << n != 0;
+
+        Self {
+            shift_uses_vy: bit(0),
+            load_store_increments_i: bit(1),
+            bxnn_uses_vx: bit(2),
+            clip_sprites: bit(3),
+            count_collision_rows: bit(4),
+            display_wait: bit(5),
+            key_wait_release: bit(6),
+        }
+    }
+
+    /// The original COSMAC VIP interpreter.
+    pub const COSMAC: Self = Self {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        bxnn_uses_vx: false,
+        clip_sprites: true,
+        count_collision_rows: false,
+        display_wait: true,
+        key_wait_release: true,
+    };
+
+    /// CHIP-48 and SUPER-CHIP, which most ROMs in circulation were written
+    /// or tested against.
+    pub const CHIP48: Self = Self {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        bxnn_uses_vx: true,
+        clip_sprites: true,
+        count_collision_rows: true,
+        display_wait: false,
+        key_wait_release: false,
+    };
+}
+
+impl Default for Quirks {
+    /// The set which runs the widest range of ROMs: CHIP-48 behaviour, with
+    /// Bnnn using V0 as very few ROMs rely on the Bxnn form.
+    fn default() -> Self {
+        Self {
+            bxnn_uses_vx: false,
+            ..Self::CHIP48
+        }
+    }
+}
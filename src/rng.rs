@@ -0,0 +1,24 @@
+use embedded_hal::adc::{Channel, OneShot};
+
+const SAMPLES: usize = 64;
+
+/// Build a seed for the CHIP-8 random number generator from the noise in the
+/// least significant bits of repeated ADC readings. This is a best-effort
+/// source of variation between boots, not a cryptographic one.
+pub fn seed_from_adc<A, ADC, PIN>(adc: &mut A, channel: &mut PIN) -> u32
+where
+    A: OneShot<ADC, u16, PIN>,
+    PIN: Channel<ADC>,
+{
+    let mut seed = 0u32;
+
+    for _ in 0..SAMPLES {
+        if let Ok(sample) = adc.read(channel) {
+            seed = (seed ^ sample as u32)
+                .wrapping_mul(0x9E37_79B1)
+                .rotate_left(5);
+        }
+    }
+
+    seed
+}
@@ -0,0 +1,48 @@
+//! A ROM flashed separately from the firmware, so a board can be set up to
+//! boot one game without building the firmware for it.
+//!
+//! The ROM goes in the 4K sector just below the settings sector, at
+//! `OFFSET` from the start of flash (`0x101FE000` through XIP), which
+//! `memory.x` leaves out of the program's flash region. It is written there
+//! with a header, for example with `picotool load -o 0x101FE000`:
+//!
+//! | Offset | Size | Contents                                   |
+//! |--------|------|--------------------------------------------|
+//! | 0      | 4    | `C8RM`                                     |
+//! | 4      | 2    | Length of the ROM, little-endian           |
+//! | 6      | 2    | Reserved, zero                             |
+//! | 8      | n    | The ROM                                    |
+//!
+//! An erased sector reads as all ones, so it never passes as a ROM.
+
+use crate::emulator::MAX_ROM_SIZE;
+
+const MAGIC: [u8; 4] = *b"C8RM";
+const HEADER_LEN: usize = 8;
+
+const FLASH_SIZE: u32 = 2048 * 1024;
+const SECTOR_SIZE: u32 = 4096;
+
+/// Offset of the ROM sector from the start of flash, below the settings.
+pub const OFFSET: u32 = FLASH_SIZE - 2 * SECTOR_SIZE;
+
+/// Where flash appears in the address space through XIP.
+const XIP_BASE: u32 = 0x1000_0000;
+
+/// The ROM flashed at `OFFSET`, if its header is valid and it fits in RAM.
+pub fn embedded_rom() -> Option<&'static [u8]> {
+    // Safety: the ROM sector is always mapped and, unlike the settings
+    // sector, never written by the firmware.
+    let sector: &'static [u8] = unsafe {
+        core::slice::from_raw_parts((XIP_BASE + OFFSET) as *const u8, SECTOR_SIZE as usize)
+    };
+
+    let (header, rom) = sector.split_at(HEADER_LEN);
+    let len = u16::from_le_bytes([header[4], header[5]]) as usize;
+
+    if header[..4] != MAGIC || len == 0 || len > MAX_ROM_SIZE {
+        return None;
+    }
+
+    Some(&rom[..len])
+}
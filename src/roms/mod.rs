@@ -0,0 +1,21 @@
+//! ROMs compiled into flash. Each one sits behind its own feature, so a
+//! minimal build can leave them out.
+
+mod flashed;
+pub use flashed::embedded_rom;
+
+/// Draws the IBM logo and loops forever.
+#[cfg(feature = "rom-ibm-logo")]
+pub const IBM_LOGO: &[u8] = include_bytes!("ibm_logo.ch8");
+
+/// Shows the hex digit of each key as it is pressed.
+#[cfg(feature = "rom-keypad-test")]
+pub const KEYPAD_TEST: &[u8] = include_bytes!("keypad_test.ch8");
+
+/// Every embedded ROM with a name for the menu, in display order.
+pub const ROM_TABLE: &[(&str, &[u8])] = &[
+    #[cfg(feature = "rom-ibm-logo")]
+    ("IBM LOGO", IBM_LOGO),
+    #[cfg(feature = "rom-keypad-test")]
+    ("KEYPAD TEST", KEYPAD_TEST),
+];
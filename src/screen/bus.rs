@@ -0,0 +1,193 @@
+use super::Error;
+use embedded_hal::{
+    blocking::spi::{Write, WriteIter},
+    digital::v2::OutputPin,
+};
+
+#[cfg(feature = "i2c")]
+use embedded_hal::blocking::i2c;
+
+type Result<T = ()> = core::result::Result<T, Error>;
+
+/// How bytes reach the SH1106, so that the display logic is shared by every
+/// way of wiring the panel up.
+pub trait DisplayBus {
+    /// Send bytes to be interpreted as commands.
+    fn command(&mut self, bytes: &[u8]) -> Result;
+
+    /// Send bytes to display RAM at the current column.
+    fn data(&mut self, bytes: &[u8]) -> Result;
+
+    /// Like `data`, for bytes which are generated as they are sent.
+    fn data_iter<I: Iterator<Item = u8>>(&mut self, bytes: I) -> Result;
+}
+
+/// The 4-wire SPI interface, with the data/command pin saying what the bytes
+/// are. This is how the Waveshare Pico-OLED-1.3 is wired.
+#[derive(Debug, Copy, Clone)]
+pub struct SpiBus<SPI, CS, MD> {
+    spi: SPI,
+    cs: CS,
+    mode: MD,
+}
+
+impl<SPI, CS, MD> SpiBus<SPI, CS, MD>
+where
+    SPI: Write<u8> + WriteIter<u8>,
+    CS: OutputPin,
+    MD: OutputPin,
+{
+    pub fn new(spi: SPI, chip_select_pin: CS, mode_pin: MD) -> Self {
+        Self {
+            spi,
+            cs: chip_select_pin,
+            mode: mode_pin,
+        }
+    }
+
+    /// Set the data/command pin and select the panel, leaving it selected.
+    /// This is all a DMA transfer straight into the SPI FIFO needs.
+    pub(super) fn select(&mut self, data: bool) -> Result {
+        let mode = if data {
+            self.mode.set_high()
+        } else {
+            self.mode.set_low()
+        };
+
+        mode.map_err(|_| {
+            #[cfg(feature = "defmt")]
+            defmt::debug!(
+                "mode pin failed: {=str}",
+                core::any::type_name::<MD::Error>()
+            );
+            Error::Mode
+        })?;
+        self.cs.set_low().map_err(Self::cs_error)
+    }
+
+    pub(super) fn deselect(&mut self) -> Result {
+        self.cs.set_high().map_err(Self::cs_error)
+    }
+
+    fn cs_error(_: CS::Error) -> Error {
+        #[cfg(feature = "defmt")]
+        defmt::debug!(
+            "chip select failed: {=str}",
+            core::any::type_name::<CS::Error>()
+        );
+        Error::ChipSelect
+    }
+
+    fn write(&mut self, data: bool, bytes: &[u8]) -> Result {
+        self.select(data)?;
+        self.spi.write(bytes).map_err(|_| {
+            #[cfg(feature = "defmt")]
+            defmt::debug!("SPI write of {} bytes failed", bytes.len());
+            Error::Spi
+        })?;
+        self.deselect()
+    }
+}
+
+impl<SPI, CS, MD> DisplayBus for SpiBus<SPI, CS, MD>
+where
+    SPI: Write<u8> + WriteIter<u8>,
+    CS: OutputPin,
+    MD: OutputPin,
+{
+    #[inline]
+    fn command(&mut self, bytes: &[u8]) -> Result {
+        self.write(false, bytes)
+    }
+
+    #[inline]
+    fn data(&mut self, bytes: &[u8]) -> Result {
+        self.write(true, bytes)
+    }
+
+    fn data_iter<I: Iterator<Item = u8>>(&mut self, bytes: I) -> Result {
+        self.select(true)?;
+        self.spi.write_iter(bytes).map_err(|_| Error::Spi)?;
+        self.deselect()
+    }
+}
+
+/// The SH1106's usual I2C address, with SA0 tied low. Modules with SA0 tied
+/// high answer on 0x3D.
+#[cfg(feature = "i2c")]
+pub const I2C_ADDRESS: u8 = 0x3C;
+
+/// Control bytes which start each I2C write. With the continuation bit
+/// clear, every byte after them is a command or data respectively.
+#[cfg(feature = "i2c")]
+const CONTROL_COMMAND: u8 = 0x00;
+#[cfg(feature = "i2c")]
+const CONTROL_DATA: u8 = 0x40;
+
+/// Bytes sent per I2C write after the control byte. Writes are buffered as
+/// the HAL has no iterator write for I2C, and this covers a doubled row.
+#[cfg(feature = "i2c")]
+const I2C_CHUNK: usize = 32;
+
+/// The I2C interface, as found on many 1.3" SH1106 modules. There are no
+/// pins besides the bus, each write says what it holds in its first byte.
+#[cfg(feature = "i2c")]
+#[derive(Debug, Copy, Clone)]
+pub struct I2cBus<I2C> {
+    i2c: I2C,
+    address: u8,
+}
+
+#[cfg(feature = "i2c")]
+impl<I2C: i2c::Write> I2cBus<I2C> {
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self { i2c, address }
+    }
+
+    /// Send `bytes` in as many writes as it takes, each led by `control`.
+    /// Display RAM writes pick up where the last left off, as the column
+    /// address increments after each byte.
+    fn write<I: Iterator<Item = u8>>(&mut self, control: u8, mut bytes: I) -> Result {
+        let mut buf = [control; I2C_CHUNK + 1];
+
+        loop {
+            let mut len = 1;
+
+            for (slot, byte) in buf[1..].iter_mut().zip(&mut bytes) {
+                *slot = byte;
+                len += 1;
+            }
+
+            if len == 1 {
+                return Ok(());
+            }
+
+            self.i2c.write(self.address, &buf[..len]).map_err(|_| {
+                #[cfg(feature = "defmt")]
+                defmt::debug!("I2C write of {} bytes failed", len);
+                Error::I2c
+            })?;
+
+            if len <= I2C_CHUNK {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(feature = "i2c")]
+impl<I2C: i2c::Write> DisplayBus for I2cBus<I2C> {
+    #[inline]
+    fn command(&mut self, bytes: &[u8]) -> Result {
+        self.write(CONTROL_COMMAND, bytes.iter().copied())
+    }
+
+    #[inline]
+    fn data(&mut self, bytes: &[u8]) -> Result {
+        self.write(CONTROL_DATA, bytes.iter().copied())
+    }
+
+    fn data_iter<I: Iterator<Item = u8>>(&mut self, bytes: I) -> Result {
+        self.write(CONTROL_DATA, bytes)
+    }
+}
@@ -0,0 +1,14 @@
+use super::FRAME_BYTES;
+
+/// A DMA channel feeding the display's SPI TX FIFO. rp2040-hal does not wrap
+/// the DMA peripheral yet, so this is implemented by the board setup code.
+pub trait FrameDma {
+    /// The buffer the next frame is rendered into. Only called when idle.
+    fn frame(&mut self) -> &mut [u8; FRAME_BYTES];
+
+    /// Start streaming the frame buffer.
+    fn start(&mut self);
+
+    /// Block until the transfer has finished.
+    fn wait(&mut self);
+}
@@ -0,0 +1,408 @@
+use super::{
+    sh1106::{PANEL_BYTES, PANEL_COLS},
+    DisplayBus, Error, SchipScreen, FRAME_BYTES, MAX_SPRITE_HEIGHT,
+};
+use chip8::pal::{self, Screen};
+use core::convert::Infallible;
+use embedded_hal::digital::v2::OutputPin;
+
+const WIDTH: u8 = 64;
+const HEIGHT: u8 = 32;
+
+/// The most calls `MockScreen` records, any more are counted but dropped.
+pub const MAX_CALLS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Call {
+    Xor { x: u8, y: u8, len: usize },
+    Clear,
+}
+
+/// A 64x32 `Screen` with no hardware behind it, for exercising the emulator
+/// off-target. Sprites are clipped or wrapped and collide exactly as on
+/// `Sh1106`, and each call is recorded. It stays in 64x32 mode, so a ROM
+/// which switches to 128x64 fails with a screen error.
+pub struct MockScreen {
+    buf: [[u8; 8]; 32],
+    clip_sprites: bool,
+    calls: [Option<Call>; MAX_CALLS],
+    count: usize,
+}
+
+impl MockScreen {
+    pub fn new() -> Self {
+        Self {
+            buf: [[0; 8]; 32],
+            clip_sprites: true,
+            calls: [None; MAX_CALLS],
+            count: 0,
+        }
+    }
+
+    /// As `Sh1106Display::set_clip_sprites`, and likewise on by default.
+    pub fn set_clip_sprites(&mut self, clip: bool) {
+        self.clip_sprites = clip;
+    }
+
+    pub fn buffer(&self) -> &[[u8; 8]; 32] {
+        &self.buf
+    }
+
+    /// Each row packed into a `u64` with the leftmost pixel in the top bit,
+    /// a compact form for comparing against a known frame.
+    pub fn rows(&self) -> [u64; 32] {
+        let mut rows = [0; 32];
+
+        for (packed, row) in rows.iter_mut().zip(&self.buf) {
+            *packed = u64::from_be_bytes(*row);
+        }
+
+        rows
+    }
+
+    fn set_rows(&mut self, rows: [u64; 32]) {
+        for (row, packed) in self.buf.iter_mut().zip(rows) {
+            *row = packed.to_be_bytes();
+        }
+    }
+
+    pub fn pixel(&self, x: u8, y: u8) -> bool {
+        self.buf[y as usize][x as usize / 8] & 0x80 >> (x % 8) != 0
+    }
+
+    /// Panic unless the pixel at `x`, `y` is `on`.
+    pub fn assert_pixel(&self, x: u8, y: u8, on: bool) {
+        assert!(
+            self.pixel(x, y) == on,
+            "pixel ({}, {}) should be {}",
+            x,
+            y,
+            if on { "on" } else { "off" }
+        );
+    }
+
+    /// The recorded calls, oldest first.
+    pub fn calls(&self) -> impl Iterator<Item = Call> + '_ {
+        self.calls.iter().flatten().copied()
+    }
+
+    /// The total number of calls, including any which weren't recorded.
+    pub fn call_count(&self) -> usize {
+        self.count
+    }
+
+    fn record(&mut self, call: Call) {
+        if let Some(slot) = self.calls.get_mut(self.count) {
+            *slot = Some(call);
+        }
+
+        self.count += 1;
+    }
+}
+
+impl Screen for MockScreen {
+    type Error = pal::Error;
+
+    fn xor(&mut self, x: u8, y: u8, data: &[u8]) -> Result<bool, pal::Error> {
+        self.xor_rows(x, y, data).map(|rows| rows != 0)
+    }
+
+    fn clear(&mut self) -> Result<(), pal::Error> {
+        self.record(Call::Clear);
+        self.buf = [[0; 8]; 32];
+        Ok(())
+    }
+}
+
+impl SchipScreen for MockScreen {
+    fn width(&self) -> u8 {
+        WIDTH
+    }
+
+    fn is_hires(&self) -> bool {
+        false
+    }
+
+    fn xor_rows(&mut self, x: u8, y: u8, data: &[u8]) -> Result<u16, pal::Error> {
+        if data.len() > MAX_SPRITE_HEIGHT {
+            return Err(pal::Error::Screen);
+        }
+
+        self.record(Call::Xor {
+            x,
+            y,
+            len: data.len(),
+        });
+
+        let (x, y, offset) = (x % WIDTH, y % HEIGHT, x % 8);
+        let xidx = (x / 8) as usize;
+        let clip = self.clip_sprites;
+        let mut collided = 0;
+
+        for ((scan, ypos), bit) in data.iter().copied().zip(y as usize..).zip(0..) {
+            if clip && ypos >= HEIGHT as usize {
+                break;
+            }
+
+            let row = &mut self.buf[ypos % HEIGHT as usize];
+            let left = scan >> offset;
+            let right = if xidx + 1 < row.len() || !clip {
+                scan.checked_shl(8 - offset as u32).unwrap_or(0)
+            } else {
+                0
+            };
+            let xnext = (xidx + 1) % row.len();
+
+            if row[xidx] & left | row[xnext] & right != 0 {
+                collided |= 1 << bit;
+            }
+
+            row[xidx] ^= left;
+            row[xnext] ^= right;
+        }
+
+        Ok(collided)
+    }
+
+    /// There is only one plane.
+    fn set_plane_mask(&mut self, _: u8) {}
+
+    fn set_hires(&mut self, hires: bool) -> Result<(), pal::Error> {
+        if hires {
+            return Err(pal::Error::Screen);
+        }
+
+        Ok(())
+    }
+
+    fn scroll_down(&mut self, n: u8) -> Result<(), pal::Error> {
+        let (old, mut rows) = (self.rows(), [0; 32]);
+
+        for (row, &src) in rows.iter_mut().skip(n as usize).zip(&old) {
+            *row = src;
+        }
+
+        self.set_rows(rows);
+        Ok(())
+    }
+
+    fn scroll_up(&mut self, n: u8) -> Result<(), pal::Error> {
+        let (old, mut rows) = (self.rows(), [0; 32]);
+
+        for (row, &src) in rows.iter_mut().zip(old.iter().skip(n as usize)) {
+            *row = src;
+        }
+
+        self.set_rows(rows);
+        Ok(())
+    }
+
+    fn scroll_left(&mut self, n: u8) -> Result<(), pal::Error> {
+        self.set_rows(
+            self.rows()
+                .map(|row| row.checked_shl(n as u32).unwrap_or(0)),
+        );
+        Ok(())
+    }
+
+    fn scroll_right(&mut self, n: u8) -> Result<(), pal::Error> {
+        self.set_rows(
+            self.rows()
+                .map(|row| row.checked_shr(n as u32).unwrap_or(0)),
+        );
+        Ok(())
+    }
+}
+
+/// A `DisplayBus` with a copy of display RAM behind it instead of a panel,
+/// for checking what `Sh1106Display` sends. Like the SH1106 in vertical
+/// addressing mode, data fills one panel column of `PANEL_BYTES` after
+/// another from the column last set, and commands other than setting the
+/// column are ignored.
+pub struct MockBus {
+    ram: [[u8; PANEL_BYTES]; PANEL_COLS as usize],
+    col: u8,
+    at: usize,
+    data_bytes: usize,
+    data_writes: usize,
+    written: u64,
+}
+
+impl MockBus {
+    pub fn new() -> Self {
+        Self {
+            ram: [[0; PANEL_BYTES]; PANEL_COLS as usize],
+            col: 0,
+            at: 0,
+            data_bytes: 0,
+            data_writes: 0,
+            written: 0,
+        }
+    }
+
+    /// Display RAM, one entry per panel column.
+    pub fn ram(&self) -> &[[u8; PANEL_BYTES]; PANEL_COLS as usize] {
+        &self.ram
+    }
+
+    /// Data bytes sent since the last `reset_counts`.
+    pub fn data_bytes(&self) -> usize {
+        self.data_bytes
+    }
+
+    /// Data writes since the last `reset_counts`, each of which sets the
+    /// column address first.
+    pub fn data_writes(&self) -> usize {
+        self.data_writes
+    }
+
+    /// The panel columns written to since the last `reset_counts`, with bit
+    /// `n` set for column `n`.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn reset_counts(&mut self) {
+        self.data_bytes = 0;
+        self.data_writes = 0;
+        self.written = 0;
+    }
+}
+
+impl DisplayBus for MockBus {
+    fn command(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        match *bytes {
+            [low @ 0x00..=0x0F] => self.col = self.col & 0x70 | low,
+            [high @ 0x10..=0x17] => self.col = (high & 0x7) << 4 | self.col & 0x0F,
+            _ => return Ok(()),
+        }
+
+        self.at = self.col as usize * PANEL_BYTES;
+        Ok(())
+    }
+
+    fn data(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.data_iter(bytes.iter().copied())
+    }
+
+    fn data_iter<I: Iterator<Item = u8>>(&mut self, bytes: I) -> Result<(), Error> {
+        self.data_writes += 1;
+
+        for byte in bytes {
+            let col = self.at / PANEL_BYTES % PANEL_COLS as usize;
+
+            self.ram[col][self.at % PANEL_BYTES] = byte;
+            self.written |= 1 << col;
+            self.data_bytes += 1;
+            self.at = (self.at + 1) % FRAME_BYTES;
+        }
+
+        Ok(())
+    }
+}
+
+/// An `OutputPin` connected to nothing, for the reset line.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MockPin;
+
+impl OutputPin for MockPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// A sprite of lit rows hanging off the bottom or right edge of a 64x32
+    /// display, with the rows lit when it is clipped and when it wraps.
+    pub(in crate::screen) struct EdgeSprite {
+        pub x: u8,
+        pub y: u8,
+        pub height: usize,
+        pub clipped: &'static [(usize, u64)],
+        pub wrapped: &'static [(usize, u64)],
+    }
+
+    pub(in crate::screen) const EDGE_SPRITES: [EdgeSprite; 3] = [
+        EdgeSprite {
+            x: 60,
+            y: 0,
+            height: 1,
+            clipped: &[(0, 0x0000_0000_0000_000F)],
+            wrapped: &[(0, 0xF000_0000_0000_000F)],
+        },
+        EdgeSprite {
+            x: 0,
+            y: 30,
+            height: 3,
+            clipped: &[(30, 0xFF00_0000_0000_0000), (31, 0xFF00_0000_0000_0000)],
+            wrapped: &[
+                (30, 0xFF00_0000_0000_0000),
+                (31, 0xFF00_0000_0000_0000),
+                (0, 0xFF00_0000_0000_0000),
+            ],
+        },
+        EdgeSprite {
+            x: 60,
+            y: 30,
+            height: 3,
+            clipped: &[(30, 0x0000_0000_0000_000F), (31, 0x0000_0000_0000_000F)],
+            wrapped: &[
+                (30, 0xF000_0000_0000_000F),
+                (31, 0xF000_0000_0000_000F),
+                (0, 0xF000_0000_0000_000F),
+            ],
+        },
+    ];
+
+    /// A packed frame with only the given rows lit.
+    pub(in crate::screen) fn frame(lit: &[(usize, u64)]) -> [u64; 32] {
+        let mut rows = [0; 32];
+
+        for &(y, row) in lit {
+            rows[y] = row;
+        }
+
+        rows
+    }
+
+    fn draw_edge_sprites(clip: bool) {
+        for sprite in &EDGE_SPRITES {
+            let mut screen = MockScreen::new();
+            screen.set_clip_sprites(clip);
+
+            let data = [0xFF; MAX_SPRITE_HEIGHT];
+            let data = &data[..sprite.height];
+            let expected = if clip { sprite.clipped } else { sprite.wrapped };
+
+            assert_eq!(screen.xor(sprite.x, sprite.y, data), Ok(false));
+            assert_eq!(
+                screen.rows(),
+                frame(expected),
+                "at ({}, {})",
+                sprite.x,
+                sprite.y
+            );
+            assert_eq!(screen.xor(sprite.x, sprite.y, data), Ok(true));
+            assert_eq!(screen.rows(), [0; 32]);
+        }
+    }
+
+    #[test]
+    fn sprites_are_clipped_at_the_edges() {
+        draw_edge_sprites(true);
+    }
+
+    #[test]
+    fn sprites_wrap_when_not_clipped() {
+        draw_edge_sprites(false);
+    }
+}
@@ -1,2 +1,26 @@
+mod bus;
+mod schip;
 mod sh1106;
-pub use sh1106::{Error, Sh1106};
+mod text;
+pub use bus::{DisplayBus, SpiBus};
+pub use schip::SchipScreen;
+pub use sh1106::{
+    Error, Orientation, Sh1106, Sh1106Config, Sh1106Display, Sh1106HiRes, TestPattern, ALL_PLANES,
+    FRAME_BYTES, MAX_SPRITE_HEIGHT, PLANE_0, PLANE_1,
+};
+pub use text::{CHAR_ADVANCE, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+#[cfg(feature = "i2c")]
+pub use bus::{I2cBus, I2C_ADDRESS};
+#[cfg(feature = "i2c")]
+pub use sh1106::{Sh1106I2c, Sh1106I2cHiRes};
+
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+#[cfg(any(test, feature = "mock"))]
+pub use mock::{Call, MockBus, MockPin, MockScreen};
+
+#[cfg(feature = "dma")]
+mod dma;
+#[cfg(feature = "dma")]
+pub use dma::FrameDma;
@@ -0,0 +1,76 @@
+use super::{DisplayBus, Error, Sh1106Display};
+use chip8::pal::Screen;
+use embedded_hal::digital::v2::OutputPin;
+
+/// The display operations SUPER-CHIP and XO-CHIP add on top of `Screen`.
+pub trait SchipScreen: Screen {
+    /// Width of the display in the current mode.
+    fn width(&self) -> u8;
+
+    fn is_hires(&self) -> bool;
+
+    /// As `xor`, with bit `n` of the result set when sprite row `n` erased a
+    /// pixel.
+    fn xor_rows(&mut self, x: u8, y: u8, data: &[u8]) -> Result<u16, Self::Error>;
+
+    /// Select the planes drawn to (XO-CHIP Fn01).
+    fn set_plane_mask(&mut self, mask: u8);
+
+    /// Switch between 128x64 (00FF) and 64x32 (00FE).
+    fn set_hires(&mut self, hires: bool) -> Result<(), Self::Error>;
+
+    /// Scroll down by `n` pixels (00Cn).
+    fn scroll_down(&mut self, n: u8) -> Result<(), Self::Error>;
+
+    /// Scroll up by `n` pixels (XO-CHIP 00Dn).
+    fn scroll_up(&mut self, n: u8) -> Result<(), Self::Error>;
+
+    /// Scroll left by `n` pixels (00FC scrolls by 4).
+    fn scroll_left(&mut self, n: u8) -> Result<(), Self::Error>;
+
+    /// Scroll right by `n` pixels (00FB scrolls by 4).
+    fn scroll_right(&mut self, n: u8) -> Result<(), Self::Error>;
+}
+
+impl<BUS, RS, const COLS: usize, const ROWS: usize> SchipScreen
+    for Sh1106Display<BUS, RS, COLS, ROWS>
+where
+    BUS: DisplayBus,
+    RS: OutputPin,
+{
+    fn width(&self) -> u8 {
+        Sh1106Display::width(self)
+    }
+
+    fn is_hires(&self) -> bool {
+        Sh1106Display::is_hires(self)
+    }
+
+    fn xor_rows(&mut self, x: u8, y: u8, data: &[u8]) -> Result<u16, Error> {
+        Sh1106Display::xor_rows(self, x, y, data)
+    }
+
+    fn set_plane_mask(&mut self, mask: u8) {
+        Sh1106Display::set_plane_mask(self, mask)
+    }
+
+    fn set_hires(&mut self, hires: bool) -> Result<(), Error> {
+        Sh1106Display::set_hires(self, hires)
+    }
+
+    fn scroll_down(&mut self, n: u8) -> Result<(), Error> {
+        Sh1106Display::scroll_down(self, n)
+    }
+
+    fn scroll_up(&mut self, n: u8) -> Result<(), Error> {
+        Sh1106Display::scroll_up(self, n)
+    }
+
+    fn scroll_left(&mut self, n: u8) -> Result<(), Error> {
+        Sh1106Display::scroll_left(self, n)
+    }
+
+    fn scroll_right(&mut self, n: u8) -> Result<(), Error> {
+        Sh1106Display::scroll_right(self, n)
+    }
+}
@@ -1,17 +1,38 @@
-use chip8::pal::{self, Screen};
+#[cfg(feature = "dma")]
+use super::FrameDma;
+use super::{DisplayBus, SpiBus};
+use chip8::pal::{self, Delay, Screen};
+use core::{fmt, iter::repeat};
 use embedded_hal::{
     blocking::spi::{Write, WriteIter},
     digital::v2::OutputPin,
 };
 
+#[cfg(feature = "i2c")]
+use super::I2cBus;
+#[cfg(feature = "i2c")]
+use embedded_hal::blocking::i2c;
+
 type Result<T = ()> = core::result::Result<T, Error>;
 
-#[derive(Copy, Clone, Debug)]
+/// Errors from the bus and pins are reduced to which of them failed. Each
+/// pin type has its own error type, so keeping them would make this generic
+/// over every pin and bus the driver is built with, and on the RP2040 the
+/// HAL's pin errors are `Infallible` anyway. With the `defmt` feature the
+/// name of the original error type is logged as the error is mapped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     Spi,
+    I2c,
     ChipSelect,
     Mode,
     Reset,
+    Delay,
+    OutOfBounds,
+    SpriteTooTall,
+    /// A write during `init_verify` took far longer than it should.
+    BusStuck,
 }
 
 impl Into<pal::Error> for Error {
@@ -20,24 +41,172 @@ impl Into<pal::Error> for Error {
     }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Error::Spi => "SPI write failed",
+            Error::I2c => "I2C write failed",
+            Error::ChipSelect => "failed to set chip select pin",
+            Error::Mode => "failed to set data/command pin",
+            Error::Reset => "failed to set reset pin",
+            Error::Delay => "delay failed",
+            Error::OutOfBounds => "pixel out of bounds",
+            Error::SpriteTooTall => "sprite too tall",
+            Error::BusStuck => "bus write took too long",
+        })
+    }
+}
+
+/// Panel settings applied by `Sh1106Display::init_with`.
+#[derive(Debug, Copy, Clone)]
+pub struct Sh1106Config {
+    pub display_start: u8,
+    pub contrast: u8,
+    pub dclk_osc_freq: u8,
+    pub pre_charge_period: u8,
+    pub vcom_deselect_level: u8,
+    pub display_offset: u8,
+    /// Run the panel from the SH1106's internal DC-DC converter. This is the
+    /// controller's power-on default and what the Waveshare Pico-OLED-1.3
+    /// expects; only modules which supply VPP externally should turn it off.
+    /// A panel which stays dark after `init` usually needs it on.
+    pub charge_pump: bool,
+}
+
+impl Default for Sh1106Config {
+    /// Settings for the Waveshare Pico-OLED-1.3.
+    fn default() -> Self {
+        Self {
+            display_start: 0,
+            contrast: 0x80,
+            dclk_osc_freq: 0x41,
+            pre_charge_period: 0x22,
+            vcom_deselect_level: 0x35,
+            display_offset: 0x60,
+            charge_pump: true,
+        }
+    }
+}
+
+/// Patterns for checking that every segment of a new panel works.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TestPattern {
+    AllOn,
+    AllOff,
+    Checkerboard,
+    VerticalStripes,
+    HorizontalStripes,
+}
+
+/// How the panel is mounted, relative to the Waveshare Pico-OLED-1.3 held
+/// with its pins at the bottom. The buffer is always drawn with row 0 at the
+/// top, and the orientation only changes where each logical pixel lands on
+/// the panel, so `xor`, `set_pixel` and scrolling need no changes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Orientation {
+    Normal,
+    Rotate180,
+    /// Mirrored left to right.
+    FlipH,
+    /// Mirrored top to bottom.
+    FlipV,
+}
+
+impl Orientation {
+    /// Whether logical rows run top to bottom in panel column order. Panel
+    /// column 0 is at the bottom when mounted normally.
+    fn rows_reversed(self) -> bool {
+        matches!(self, Orientation::Rotate180 | Orientation::FlipV)
+    }
+
+    /// Whether each panel column is sent back to front, which mirrors the
+    /// pixels along it.
+    fn mirrored(self) -> bool {
+        matches!(self, Orientation::Rotate180 | Orientation::FlipH)
+    }
+}
+
+/// The longest any step of `init_verify` may take. Blanking the panel is the
+/// slowest, at around 25ms over 400kHz I2C and 1ms over SPI.
+pub const INIT_STEP_LIMIT_US: u64 = 50_000;
+
+/// The tallest sprite `xor` accepts (SUPER-CHIP 16x16 sprites).
+pub const MAX_SPRITE_HEIGHT: usize = 16;
+
+/// The physical panel is 64 columns of 128 pixels, mounted so that columns
+/// run across the screen.
+pub(super) const PANEL_COLS: u8 = 64;
+pub(super) const PANEL_BYTES: usize = 16;
+
+/// The size of a complete panel frame.
+pub const FRAME_BYTES: usize = PANEL_COLS as usize * PANEL_BYTES;
+
+/// Drawing plane masks for `set_plane_mask`. Plane 0 is the framebuffer
+/// returned by `buffer`; plane 1 is the second XO-CHIP plane.
+pub const PLANE_0: u8 = 0b01;
+pub const PLANE_1: u8 = 0b10;
+pub const ALL_PLANES: u8 = PLANE_0 | PLANE_1;
+
+/// A 64x32 logical display, scaled 2x to fill the panel.
+pub type Sh1106<SPI, CS, MD, RS> = Sh1106Display<SpiBus<SPI, CS, MD>, RS, 8, 32>;
+
+/// A 128x64 logical display, drawn 1:1 on the panel.
+pub type Sh1106HiRes<SPI, CS, MD, RS> = Sh1106Display<SpiBus<SPI, CS, MD>, RS, 16, 64>;
+
+/// `Sh1106` on a module wired for I2C.
+#[cfg(feature = "i2c")]
+pub type Sh1106I2c<I2C, RS> = Sh1106Display<I2cBus<I2C>, RS, 8, 32>;
+
+/// `Sh1106HiRes` on a module wired for I2C.
+#[cfg(feature = "i2c")]
+pub type Sh1106I2cHiRes<I2C, RS> = Sh1106Display<I2cBus<I2C>, RS, 16, 64>;
+
 /// Incomplete instruction-set implementation for the SH1106 OLED driver, which
 /// is the one used by https://www.waveshare.com/wiki/Pico-OLED-1.3.
+///
+/// The logical display is `COLS` bytes (`8 * COLS` pixels) wide and `ROWS`
+/// pixels high. When it is exactly half the panel size in each dimension,
+/// every pixel is doubled; otherwise it is drawn 1:1. Bytes reach the panel
+/// over `BUS`, which is SPI unless the module is wired for I2C.
 #[derive(Debug, Copy, Clone)]
-pub struct Sh1106<SPI, CS, MD, RS>
+pub struct Sh1106Display<BUS, RS, const COLS: usize, const ROWS: usize>
 where
-    SPI: Write<u8> + WriteIter<u8>,
-    CS: OutputPin,
-    MD: OutputPin,
+    BUS: DisplayBus,
     RS: OutputPin,
 {
-    spi: SPI,
-    cs: CS,
-    mode: MD,
+    bus: BUS,
     reset: RS,
-    buf: [[u8; 8]; 32],
+    buf: [[u8; COLS]; ROWS],
+    plane1: [[u8; COLS]; ROWS],
+    plane_mask: u8,
+    /// Drawn over the planes as rows are sent, lit where `overlay` is set
+    /// and blank elsewhere under `overlay_mask`. Bit `n` of `overlay_rows`
+    /// is set when row `n` has anything on it.
+    overlay: [[u8; COLS]; ROWS],
+    overlay_mask: [[u8; COLS]; ROWS],
+    overlay_rows: u64,
+    /// The panel as last sent, one entry per panel column.
+    shadow: [[u8; PANEL_BYTES]; PANEL_COLS as usize],
+    shadow_valid: bool,
+    diff_flush: bool,
+    sent: u32,
+    dirty: u64,
+    deferred: bool,
+    scaled: bool,
+    clip_sprites: bool,
+    invert_render: bool,
+    orientation: Orientation,
+    lores: bool,
+    flushes: u32,
+    config: Sh1106Config,
+    display_start: u8,
+    contrast: u8,
+    #[cfg(feature = "dma")]
+    flushing: bool,
 }
 
-impl<SPI, CS, MD, RS> Sh1106<SPI, CS, MD, RS>
+impl<SPI, CS, MD, RS, const COLS: usize, const ROWS: usize>
+    Sh1106Display<SpiBus<SPI, CS, MD>, RS, COLS, ROWS>
 where
     SPI: Write<u8> + WriteIter<u8>,
     CS: OutputPin,
@@ -45,62 +214,341 @@ where
     RS: OutputPin,
 {
     pub fn new(spi: SPI, chip_select_pin: CS, mode_pin: MD, reset_pin: RS) -> Self {
+        Self::with_bus(SpiBus::new(spi, chip_select_pin, mode_pin), reset_pin)
+    }
+}
+
+#[cfg(feature = "i2c")]
+impl<I2C, RS, const COLS: usize, const ROWS: usize> Sh1106Display<I2cBus<I2C>, RS, COLS, ROWS>
+where
+    I2C: i2c::Write,
+    RS: OutputPin,
+{
+    /// A display on the I2C bus at `address`, usually `I2C_ADDRESS`.
+    pub fn new_i2c(i2c: I2C, address: u8, reset_pin: RS) -> Self {
+        Self::with_bus(I2cBus::new(i2c, address), reset_pin)
+    }
+}
+
+impl<BUS, RS, const COLS: usize, const ROWS: usize> Sh1106Display<BUS, RS, COLS, ROWS>
+where
+    BUS: DisplayBus,
+    RS: OutputPin,
+{
+    pub const WIDTH: u8 = (COLS * 8) as u8;
+    pub const HEIGHT: u8 = ROWS as u8;
+    const SCALED: bool = COLS * 16 == PANEL_BYTES * 8 && ROWS * 2 == PANEL_COLS as usize;
+    const PANEL_SIZED: bool = COLS == PANEL_BYTES && ROWS == PANEL_COLS as usize;
+
+    pub fn with_bus(bus: BUS, reset_pin: RS) -> Self {
         Self {
-            spi,
-            cs: chip_select_pin,
-            mode: mode_pin,
+            bus,
             reset: reset_pin,
-            buf: [[0; 8]; 32],
+            buf: [[0; COLS]; ROWS],
+            plane1: [[0; COLS]; ROWS],
+            plane_mask: PLANE_0,
+            overlay: [[0; COLS]; ROWS],
+            overlay_mask: [[0; COLS]; ROWS],
+            overlay_rows: 0,
+            shadow: [[0; PANEL_BYTES]; PANEL_COLS as usize],
+            shadow_valid: false,
+            diff_flush: true,
+            sent: 0,
+            dirty: 0,
+            deferred: false,
+            scaled: Self::SCALED,
+            clip_sprites: true,
+            invert_render: false,
+            orientation: Orientation::Normal,
+            lores: false,
+            flushes: 0,
+            config: Sh1106Config::default(),
+            display_start: 0,
+            contrast: 0x80,
+            #[cfg(feature = "dma")]
+            flushing: false,
         }
     }
 
-    #[inline]
-    fn chip_select(&mut self) -> Result {
-        self.cs.set_low().map_err(|_| Error::ChipSelect)
+    /// When deferred, `xor` only updates the buffer and marks rows as dirty;
+    /// nothing is transmitted until `flush` is called.
+    pub fn set_deferred(&mut self, deferred: bool) {
+        self.deferred = deferred;
     }
 
-    #[inline]
-    fn chip_deselect(&mut self) -> Result {
-        self.cs.set_high().map_err(|_| Error::ChipSelect)
+    /// With diffing on, which is the default, `flush` renders each dirty row
+    /// and compares it with what was last sent, then sends only the panel
+    /// columns which really changed. Runs of changed columns share a single
+    /// write with one column address, so a frame where little moves costs a
+    /// few short writes, and rows marked dirty without changing, as after
+    /// `buffer_mut`, cost nothing. With it off every dirty row is sent
+    /// whole, each with its own column address.
+    pub fn set_diff_flush(&mut self, diff: bool) {
+        self.diff_flush = diff;
     }
 
-    #[inline]
-    fn set_mode_cmd(&mut self) -> Result {
-        self.mode.set_low().map_err(|_| Error::Mode)
+    /// Bytes sent to the panel, commands included, wrapping on overflow.
+    /// Compare readings before and after a flush to see what it cost.
+    pub fn bytes_sent(&self) -> u32 {
+        self.sent
     }
 
-    #[inline]
-    fn set_mode_data(&mut self) -> Result {
-        self.mode.set_high().map_err(|_| Error::Mode)
+    /// The logical framebuffer, one bit per pixel with the MSB of each byte
+    /// leftmost.
+    pub fn buffer(&self) -> &[[u8; COLS]; ROWS] {
+        &self.buf
     }
 
-    #[inline]
-    fn write(&mut self, data: &[u8]) -> Result {
-        self.chip_select()?;
-        self.spi.write(data).map_err(|_| Error::Spi)?;
-        self.chip_deselect()
+    /// Mutable access to the logical framebuffer. Every row is marked dirty,
+    /// so the next `flush` redraws the whole display.
+    pub fn buffer_mut(&mut self) -> &mut [[u8; COLS]; ROWS] {
+        self.dirty = u64::MAX >> (64 - ROWS);
+        &mut self.buf
+    }
+
+    /// Zero both planes without sending anything, leaving every row dirty.
+    /// Unlike `clear` there is no blank frame when a new screen is drawn
+    /// straight away, but the old pixels stay on the panel until the next
+    /// `flush`.
+    pub fn clear_fast(&mut self) {
+        self.plane1 = [[0; COLS]; ROWS];
+        *self.buffer_mut() = [[0; COLS]; ROWS];
+    }
+
+    /// Choose which planes `xor`, `clear` and scrolling act on, as selected
+    /// by XO-CHIP's Fn01. A pixel is lit on the panel when it is lit in
+    /// either plane, so overlapping layers simply merge. With both planes
+    /// selected the same sprite is drawn to each, where XO-CHIP would read a
+    /// second sprite for plane 1; split such draws by selecting each plane
+    /// in turn. Text, `fill_rect` and test patterns always use plane 0.
+    pub fn set_plane_mask(&mut self, mask: u8) {
+        self.plane_mask = mask & ALL_PLANES;
+    }
+
+    pub fn plane_mask(&self) -> u8 {
+        self.plane_mask
+    }
+
+    /// Apply `f` to each plane selected by the plane mask.
+    fn for_each_plane(&mut self, mut f: impl FnMut(&mut [[u8; COLS]; ROWS])) {
+        if self.plane_mask & PLANE_0 != 0 {
+            f(&mut self.buf);
+        }
+
+        if self.plane_mask & PLANE_1 != 0 {
+            f(&mut self.plane1);
+        }
+    }
+
+    /// Remove everything drawn with `overlay_str`.
+    pub fn clear_overlay(&mut self) -> Result {
+        if self.overlay_rows == 0 {
+            return Ok(());
+        }
+
+        self.dirty |= self.overlay_rows;
+        self.overlay = [[0; COLS]; ROWS];
+        self.overlay_mask = [[0; COLS]; ROWS];
+        self.overlay_rows = 0;
+        self.present()
+    }
+
+    /// Blank every overlay pixel in rows `y..yend`, leaving them out of the
+    /// overlay until something is drawn there again.
+    pub(super) fn clear_overlay_rows(&mut self, y: u8, yend: u8) {
+        for row in y as usize..yend as usize {
+            self.overlay[row] = [0; COLS];
+            self.overlay_mask[row] = [0; COLS];
+            self.overlay_rows &= !(1 << row);
+            self.dirty |= 1 << row;
+        }
+    }
+
+    /// Cover a single pixel of the overlay, lit or blank.
+    pub(super) fn put_overlay_pixel(&mut self, x: u8, y: u8, on: bool) {
+        let (xidx, yidx) = ((x / 8) as usize, y as usize);
+        let mask = 0x80 >> (x % 8);
+
+        self.overlay_mask[yidx][xidx] |= mask;
+
+        if on {
+            self.overlay[yidx][xidx] |= mask;
+        } else {
+            self.overlay[yidx][xidx] &= !mask;
+        }
+
+        self.overlay_rows |= 1 << yidx;
+        self.dirty |= 1 << yidx;
+    }
+
+    /// Blank the panel, both planes and the overlay, whatever the plane
+    /// mask.
+    fn clear_all(&mut self) -> Result {
+        let mask = self.plane_mask;
+
+        self.overlay = [[0; COLS]; ROWS];
+        self.overlay_mask = [[0; COLS]; ROWS];
+        self.overlay_rows = 0;
+
+        self.plane_mask = ALL_PLANES;
+        let result = self.clear();
+        self.plane_mask = mask;
+        result
+    }
+
+    /// Switch between doubling each pixel and drawing 1:1 in the centre of the
+    /// panel. Scaling is only possible when the logical display is half the
+    /// panel size. The two modes lay out the panel differently, so call
+    /// `clear` after switching to avoid leftover pixels.
+    pub fn set_scaling(&mut self, scaled: bool) {
+        self.scaled = scaled && Self::SCALED;
+    }
+
+    /// Switch between the SUPER-CHIP 128x64 and classic 64x32 modes. This is
+    /// only possible when the display is the full panel size, and otherwise
+    /// does nothing. In low resolution mode only the top-left quarter of the
+    /// buffer is used and every pixel is doubled. The display is cleared when
+    /// the mode changes.
+    pub fn set_hires(&mut self, hires: bool) -> Result {
+        let lores = !hires && Self::PANEL_SIZED;
+
+        if lores != self.lores {
+            self.lores = lores;
+            self.clear_all()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_hires(&self) -> bool {
+        !self.lores
+    }
+
+    /// Width of the display in the current mode.
+    pub fn width(&self) -> u8 {
+        if self.lores {
+            Self::WIDTH / 2
+        } else {
+            Self::WIDTH
+        }
+    }
+
+    /// Height of the display in the current mode.
+    pub fn height(&self) -> u8 {
+        if self.lores {
+            Self::HEIGHT / 2
+        } else {
+            Self::HEIGHT
+        }
+    }
+
+    /// Whether each logical pixel is drawn as a 2x2 block.
+    fn doubled(&self) -> bool {
+        self.scaled || self.lores
+    }
+
+    /// Clip sprites at the edges of the display instead of wrapping them to
+    /// the opposite side. The starting position always wraps. Sprites are
+    /// clipped by default, matching `Quirks::default` and the original
+    /// interpreter; wrapping is for the few ROMs which expect it.
+    pub fn set_clip_sprites(&mut self, clip: bool) {
+        self.clip_sprites = clip;
+    }
+
+    /// Draw dark pixels on a lit background by complementing every byte sent
+    /// to display RAM, margins included. Only what is sent changes, so the
+    /// buffer, and with it collision detection, is unaffected. A screenshot
+    /// from the buffer shows normal polarity. Unlike `set_inverted` this
+    /// works the same on any panel, but it is applied before the controller
+    /// inverts anything, so with both on they cancel out. The whole panel is
+    /// redrawn when this changes; while deferred it stays blank until the
+    /// next `flush`.
+    pub fn set_inverted_render(&mut self, inverted: bool) -> Result {
+        if inverted == self.invert_render {
+            return Ok(());
+        }
+
+        self.invert_render = inverted;
+        self.blank_panel()?;
+        self.dirty = u64::MAX >> (64 - ROWS);
+        self.present()
+    }
+
+    pub fn is_inverted_render(&self) -> bool {
+        self.invert_render
+    }
+
+    /// Draw for a panel mounted as `orientation`. The whole display is
+    /// redrawn when this changes, unless drawing is deferred.
+    pub fn set_orientation(&mut self, orientation: Orientation) -> Result {
+        if orientation == self.orientation {
+            return Ok(());
+        }
+
+        self.orientation = orientation;
+        self.dirty = u64::MAX >> (64 - ROWS);
+        self.present()
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// The byte sent for a blank stretch of panel.
+    fn background(&self) -> u8 {
+        if self.invert_render {
+            0xFF
+        } else {
+            0
+        }
+    }
+
+    /// Fill display RAM with the background, with a single data write which
+    /// relies on the column address incrementing in vertical addressing
+    /// mode.
+    fn blank_panel(&mut self) -> Result {
+        let fill = self.background();
+
+        self.set_col(0)?;
+        self.bus.data_iter(repeat(fill).take(FRAME_BYTES))?;
+        self.shadow = [[fill; PANEL_BYTES]; PANEL_COLS as usize];
+        self.shadow_valid = true;
+        self.sent = self.sent.wrapping_add(FRAME_BYTES as u32);
+
+        Ok(())
     }
 
     #[inline]
-    fn cmd(&mut self, cmd: u8) -> Result {
-        self.set_mode_cmd()?;
-        self.write(&[cmd])
+    fn write_cmd(&mut self, bytes: &[u8]) -> Result {
+        self.sent = self.sent.wrapping_add(bytes.len() as u32);
+        self.bus.command(bytes)
     }
 
     #[inline]
-    fn data(&mut self, data: &[u8]) -> Result {
-        self.set_mode_data()?;
-        self.write(data)
+    fn cmd(&mut self, cmd: u8) -> Result {
+        self.write_cmd(&[cmd])
     }
 
     #[inline]
     fn multibyte_cmd(&mut self, cmd: u8, data: u8) -> Result {
-        self.set_mode_cmd()?;
-        self.write(&[cmd, data])
+        self.write_cmd(&[cmd, data])
     }
 
     pub fn set_display_start(&mut self, start: u8) -> Result {
-        self.multibyte_cmd(0xDC, start)
+        self.display_start = start % PANEL_COLS;
+        self.multibyte_cmd(0xDC, self.display_start)
+    }
+
+    /// Move the display start line by `delta`, wrapping around the panel.
+    /// Stepping this once per frame scrolls the whole panel in hardware,
+    /// without touching the buffer.
+    pub fn scroll_start_by(&mut self, delta: i8) -> Result {
+        let start = (self.display_start as i16 + delta as i16).rem_euclid(PANEL_COLS as i16);
+        self.set_display_start(start as u8)
+    }
+
+    pub fn display_start(&self) -> u8 {
+        self.display_start
     }
 
     pub fn set_vertical_addressing(&mut self) -> Result {
@@ -108,13 +556,70 @@ where
     }
 
     pub fn set_contrast(&mut self, contrast: u8) -> Result {
-        self.multibyte_cmd(0x81, contrast)
+        self.multibyte_cmd(0x81, contrast)?;
+        self.contrast = contrast;
+        Ok(())
+    }
+
+    pub fn contrast(&self) -> u8 {
+        self.contrast
+    }
+
+    /// Step the contrast towards `target` one unit at a time, waiting
+    /// `step_us` between each step.
+    pub fn fade_to<D: Delay>(&mut self, target: u8, delay: &mut D, step_us: u32) -> Result {
+        while self.contrast != target {
+            let next = if self.contrast < target {
+                self.contrast + 1
+            } else {
+                self.contrast - 1
+            };
+
+            self.set_contrast(next)?;
+            delay.delay_us(step_us).map_err(|_| Error::Delay)?;
+        }
+
+        Ok(())
+    }
+
+    /// Enable or disable the internal DC-DC converter. It only takes effect
+    /// while the display is off, so this is best left to `init_with`.
+    pub fn set_charge_pump(&mut self, on: bool) -> Result {
+        self.multibyte_cmd(0xAD, if on { 0x8B } else { 0x8A })
     }
 
     pub fn display_on(&mut self) -> Result {
         self.cmd(0xAF)
     }
 
+    pub fn display_off(&mut self) -> Result {
+        self.cmd(0xAE)
+    }
+
+    /// Turn the panel off to save power. The buffer is kept, so the previous
+    /// contents reappear on the next call to `display_on`.
+    pub fn sleep(&mut self) -> Result {
+        self.display_off()
+    }
+
+    /// Inversion is performed by the display controller, so the XOR buffer
+    /// (and therefore collision detection) is unaffected. It applies on top
+    /// of `set_inverted_render`, so with both on they cancel out.
+    pub fn set_inverted(&mut self, on: bool) -> Result {
+        self.cmd(if on { 0xA7 } else { 0xA6 })
+    }
+
+    /// Mirror the panel horizontally. Combine with `set_com_scan_reverse(true)`
+    /// to rotate the display by 180°.
+    pub fn set_segment_remap(&mut self, flip: bool) -> Result {
+        self.cmd(if flip { 0xA1 } else { 0xA0 })
+    }
+
+    /// Mirror the panel vertically.
+    pub fn set_com_scan_reverse(&mut self, reverse: bool) -> Result {
+        self.cmd(if reverse { 0xC8 } else { 0xC0 })
+    }
+
     pub fn set_display_offset(&mut self, offset: u8) -> Result {
         self.multibyte_cmd(0xD3, offset)
     }
@@ -144,22 +649,136 @@ where
         self.set_higher_col_addr(col >> 4)
     }
 
+    /// Send raw command bytes, for SH1106 features this driver doesn't wrap.
+    /// The driver doesn't know what they do, so anything which changes how
+    /// display RAM is addressed or shown (such as page addressing or the
+    /// start line) leaves the buffer out of step with the panel until the
+    /// next `init`.
+    pub fn send_command(&mut self, bytes: &[u8]) -> Result {
+        self.shadow_valid = false;
+        self.write_cmd(bytes)
+    }
+
+    /// Send raw bytes to display RAM at the current column. The buffer isn't
+    /// updated, so they are overwritten when their rows are next flushed.
+    pub fn send_data(&mut self, bytes: &[u8]) -> Result {
+        self.shadow_valid = false;
+        self.sent = self.sent.wrapping_add(bytes.len() as u32);
+        self.bus.data(bytes)
+    }
+
     pub fn init(&mut self) -> Result {
+        self.init_with(&Sh1106Config::default())
+    }
+
+    pub fn init_with(&mut self, cfg: &Sh1106Config) -> Result {
+        self.config = *cfg;
+
+        for step in Self::INIT_STEPS {
+            step(self, cfg)?;
+        }
+
+        Ok(())
+    }
+
+    /// The steps of `init_with`, each a single bus write apart from the
+    /// reset, so that `init_verify` can time them.
+    const INIT_STEPS: [fn(&mut Self, &Sh1106Config) -> Result; 11] = [
+        |screen, _| screen.reset_panel(),
+        |screen, cfg| screen.set_display_start(cfg.display_start),
+        |screen, cfg| screen.set_contrast(cfg.contrast),
+        |screen, _| screen.set_vertical_addressing(),
+        |screen, cfg| screen.set_dclk_osc_freq(cfg.dclk_osc_freq),
+        |screen, cfg| screen.set_pre_charge_period(cfg.pre_charge_period),
+        |screen, cfg| screen.set_vcom_deselect_level(cfg.vcom_deselect_level),
+        |screen, cfg| screen.set_display_offset(cfg.display_offset),
+        |screen, cfg| screen.set_charge_pump(cfg.charge_pump),
+        |screen, _| screen.clear_all(),
+        |screen, _| screen.display_on(),
+    ];
+
+    fn reset_panel(&mut self) -> Result {
         self.reset
             .set_high()
             .and_then(|_| self.reset.set_low())
             .and_then(|_| self.reset.set_high())
-            .map_err(|_| Error::Reset)?;
+            .map_err(|_| {
+                #[cfg(feature = "defmt")]
+                defmt::debug!(
+                    "reset pin failed: {=str}",
+                    core::any::type_name::<RS::Error>()
+                );
+                Error::Reset
+            })
+    }
+
+    /// As `init_with`, timing each step with `now_us` and failing with
+    /// `Error::BusStuck` if one takes longer than `INIT_STEP_LIMIT_US`,
+    /// which means the bus is hung up rather than slow. Then a border is
+    /// drawn around the display in the overlay and left up, for a person to
+    /// check, until `clear_overlay` takes it down. The framebuffer is left
+    /// blank. Returns how long the init took.
+    ///
+    /// Every step returning `Ok` only means the bytes were sent. The SH1106
+    /// is write-only, so nothing can confirm the panel took them: that takes
+    /// someone looking at the border.
+    pub fn init_verify<F: FnMut() -> u64>(
+        &mut self,
+        cfg: &Sh1106Config,
+        mut now_us: F,
+    ) -> Result<u64> {
+        let start_us = now_us();
+
+        self.config = *cfg;
 
-        self.set_display_start(0)?;
-        self.set_contrast(0x80)?;
-        self.set_vertical_addressing()?;
-        self.set_dclk_osc_freq(0x41)?;
-        self.set_pre_charge_period(0x22)?;
-        self.set_vcom_deselect_level(0x35)?;
-        self.set_display_offset(0x60)?;
-        self.clear()?;
-        self.display_on()
+        for step in Self::INIT_STEPS {
+            let step_us = now_us();
+            step(self, cfg)?;
+
+            if now_us().saturating_sub(step_us) > INIT_STEP_LIMIT_US {
+                return Err(Error::BusStuck);
+            }
+        }
+
+        let init_us = now_us().saturating_sub(start_us);
+        let (width, height) = (self.width(), self.height());
+
+        for x in 0..width {
+            self.put_overlay_pixel(x, 0, true);
+            self.put_overlay_pixel(x, height - 1, true);
+        }
+
+        for y in 0..height {
+            self.put_overlay_pixel(0, y, true);
+            self.put_overlay_pixel(width - 1, y, true);
+        }
+
+        self.present()?;
+
+        Ok(init_us)
+    }
+
+    /// Reset and reinitialise the panel with the settings last passed to
+    /// `init_with` or `init_verify`, then redraw the buffer and the overlay.
+    /// Use this to get going again after a transient bus error. The panel is
+    /// blanked and redrawn straight away, even while drawing is deferred, as
+    /// whatever it held was lost with the reset.
+    pub fn recover(&mut self) -> Result {
+        let (config, buf, plane1) = (self.config, self.buf, self.plane1);
+        let (overlay, overlay_mask, overlay_rows) =
+            (self.overlay, self.overlay_mask, self.overlay_rows);
+
+        let deferred = core::mem::replace(&mut self.deferred, false);
+        let result = self.init_with(&config);
+        self.deferred = deferred;
+        result?;
+
+        self.plane1 = plane1;
+        self.overlay = overlay;
+        self.overlay_mask = overlay_mask;
+        self.overlay_rows = overlay_rows;
+        *self.buffer_mut() = buf;
+        self.flush()
     }
 
     fn scale(byte: u8) -> [u8; 2] {
@@ -174,57 +793,987 @@ where
 
         [SCALED_NIBBLE[lsb], SCALED_NIBBLE[msb]]
     }
+
+    /// The first panel column a logical row is drawn to. When doubled, the
+    /// row is drawn to the following column too.
+    fn row_col(&self, row: usize) -> u8 {
+        // Mounted normally, the highest panel column is the top of the screen.
+        let flipped = if self.orientation.rows_reversed() {
+            row as u8
+        } else {
+            self.height() - 1 - row as u8
+        };
+
+        if self.doubled() {
+            2 * flipped
+        } else {
+            (PANEL_COLS - Self::HEIGHT) / 2 + flipped
+        }
+    }
+
+    /// The panel data for a logical row, and the first panel column it is
+    /// drawn to.
+    fn render_row(&self, row: usize) -> (u8, [u8; PANEL_BYTES]) {
+        let mut draw = [0; PANEL_BYTES];
+
+        if self.doubled() {
+            for (pair, scan) in draw.chunks_exact_mut(2).zip(self.scans(row)) {
+                pair.copy_from_slice(&Self::scale(scan));
+            }
+        } else {
+            let margin = (PANEL_BYTES - COLS) / 2;
+
+            for (byte, scan) in draw[margin..].iter_mut().zip(self.scans(row)) {
+                *byte = scan.reverse_bits();
+            }
+        }
+
+        // The margins either side of the row are the same width, so
+        // mirroring the whole column keeps it centred.
+        if self.orientation.mirrored() {
+            draw.reverse();
+
+            for byte in &mut draw {
+                *byte = byte.reverse_bits();
+            }
+        }
+
+        for byte in &mut draw {
+            *byte ^= self.background();
+        }
+
+        (self.row_col(row), draw)
+    }
+
+    /// A logical row as shown, with the planes composited and the overlay
+    /// on top.
+    fn scans(&self, row: usize) -> impl Iterator<Item = u8> + '_ {
+        self.buf[row]
+            .iter()
+            .zip(&self.plane1[row])
+            .zip(self.overlay_mask[row].iter().zip(&self.overlay[row]))
+            .map(Self::composite)
+    }
+
+    fn composite(((plane0, plane1), (mask, overlay)): ((&u8, &u8), (&u8, &u8))) -> u8 {
+        (plane0 | plane1) & !mask | overlay
+    }
+
+    /// Send a logical row to panel column `col`, scaling it on the fly as it
+    /// is written rather than rendering it into a temporary first. A mirrored
+    /// row is sent back to front, so it goes through `render_row` instead.
+    fn write_row(&mut self, row: usize, col: u8) -> Result {
+        self.set_col(col)?;
+
+        if self.orientation.mirrored() {
+            let (_, draw) = self.render_row(row);
+            self.sent = self.sent.wrapping_add(PANEL_BYTES as u32);
+            return self.bus.data_iter(draw.into_iter());
+        }
+
+        let fill = self.background();
+        let scans = self.buf[row]
+            .iter()
+            .zip(&self.plane1[row])
+            .zip(self.overlay_mask[row].iter().zip(&self.overlay[row]))
+            .map(Self::composite);
+        let result = if self.doubled() {
+            let draw = scans.take(PANEL_BYTES / 2).flat_map(Self::scale);
+            self.bus.data_iter(draw.map(|byte| byte ^ fill))
+        } else {
+            let margin = (PANEL_BYTES - COLS) / 2;
+            let draw = repeat(0)
+                .take(margin)
+                .chain(scans.map(u8::reverse_bits))
+                .chain(repeat(0))
+                .take(PANEL_BYTES);
+            self.bus.data_iter(draw.map(|byte| byte ^ fill))
+        };
+
+        self.sent = self.sent.wrapping_add(PANEL_BYTES as u32);
+        result
+    }
+
+    fn draw_row(&mut self, row: usize) -> Result {
+        let col = self.row_col(row);
+
+        self.write_row(row, col)?;
+
+        if self.doubled() {
+            self.write_row(row, col + 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Set or clear a single logical pixel, bypassing the XOR logic.
+    pub fn set_pixel(&mut self, x: u8, y: u8, on: bool) -> Result {
+        if x >= self.width() || y >= self.height() {
+            return Err(Error::OutOfBounds);
+        }
+
+        self.put_pixel(x, y, on);
+
+        self.present()?;
+
+        Ok(())
+    }
+
+    /// Set or clear every pixel in a rectangle. Unlike `xor`, the rectangle is
+    /// clamped to the edges of the display rather than wrapped.
+    pub fn fill_rect(&mut self, x: u8, y: u8, w: u8, h: u8, on: bool) -> Result {
+        let xend = x.saturating_add(w).min(self.width());
+        let yend = y.saturating_add(h).min(self.height());
+
+        for ypos in y..yend {
+            for xpos in x..xend {
+                self.put_pixel(xpos, ypos, on);
+            }
+        }
+
+        self.present()?;
+
+        Ok(())
+    }
+
+    /// Flush the buffer, unless drawing is deferred.
+    pub(super) fn present(&mut self) -> Result {
+        if self.deferred {
+            Ok(())
+        } else {
+            self.flush()
+        }
+    }
+
+    pub(super) fn put_pixel(&mut self, x: u8, y: u8, on: bool) {
+        let (xidx, yidx) = ((x / 8) as usize, y as usize);
+        let mask = 0x80 >> (x % 8);
+
+        if on {
+            self.buf[yidx][xidx] |= mask;
+        } else {
+            self.buf[yidx][xidx] &= !mask;
+        }
+
+        self.dirty |= 1 << yidx;
+    }
+
+    /// Move the display contents down by `n` pixels, clearing the rows
+    /// uncovered at the top. Scrolling works on the buffer, where row 0 is
+    /// the top of the screen, so the direction is unaffected by the
+    /// orientation.
+    pub fn scroll_down(&mut self, n: u8) -> Result {
+        let rows = self.height() as usize;
+        let n = (n as usize).min(rows);
+
+        self.for_each_plane(|buf| {
+            buf.copy_within(..rows - n, n);
+            buf[..n].fill([0; COLS]);
+        });
+        self.scrolled()
+    }
+
+    /// Move the display contents up by `n` pixels, clearing the rows
+    /// uncovered at the bottom.
+    pub fn scroll_up(&mut self, n: u8) -> Result {
+        let rows = self.height() as usize;
+        let n = (n as usize).min(rows);
+
+        self.for_each_plane(|buf| {
+            buf.copy_within(n..rows, 0);
+            buf[rows - n..rows].fill([0; COLS]);
+        });
+        self.scrolled()
+    }
+
+    /// Move the display contents left by `n` pixels, clearing the columns
+    /// uncovered at the right.
+    pub fn scroll_left(&mut self, n: u8) -> Result {
+        let (cols, rows) = ((self.width() / 8) as usize, self.height() as usize);
+        let (bytes, bits) = ((n / 8) as usize, n % 8);
+
+        self.for_each_plane(|buf| {
+            for row in &mut buf[..rows] {
+                let row = &mut row[..cols];
+
+                for i in 0..cols {
+                    let byte = |i: usize| row.get(i + bytes).copied().unwrap_or(0);
+                    let (high, low) = (byte(i), byte(i + 1));
+
+                    row[i] = match bits {
+                        0 => high,
+                        _ => high << bits | low >> (8 - bits),
+                    };
+                }
+            }
+        });
+
+        self.scrolled()
+    }
+
+    /// Move the display contents right by `n` pixels, clearing the columns
+    /// uncovered at the left.
+    pub fn scroll_right(&mut self, n: u8) -> Result {
+        let (cols, rows) = ((self.width() / 8) as usize, self.height() as usize);
+        let (bytes, bits) = ((n / 8) as usize, n % 8);
+
+        self.for_each_plane(|buf| {
+            for row in &mut buf[..rows] {
+                let row = &mut row[..cols];
+
+                for i in (0..cols).rev() {
+                    let byte = |n: usize| i.checked_sub(n).map_or(0, |i| row[i]);
+                    let (high, low) = (byte(bytes), byte(bytes + 1));
+
+                    row[i] = match bits {
+                        0 => high,
+                        _ => high >> bits | low << (8 - bits),
+                    };
+                }
+            }
+        });
+
+        self.scrolled()
+    }
+
+    fn scrolled(&mut self) -> Result {
+        self.dirty |= u64::MAX >> (64 - self.height());
+        self.present()
+    }
+
+    /// Fill the whole display with a pattern and transmit it immediately.
+    pub fn test_pattern(&mut self, pattern: TestPattern) -> Result {
+        for (y, row) in self.buf.iter_mut().enumerate() {
+            let even = y % 2 == 0;
+
+            let fill = match pattern {
+                TestPattern::AllOn => 0xFF,
+                TestPattern::AllOff => 0x00,
+                TestPattern::Checkerboard if even => 0xAA,
+                TestPattern::Checkerboard => 0x55,
+                TestPattern::VerticalStripes => 0xAA,
+                TestPattern::HorizontalStripes if even => 0xFF,
+                TestPattern::HorizontalStripes => 0x00,
+            };
+
+            *row = [fill; COLS];
+            self.dirty |= 1 << y;
+        }
+
+        self.flush()
+    }
+
+    /// As `Screen::xor`, but reporting which sprite rows collided rather than
+    /// whether any did, with bit `n` set when row `n` erased a pixel. SUPER-CHIP
+    /// sets VF to the number of such rows in 128x64 mode.
+    pub fn xor_rows(&mut self, x: u8, y: u8, data: &[u8]) -> Result<u16> {
+        if data.len() > MAX_SPRITE_HEIGHT {
+            return Err(Error::SpriteTooTall);
+        }
+
+        let (width, height) = (self.width(), self.height());
+        let (cols, rows) = ((width / 8) as usize, height as usize);
+        let (x, y) = (x % width, y % height);
+        let (xidx, offset) = ((x / 8) as usize, x % 8);
+        let clip = self.clip_sprites;
+        let (mut rows_collided, mut dirty) = (0, 0);
+
+        self.for_each_plane(|buf| {
+            for (row, (scan, ypos)) in data.iter().copied().zip(y as usize..).enumerate() {
+                if clip && ypos >= rows {
+                    break;
+                }
+
+                let yidx = ypos % rows;
+                let mut collided = 0;
+
+                if offset == 0 {
+                    collided |= buf[yidx][xidx] & scan;
+                    buf[yidx][xidx] ^= scan;
+                } else {
+                    let (left, right) = (scan >> offset, scan << (8 - offset));
+                    let xnext = xidx + 1;
+
+                    collided |= buf[yidx][xidx] & left;
+                    buf[yidx][xidx] ^= left;
+
+                    if xnext < cols || !clip {
+                        let xnext = xnext % cols;
+                        collided |= buf[yidx][xnext] & right;
+                        buf[yidx][xnext] ^= right;
+                    }
+                }
+
+                dirty |= 1 << yidx;
+
+                if collided != 0 {
+                    rows_collided |= 1 << row;
+                }
+            }
+        });
+
+        self.dirty |= dirty;
+        self.present()?;
+
+        Ok(rows_collided)
+    }
+
+    /// Transmit every row modified since the last flush.
+    pub fn flush(&mut self) -> Result {
+        if self.dirty != 0 {
+            self.flushes = self.flushes.wrapping_add(1);
+        }
+
+        if self.diff_flush {
+            return self.flush_changed();
+        }
+
+        // Rows sent from here don't go through the shadow.
+        self.shadow_valid &= self.dirty == 0;
+
+        for row in 0..self.height() as usize {
+            if self.dirty & (1 << row) != 0 {
+                self.draw_row(row)?;
+                self.dirty &= !(1 << row);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Transmit the logical rows from `y` to `y + h`, clamped to the display,
+    /// whether or not they have changed, and mark them as sent. Each row
+    /// fills whole panel columns, so a region is always the full width.
+    /// Consecutive rows are always in consecutive panel columns, whichever
+    /// way up the panel is, so the region is a single run of them, sent with
+    /// one data write.
+    pub fn flush_region(&mut self, y: u8, h: u8) -> Result {
+        let yend = y.saturating_add(h).min(self.height());
+
+        if y >= yend {
+            return Ok(());
+        }
+
+        let count = if self.doubled() { 2 } else { 1 };
+
+        // Rows go through the shadow, so it stays valid for `flush_changed`.
+        for row in y as usize..yend as usize {
+            let (col, draw) = self.render_row(row);
+
+            for col in col as usize..col as usize + count {
+                self.shadow[col] = draw;
+            }
+
+            self.dirty &= !(1 << row);
+        }
+
+        self.flushes = self.flushes.wrapping_add(1);
+
+        let first = self
+            .row_col(y as usize)
+            .min(self.row_col(yend as usize - 1));
+        self.write_cols(first, (yend - y) as usize * count)
+    }
+
+    /// `flush` with diffing, as described on `set_diff_flush`. Until the
+    /// shadow is known to match the panel, everything is treated as changed.
+    fn flush_changed(&mut self) -> Result {
+        if !self.shadow_valid {
+            self.dirty = u64::MAX >> (64 - self.height());
+        }
+
+        let count = if self.doubled() { 2 } else { 1 };
+        let mut changed = 0u64;
+
+        for row in 0..self.height() as usize {
+            if self.dirty & (1 << row) == 0 {
+                continue;
+            }
+
+            let (col, draw) = self.render_row(row);
+
+            for col in col as usize..col as usize + count {
+                if !self.shadow_valid || self.shadow[col] != draw {
+                    self.shadow[col] = draw;
+                    changed |= 1 << col;
+                }
+            }
+        }
+
+        self.dirty = 0;
+        self.shadow_valid = true;
+
+        let mut col = 0;
+
+        while let Some(rest) = changed.checked_shr(col).filter(|&rest| rest != 0) {
+            let start = col + rest.trailing_zeros();
+            let len = (!(changed >> start)).trailing_zeros();
+
+            self.write_cols(start as u8, len as usize)?;
+            col = start + len;
+        }
+
+        Ok(())
+    }
+
+    /// Send `count` panel columns from the shadow, starting at `col`.
+    fn write_cols(&mut self, col: u8, count: usize) -> Result {
+        self.set_col(col)?;
+
+        let cols = &self.shadow[col as usize..col as usize + count];
+        self.bus.data_iter(cols.iter().flatten().copied())?;
+        self.sent = self.sent.wrapping_add((count * PANEL_BYTES) as u32);
+
+        Ok(())
+    }
+
+    /// The number of flushes which sent anything to the panel, wrapping on
+    /// overflow.
+    pub fn flush_count(&self) -> u32 {
+        self.flushes
+    }
 }
 
-impl<SPI, CS, MD, RS> Screen for Sh1106<SPI, CS, MD, RS>
+/// DMA feeds the SPI FIFO directly, so it is only available over SPI.
+#[cfg(feature = "dma")]
+impl<SPI, CS, MD, RS, const COLS: usize, const ROWS: usize>
+    Sh1106Display<SpiBus<SPI, CS, MD>, RS, COLS, ROWS>
 where
     SPI: Write<u8> + WriteIter<u8>,
     CS: OutputPin,
     MD: OutputPin,
     RS: OutputPin,
 {
-    type Error = Error;
+    /// Render the whole frame and hand it to the DMA channel, returning as
+    /// soon as the transfer has started. The frame is sent as a single data
+    /// write, relying on the column address incrementing in vertical
+    /// addressing mode. Nothing is sent when no row has changed since the
+    /// last flush. Call `wait_flush` before sending anything else.
+    pub fn flush_dma<D: FrameDma>(&mut self, dma: &mut D) -> Result {
+        self.wait_flush(dma)?;
 
-    fn xor(&mut self, x: u8, y: u8, data: &[u8]) -> Result<bool> {
-        let offset = x % 8;
+        if self.dirty == 0 && self.shadow_valid {
+            return Ok(());
+        }
 
-        for (scan, ypos) in data.iter().copied().zip(y..) {
-            let yidx = ypos as usize;
+        let count = if self.doubled() { 2 } else { 1 };
+        let fill = self.background();
+        let frame = dma.frame();
+        frame.fill(fill);
 
-            // Screen orientation: Highest index is top of screen
-            let ypos = 2 * (31 - (ypos % 32));
-            let xidx = ((x % 64) / 8) as usize;
+        for row in 0..self.height() as usize {
+            let (col, draw) = self.render_row(row);
 
-            if offset == 0 {
-                self.buf[yidx][xidx] ^= scan;
-            } else {
-                self.buf[yidx][xidx] ^= scan >> offset;
-                self.buf[yidx][(xidx + 1) % 8] ^= scan << (8 - offset);
+            for col in col..col + count {
+                let start = col as usize * PANEL_BYTES;
+                frame[start..start + PANEL_BYTES].copy_from_slice(&draw);
             }
+        }
 
-            let draw = unsafe {
-                let scaled = self.buf[yidx].map(Self::scale);
-                core::mem::transmute::<[[u8; 2]; 8], [u8; 16]>(scaled)
-            };
+        for (shadow, col) in self.shadow.iter_mut().zip(frame.chunks_exact(PANEL_BYTES)) {
+            shadow.copy_from_slice(col);
+        }
+
+        self.shadow_valid = true;
+        self.dirty = 0;
+        self.flushes = self.flushes.wrapping_add(1);
+        self.sent = self.sent.wrapping_add(FRAME_BYTES as u32);
+        self.set_col(0)?;
+        self.bus.select(true)?;
 
-            self.set_col(ypos)?;
-            self.data(&draw)?;
-            self.set_col(ypos + 1)?;
-            self.data(&draw)?;
+        dma.start();
+        self.flushing = true;
+
+        Ok(())
+    }
+
+    /// Block until a frame started by `flush_dma` has been sent.
+    pub fn wait_flush<D: FrameDma>(&mut self, dma: &mut D) -> Result {
+        if self.flushing {
+            dma.wait();
+            self.flushing = false;
+            self.bus.deselect()?;
         }
 
-        // @TODO: Determine if bits have been erased
-        Ok(true)
+        Ok(())
     }
+}
 
+impl<BUS, RS, const COLS: usize, const ROWS: usize> Screen for Sh1106Display<BUS, RS, COLS, ROWS>
+where
+    BUS: DisplayBus,
+    RS: OutputPin,
+{
+    type Error = Error;
+
+    fn xor(&mut self, x: u8, y: u8, data: &[u8]) -> Result<bool> {
+        self.xor_rows(x, y, data).map(|rows| rows != 0)
+    }
+
+    /// The whole panel is blanked with a single data write, relying on the
+    /// column address incrementing in vertical addressing mode. That is three
+    /// bus transactions (two to set the column, one of data) where writing
+    /// each column separately took 192. With `set_inverted_render` the panel
+    /// is lit rather than blanked.
+    ///
+    /// Only the planes in the plane mask are cleared. When another plane still
+    /// has something on it, or there is an overlay, the remaining image is
+    /// redrawn instead. While drawing is deferred nothing is sent, and every
+    /// row is left dirty for the next `flush`.
     fn clear(&mut self) -> Result {
-        for col in 0..64 {
-            self.set_col(col)?;
-            self.data(&[0; 16])?;
+        let keep_plane1 =
+            self.plane_mask & PLANE_1 == 0 && self.plane1.iter().flatten().any(|&b| b != 0);
+
+        if self.deferred || self.plane_mask & PLANE_0 == 0 || keep_plane1 || self.overlay_rows != 0
+        {
+            self.for_each_plane(|buf| *buf = [[0; COLS]; ROWS]);
+            self.dirty = u64::MAX >> (64 - ROWS);
+            return self.present();
         }
 
-        self.buf = [[0; 8]; 32];
+        self.blank_panel()?;
+
+        self.buf = [[0; COLS]; ROWS];
+        self.plane1 = [[0; COLS]; ROWS];
+        self.dirty = 0;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::{
+        mock::tests::{frame, EDGE_SPRITES},
+        MockBus, MockPin,
+    };
+
+    type HiRes = Sh1106Display<MockBus, MockPin, 16, 64>;
+    type LoRes = Sh1106Display<MockBus, MockPin, 8, 32>;
+
+    fn screen<const COLS: usize, const ROWS: usize>() -> Sh1106Display<MockBus, MockPin, COLS, ROWS>
+    {
+        let mut screen = Sh1106Display::with_bus(MockBus::new(), MockPin);
+        screen.init().unwrap();
+        screen
+    }
+
+    #[test]
+    fn fill_rect_sets_bits_across_bytes() {
+        let mut screen: LoRes = screen();
+        screen.fill_rect(4, 1, 8, 2, true).unwrap();
+
+        for (y, row) in screen.buffer().iter().enumerate() {
+            let expected = match y {
+                1 | 2 => [0x0F, 0xF0, 0, 0, 0, 0, 0, 0],
+                _ => [0; 8],
+            };
+
+            assert_eq!(*row, expected, "row {}", y);
+        }
+    }
+
+    #[test]
+    fn fill_rect_clears_without_touching_neighbours() {
+        let mut screen: LoRes = screen();
+        screen.fill_rect(0, 0, 64, 32, true).unwrap();
+        screen.fill_rect(9, 3, 3, 1, false).unwrap();
+
+        assert_eq!(
+            screen.buffer()[3],
+            [0xFF, 0x8F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(screen.buffer()[2], [0xFF; 8]);
+        assert_eq!(screen.buffer()[4], [0xFF; 8]);
+    }
+
+    #[test]
+    fn fill_rect_clamps_at_right_and_bottom() {
+        let mut screen: LoRes = screen();
+        screen.fill_rect(60, 30, 10, 10, true).unwrap();
+
+        for (y, row) in screen.buffer().iter().enumerate() {
+            let expected = match y {
+                30 | 31 => [0, 0, 0, 0, 0, 0, 0, 0x0F],
+                _ => [0; 8],
+            };
+
+            assert_eq!(*row, expected, "row {}", y);
+        }
+    }
+
+    #[test]
+    fn fill_rect_clamps_overflowing_size() {
+        let mut screen: LoRes = screen();
+        screen.fill_rect(62, 0, 255, 1, true).unwrap();
+
+        assert_eq!(screen.buffer()[0], [0, 0, 0, 0, 0, 0, 0, 0x03]);
+    }
+
+    #[test]
+    fn fill_rect_outside_display_does_nothing() {
+        let mut screen: HiRes = screen();
+        screen.fill_rect(128, 0, 8, 8, true).unwrap();
+        screen.fill_rect(0, 64, 8, 8, true).unwrap();
+        screen.fill_rect(0, 0, 0, 8, true).unwrap();
+
+        assert!(screen.buffer().iter().flatten().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn xor_rejects_sprite_taller_than_16_rows() {
+        let mut screen: HiRes = screen();
+
+        assert_eq!(screen.xor(0, 0, &[0xFF; 17]), Err(Error::SpriteTooTall));
+        assert!(screen.buffer().iter().flatten().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn xor_draws_16_row_sprite() {
+        let mut screen: HiRes = screen();
+
+        assert_eq!(screen.xor(0, 0, &[0xFF; 16]), Ok(false));
+
+        for (y, row) in screen.buffer().iter().enumerate() {
+            assert_eq!(row[0], if y < 16 { 0xFF } else { 0 }, "row {}", y);
+        }
+    }
+
+    #[test]
+    fn overlay_is_sent_without_touching_the_framebuffer() {
+        let mut screen: HiRes = screen();
+        screen.overlay_str(0, 0, "8").unwrap();
+
+        assert!(screen.buffer().iter().flatten().all(|&byte| byte == 0));
+        assert!(screen.bus.ram().iter().flatten().any(|&byte| byte != 0));
+        assert_eq!(screen.xor(0, 0, &[0xFF; 7]), Ok(false));
+
+        screen.xor(0, 0, &[0xFF; 7]).unwrap();
+        screen.clear_overlay().unwrap();
+
+        assert!(screen.bus.ram().iter().flatten().all(|&byte| byte == 0));
+    }
+
+    fn draw_edge_sprites(clip: bool) {
+        for sprite in &EDGE_SPRITES {
+            let mut screen: LoRes = screen();
+            screen.set_clip_sprites(clip);
+
+            let data = [0xFF; MAX_SPRITE_HEIGHT];
+            let data = &data[..sprite.height];
+            let expected = if clip { sprite.clipped } else { sprite.wrapped };
+
+            assert_eq!(screen.xor(sprite.x, sprite.y, data), Ok(false));
+            assert_eq!(
+                screen.buffer().map(u64::from_be_bytes),
+                frame(expected),
+                "at ({}, {})",
+                sprite.x,
+                sprite.y
+            );
+            assert_eq!(screen.xor(sprite.x, sprite.y, data), Ok(true));
+            assert!(screen.buffer().iter().flatten().all(|&byte| byte == 0));
+        }
+    }
+
+    #[test]
+    fn sprites_are_clipped_at_the_edges() {
+        draw_edge_sprites(true);
+    }
+
+    #[test]
+    fn sprites_wrap_when_not_clipped() {
+        draw_edge_sprites(false);
+    }
+
+    /// Light the pixel at (10, 3) on a panel mounted as `orientation` and
+    /// check it is the only one lit in display RAM, at `byte` of panel
+    /// column `col` as `mask`.
+    fn assert_pixel_lands(orientation: Orientation, col: usize, byte: usize, mask: u8) {
+        let mut screen: HiRes = screen();
+        screen.set_orientation(orientation).unwrap();
+        screen.set_pixel(10, 3, true).unwrap();
+
+        for (c, column) in screen.bus.ram().iter().enumerate() {
+            for (b, &bits) in column.iter().enumerate() {
+                let expected = if (c, b) == (col, byte) { mask } else { 0 };
+                assert_eq!(bits, expected, "column {} byte {}", c, b);
+            }
+        }
+    }
+
+    #[test]
+    fn normal_pixel_lands_on_panel() {
+        assert_pixel_lands(Orientation::Normal, 60, 1, 0x04);
+    }
+
+    #[test]
+    fn rotate180_pixel_lands_on_panel() {
+        assert_pixel_lands(Orientation::Rotate180, 3, 14, 0x20);
+    }
+
+    #[test]
+    fn flip_h_pixel_lands_on_panel() {
+        assert_pixel_lands(Orientation::FlipH, 60, 14, 0x20);
+    }
+
+    #[test]
+    fn flip_v_pixel_lands_on_panel() {
+        assert_pixel_lands(Orientation::FlipV, 3, 1, 0x04);
+    }
+
+    #[test]
+    fn init_verify_keeps_the_given_config() {
+        let mut screen: HiRes = Sh1106Display::with_bus(MockBus::new(), MockPin);
+        let cfg = Sh1106Config {
+            contrast: 0x20,
+            display_offset: 2,
+            ..Sh1106Config::default()
+        };
+
+        screen.init_verify(&cfg, || 0).unwrap();
+        assert_eq!(screen.config.contrast, 0x20);
+        assert_eq!(screen.config.display_offset, 2);
+
+        screen.recover().unwrap();
+        assert_eq!(screen.config.contrast, 0x20);
+        assert_eq!(screen.config.display_offset, 2);
+    }
+
+    #[test]
+    fn recover_redraws_the_overlay() {
+        let mut screen: HiRes = screen();
+        screen.xor(8, 10, &[0xFF; 5]).unwrap();
+        screen.overlay_str(0, 0, "AB").unwrap();
+        let ram = *screen.bus.ram();
+
+        screen.set_deferred(true);
+        screen.recover().unwrap();
+
+        assert_eq!(screen.bus.ram(), &ram);
+        assert_eq!(screen.dirty, 0);
+    }
+
+    #[test]
+    fn init_verify_border_is_in_the_overlay() {
+        let mut screen: HiRes = Sh1106Display::with_bus(MockBus::new(), MockPin);
+        screen.init_verify(&Sh1106Config::default(), || 0).unwrap();
+
+        assert!(screen.buffer().iter().flatten().all(|&b| b == 0));
+        assert!(screen.bus.ram().iter().flatten().any(|&b| b != 0));
+
+        screen.clear_overlay().unwrap();
+        assert!(screen.bus.ram().iter().flatten().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn init_verify_survives_a_clock_going_backwards() {
+        let mut screen: HiRes = Sh1106Display::with_bus(MockBus::new(), MockPin);
+        let mut now = 1_000_000;
+
+        let init_us = screen
+            .init_verify(&Sh1106Config::default(), || {
+                now -= 1;
+                now
+            })
+            .unwrap();
+
+        assert_eq!(init_us, 0);
+    }
+
+    /// The panel columns written by `flush_region(y, h)`.
+    fn region_cols<const COLS: usize, const ROWS: usize>(y: u8, h: u8) -> u64 {
+        let mut screen: Sh1106Display<MockBus, MockPin, COLS, ROWS> = screen();
+        screen.bus.reset_counts();
+        screen.flush_region(y, h).unwrap();
+        screen.bus.written()
+    }
+
+    #[test]
+    fn flush_region_writes_only_its_rows() {
+        // The top row is the highest panel column.
+        assert_eq!(region_cols::<16, 64>(10, 3), 0b111 << 51);
+        assert_eq!(region_cols::<16, 64>(0, 1), 1 << 63);
+    }
+
+    #[test]
+    fn flush_region_writes_two_columns_per_doubled_row() {
+        assert_eq!(region_cols::<8, 32>(0, 2), 0b1111 << 60);
+    }
+
+    #[test]
+    fn flush_region_clamps_to_the_display() {
+        assert_eq!(region_cols::<16, 64>(60, 255), 0b1111);
+        assert_eq!(region_cols::<8, 32>(30, 5), 0b1111);
+    }
+
+    #[test]
+    fn flush_region_outside_the_display_writes_nothing() {
+        assert_eq!(region_cols::<16, 64>(64, 8), 0);
+        assert_eq!(region_cols::<16, 64>(0, 0), 0);
+    }
+
+    /// Data bytes sent for a frame which moves a block down one row and
+    /// redraws a score in the same place, the usual erase-and-redraw of a
+    /// CHIP-8 game.
+    fn frame_bytes_sent(diff: bool) -> usize {
+        let mut screen: HiRes = screen();
+        screen.set_diff_flush(diff);
+        screen.set_deferred(true);
+        screen.xor(8, 10, &[0xFF; 5]).unwrap();
+        screen.xor(64, 40, &[0xA5; 5]).unwrap();
+        screen.flush().unwrap();
+        screen.bus.reset_counts();
+
+        screen.xor(8, 10, &[0xFF; 5]).unwrap();
+        screen.xor(8, 11, &[0xFF; 5]).unwrap();
+        screen.xor(64, 40, &[0xA5; 5]).unwrap();
+        screen.xor(64, 40, &[0xA5; 5]).unwrap();
+        screen.flush().unwrap();
+        screen.bus.data_bytes()
+    }
+
+    #[test]
+    fn diff_flush_sends_only_changed_columns() {
+        assert_eq!(frame_bytes_sent(false), 11 * PANEL_BYTES);
+        assert_eq!(frame_bytes_sent(true), 2 * PANEL_BYTES);
+    }
+
+    #[test]
+    fn deferred_clear_waits_for_flush() {
+        let mut screen: HiRes = screen();
+        screen.xor(8, 10, &[0xFF; 5]).unwrap();
+        screen.set_deferred(true);
+        screen.bus.reset_counts();
+
+        screen.clear().unwrap();
+        assert_eq!(screen.bus.data_bytes(), 0);
+
+        screen.flush().unwrap();
+        assert!(screen.bus.ram().iter().flatten().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn scroll_start_by_wraps_within_the_panel() {
+        let mut screen: HiRes = screen();
+
+        screen.scroll_start_by(-1).unwrap();
+        assert_eq!(screen.display_start(), 63);
+
+        screen.scroll_start_by(1).unwrap();
+        assert_eq!(screen.display_start(), 0);
+
+        screen.set_display_start(63).unwrap();
+        screen.scroll_start_by(1).unwrap();
+        assert_eq!(screen.display_start(), 0);
+
+        screen.scroll_start_by(i8::MIN).unwrap();
+        assert_eq!(screen.display_start(), 0);
+
+        screen.scroll_start_by(i8::MAX).unwrap();
+        assert_eq!(screen.display_start(), 63);
+
+        for delta in i8::MIN..=i8::MAX {
+            screen.scroll_start_by(delta).unwrap();
+            assert!(screen.display_start() < 64, "delta {}", delta);
+        }
+    }
+
+    #[test]
+    fn scroll_down_moves_rows_and_clears_top() {
+        let mut screen: HiRes = screen();
+        screen.buffer_mut()[0][0] = 0x80;
+        screen.buffer_mut()[61][15] = 0x01;
+        screen.scroll_down(2).unwrap();
+
+        assert_eq!(screen.buffer()[0], [0; 16]);
+        assert_eq!(screen.buffer()[1], [0; 16]);
+        assert_eq!(screen.buffer()[2][0], 0x80);
+        assert_eq!(screen.buffer()[63][15], 0x01);
+        assert_eq!(
+            screen
+                .buffer()
+                .iter()
+                .flatten()
+                .filter(|&&b| b != 0)
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn scroll_up_moves_rows_and_clears_bottom() {
+        let mut screen: HiRes = screen();
+        screen.buffer_mut()[3][2] = 0x18;
+        screen.buffer_mut()[63][0] = 0xFF;
+        screen.scroll_up(3).unwrap();
+
+        assert_eq!(screen.buffer()[0][2], 0x18);
+        assert_eq!(screen.buffer()[60][0], 0xFF);
+        assert_eq!(screen.buffer()[61..], [[0; 16]; 3]);
+        assert_eq!(
+            screen
+                .buffer()
+                .iter()
+                .flatten()
+                .filter(|&&b| b != 0)
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn scroll_left_shifts_across_bytes() {
+        let mut screen: HiRes = screen();
+        screen.buffer_mut()[5][1] = 0x81;
+        screen.buffer_mut()[6][15] = 0xFF;
+        screen.scroll_left(4).unwrap();
+
+        let mut row5 = [0; 16];
+        row5[..2].copy_from_slice(&[0x08, 0x10]);
+        let mut row6 = [0; 16];
+        row6[14..].copy_from_slice(&[0x0F, 0xF0]);
+
+        assert_eq!(screen.buffer()[5], row5);
+        assert_eq!(screen.buffer()[6], row6);
+    }
+
+    #[test]
+    fn scroll_left_by_more_than_a_byte() {
+        let mut screen: HiRes = screen();
+        screen.buffer_mut()[0][1] = 0x81;
+        screen.scroll_left(12).unwrap();
+
+        let mut row = [0; 16];
+        row[0] = 0x10;
+
+        assert_eq!(screen.buffer()[0], row);
+    }
+
+    #[test]
+    fn scroll_right_shifts_across_bytes_and_clears_left() {
+        let mut screen: HiRes = screen();
+        screen.buffer_mut()[5][0] = 0x81;
+        screen.buffer_mut()[6][15] = 0x0F;
+        screen.scroll_right(4).unwrap();
+
+        let mut row5 = [0; 16];
+        row5[..2].copy_from_slice(&[0x08, 0x10]);
+
+        assert_eq!(screen.buffer()[5], row5);
+        assert_eq!(screen.buffer()[6], [0; 16]);
+    }
+
+    #[test]
+    fn scroll_in_lores_stays_in_the_top_left_quarter() {
+        let mut screen: HiRes = screen();
+        screen.set_hires(false).unwrap();
+        screen.buffer_mut()[0][7] = 0x01;
+        screen.buffer_mut()[31][0] = 0x80;
+        screen.scroll_right(1).unwrap();
+        screen.scroll_down(1).unwrap();
+
+        assert!(screen.buffer().iter().flatten().all(|&byte| byte == 0));
+    }
+}
@@ -0,0 +1,236 @@
+use super::{DisplayBus, Error, Sh1106Display};
+use embedded_hal::digital::v2::OutputPin;
+
+type Result<T = ()> = core::result::Result<T, Error>;
+
+pub const GLYPH_WIDTH: u8 = 3;
+pub const GLYPH_HEIGHT: u8 = 5;
+
+/// Horizontal distance between the start of each character.
+pub const CHAR_ADVANCE: u8 = GLYPH_WIDTH + 1;
+
+/// 3x5 glyphs for ASCII 0x20 to 0x5F. Each row is three bits, with bit 2
+/// leftmost. Lowercase letters are drawn as uppercase.
+const FONT: [[u8; 5]; 64] = [
+    [0b000, 0b000, 0b000, 0b000, 0b000], //
+    [0b010, 0b010, 0b010, 0b000, 0b010], // !
+    [0b101, 0b101, 0b000, 0b000, 0b000], // "
+    [0b101, 0b111, 0b101, 0b111, 0b101], // #
+    [0b011, 0b110, 0b010, 0b011, 0b110], // $
+    [0b101, 0b001, 0b010, 0b100, 0b101], // %
+    [0b010, 0b101, 0b010, 0b101, 0b011], // &
+    [0b010, 0b010, 0b000, 0b000, 0b000], // '
+    [0b001, 0b010, 0b010, 0b010, 0b001], // (
+    [0b100, 0b010, 0b010, 0b010, 0b100], // )
+    [0b000, 0b101, 0b010, 0b101, 0b000], // *
+    [0b000, 0b010, 0b111, 0b010, 0b000], // +
+    [0b000, 0b000, 0b000, 0b010, 0b100], // ,
+    [0b000, 0b000, 0b111, 0b000, 0b000], // -
+    [0b000, 0b000, 0b000, 0b000, 0b010], // .
+    [0b001, 0b001, 0b010, 0b100, 0b100], // /
+    [0b111, 0b101, 0b101, 0b101, 0b111], // 0
+    [0b010, 0b110, 0b010, 0b010, 0b111], // 1
+    [0b111, 0b001, 0b111, 0b100, 0b111], // 2
+    [0b111, 0b001, 0b111, 0b001, 0b111], // 3
+    [0b101, 0b101, 0b111, 0b001, 0b001], // 4
+    [0b111, 0b100, 0b111, 0b001, 0b111], // 5
+    [0b111, 0b100, 0b111, 0b101, 0b111], // 6
+    [0b111, 0b001, 0b001, 0b010, 0b010], // 7
+    [0b111, 0b101, 0b111, 0b101, 0b111], // 8
+    [0b111, 0b101, 0b111, 0b001, 0b111], // 9
+    [0b000, 0b010, 0b000, 0b010, 0b000], // :
+    [0b000, 0b010, 0b000, 0b010, 0b100], // ;
+    [0b001, 0b010, 0b100, 0b010, 0b001], // <
+    [0b000, 0b111, 0b000, 0b111, 0b000], // =
+    [0b100, 0b010, 0b001, 0b010, 0b100], // >
+    [0b111, 0b001, 0b010, 0b000, 0b010], // ?
+    [0b010, 0b101, 0b111, 0b100, 0b011], // @
+    [0b010, 0b101, 0b111, 0b101, 0b101], // A
+    [0b110, 0b101, 0b110, 0b101, 0b110], // B
+    [0b011, 0b100, 0b100, 0b100, 0b011], // C
+    [0b110, 0b101, 0b101, 0b101, 0b110], // D
+    [0b111, 0b100, 0b110, 0b100, 0b111], // E
+    [0b111, 0b100, 0b110, 0b100, 0b100], // F
+    [0b011, 0b100, 0b101, 0b101, 0b011], // G
+    [0b101, 0b101, 0b111, 0b101, 0b101], // H
+    [0b111, 0b010, 0b010, 0b010, 0b111], // I
+    [0b001, 0b001, 0b001, 0b101, 0b010], // J
+    [0b101, 0b101, 0b110, 0b101, 0b101], // K
+    [0b100, 0b100, 0b100, 0b100, 0b111], // L
+    [0b101, 0b111, 0b111, 0b101, 0b101], // M
+    [0b110, 0b101, 0b101, 0b101, 0b101], // N
+    [0b010, 0b101, 0b101, 0b101, 0b010], // O
+    [0b110, 0b101, 0b110, 0b100, 0b100], // P
+    [0b010, 0b101, 0b101, 0b110, 0b011], // Q
+    [0b110, 0b101, 0b110, 0b101, 0b101], // R
+    [0b011, 0b100, 0b010, 0b001, 0b110], // S
+    [0b111, 0b010, 0b010, 0b010, 0b010], // T
+    [0b101, 0b101, 0b101, 0b101, 0b111], // U
+    [0b101, 0b101, 0b101, 0b101, 0b010], // V
+    [0b101, 0b101, 0b111, 0b111, 0b101], // W
+    [0b101, 0b101, 0b010, 0b101, 0b101], // X
+    [0b101, 0b101, 0b010, 0b010, 0b010], // Y
+    [0b111, 0b001, 0b010, 0b100, 0b111], // Z
+    [0b011, 0b010, 0b010, 0b010, 0b011], // [
+    [0b100, 0b100, 0b010, 0b001, 0b001], // \
+    [0b110, 0b010, 0b010, 0b010, 0b110], // ]
+    [0b010, 0b101, 0b000, 0b000, 0b000], // ^
+    [0b000, 0b000, 0b000, 0b000, 0b111], // _
+];
+
+/// Drawn for any character which isn't in `FONT`.
+const PLACEHOLDER: [u8; 5] = [0b111, 0b111, 0b111, 0b111, 0b111];
+
+impl<BUS, RS, const COLS: usize, const ROWS: usize> Sh1106Display<BUS, RS, COLS, ROWS>
+where
+    BUS: DisplayBus,
+    RS: OutputPin,
+{
+    /// Draw lit text with its top-left corner at `x`, `y`.
+    pub fn draw_str(&mut self, x: u8, y: u8, s: &str) -> Result {
+        self.draw_text(x, y, s, true)
+    }
+
+    /// Draw lit text at twice the usual size, for headings.
+    pub fn draw_heading(&mut self, x: u8, y: u8, s: &str) -> Result {
+        self.draw_text_scaled(x, y, s, true, 2)
+    }
+
+    /// Draw text by setting (or, with `on` false, clearing) its pixels. The
+    /// background is left alone and text is clipped at the display edges.
+    /// Characters outside the font are drawn as `PLACEHOLDER`.
+    pub fn draw_text(&mut self, x: u8, y: u8, s: &str, on: bool) -> Result {
+        self.draw_text_scaled(x, y, s, on, 1)
+    }
+
+    /// As `draw_text`, with each font pixel drawn as a `scale` by `scale`
+    /// block and characters spaced to match.
+    pub fn draw_text_scaled(&mut self, x: u8, y: u8, s: &str, on: bool, scale: u8) -> Result {
+        let (width, height) = (self.width() as usize, self.height() as usize);
+
+        for (px, py) in Self::text_pixels(x, y, s, scale) {
+            if px < width && py < height {
+                self.put_pixel(px as u8, py as u8, on);
+            }
+        }
+
+        self.present()
+    }
+
+    /// Draw lit text on a blank box one pixel larger all round, with the
+    /// box's top-left corner at `x`, `y`, for a readout such as the perf
+    /// counter. It goes in the overlay, which is composited as rows are sent,
+    /// so the planes a ROM draws into are left alone and it can't cause
+    /// collisions. Anything already in the overlay on the rows it covers is
+    /// replaced.
+    pub fn overlay_str(&mut self, x: u8, y: u8, s: &str) -> Result {
+        let width = s
+            .chars()
+            .count()
+            .saturating_mul(CHAR_ADVANCE as usize)
+            .saturating_add(1);
+        let xend = (x as usize)
+            .saturating_add(width)
+            .min(self.width() as usize) as u8;
+        let yend = y.saturating_add(GLYPH_HEIGHT + 2).min(self.height());
+
+        self.clear_overlay_rows(y, yend);
+
+        for py in y..yend {
+            for px in x..xend {
+                self.put_overlay_pixel(px, py, false);
+            }
+        }
+
+        for (px, py) in Self::text_pixels(x.saturating_add(1), y.saturating_add(1), s, 1) {
+            if px < xend as usize && py < yend as usize {
+                self.put_overlay_pixel(px as u8, py as u8, true);
+            }
+        }
+
+        self.present()
+    }
+
+    fn glyph(c: char) -> &'static [u8; 5] {
+        let c = c.to_ascii_uppercase() as usize;
+
+        c.checked_sub(0x20)
+            .and_then(|index| FONT.get(index))
+            .unwrap_or(&PLACEHOLDER)
+    }
+
+    /// The pixels lit by `s` drawn at `x`, `y`. These may be well off the
+    /// display, so are counted in `usize` and left to the caller to clip.
+    fn text_pixels(x: u8, y: u8, s: &str, scale: u8) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let advance = (CHAR_ADVANCE as usize).saturating_mul(scale as usize);
+
+        s.chars().enumerate().flat_map(move |(i, c)| {
+            let cx = (x as usize).saturating_add(i.saturating_mul(advance));
+            Self::glyph_pixels(cx, y as usize, Self::glyph(c), scale as usize)
+        })
+    }
+
+    fn glyph_pixels(
+        x: usize,
+        y: usize,
+        glyph: &'static [u8; 5],
+        scale: usize,
+    ) -> impl Iterator<Item = (usize, usize)> {
+        glyph.iter().enumerate().flat_map(move |(gy, &bits)| {
+            (0..GLYPH_WIDTH as usize)
+                .filter(move |gx| bits & (0b100 >> gx) != 0)
+                .flat_map(move |gx| {
+                    (0..scale).flat_map(move |dy| {
+                        (0..scale).map(move |dx| {
+                            let px = x.saturating_add(gx.saturating_mul(scale).saturating_add(dx));
+                            let py = y.saturating_add(gy.saturating_mul(scale).saturating_add(dy));
+                            (px, py)
+                        })
+                    })
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screen::{MockBus, MockPin};
+
+    type HiRes = Sh1106Display<MockBus, MockPin, 16, 64>;
+
+    fn screen() -> HiRes {
+        let mut screen = Sh1106Display::with_bus(MockBus::new(), MockPin);
+        screen.init().unwrap();
+        screen
+    }
+
+    #[test]
+    fn draw_str_clips_strings_longer_than_256_chars() {
+        let mut screen = screen();
+        let mut text = [b'8'; 300];
+        text[299] = b'!';
+        screen
+            .draw_str(0, 0, core::str::from_utf8(&text).unwrap())
+            .unwrap();
+
+        assert_eq!(screen.buffer()[0], [0xEE; 16]);
+        assert_eq!(screen.buffer()[GLYPH_HEIGHT as usize], [0; 16]);
+    }
+
+    #[test]
+    fn draw_text_scaled_survives_the_largest_scale() {
+        let mut screen = screen();
+        screen.draw_text_scaled(0, 0, "8", true, u8::MAX).unwrap();
+
+        assert!(screen.buffer().iter().flatten().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn overlay_str_clips_at_the_right_edge() {
+        let mut screen = screen();
+        screen.overlay_str(120, 60, "ABCDEFGHIJ").unwrap();
+
+        assert!(screen.buffer().iter().flatten().all(|&byte| byte == 0));
+    }
+}
@@ -0,0 +1,186 @@
+//! ROMs and cartridges loaded from a FAT formatted microSD card.
+//!
+//! The SH1106 owns SPI1, so the card reader goes on SPI0 using the Pico's
+//! default SPI0 pins: GPIO16 (MISO), GPIO17 (CS), GPIO18 (SCK) and GPIO19
+//! (MOSI). None of these are used by the screen, keypad or buzzer.
+
+use embedded_hal::{digital::v2::OutputPin, spi::FullDuplex};
+use embedded_sdmmc::{
+    Controller, Mode, SdMmcError, SdMmcSpi, TimeSource, Timestamp, Volume, VolumeIdx,
+};
+
+/// The most ROMs `list_roms` will return, any more are ignored.
+pub const MAX_ROMS: usize = 256;
+
+/// An 8.3 file name plus the dot.
+const NAME_LEN: usize = 12;
+
+type Result<T = ()> = core::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Card(embedded_sdmmc::Error<SdMmcError>),
+    RomTooLarge,
+}
+
+impl From<embedded_sdmmc::Error<SdMmcError>> for Error {
+    fn from(err: embedded_sdmmc::Error<SdMmcError>) -> Self {
+        Error::Card(err)
+    }
+}
+
+/// There is no RTC, and files are only ever read.
+struct NoClock;
+
+impl TimeSource for NoClock {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp {
+            year_since_1970: 0,
+            zero_indexed_month: 0,
+            zero_indexed_day: 0,
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+        }
+    }
+}
+
+/// The file name of a ROM on the card, such as `PONG.CH8`.
+#[derive(Clone, Copy)]
+pub struct RomName {
+    bytes: [u8; NAME_LEN],
+    len: u8,
+}
+
+impl RomName {
+    const EMPTY: Self = Self {
+        bytes: [0; NAME_LEN],
+        len: 0,
+    };
+
+    fn new(base: &[u8], ext: &[u8]) -> Self {
+        let mut name = Self::EMPTY;
+
+        for &byte in base.iter().chain(b".").chain(ext) {
+            name.bytes[name.len as usize] = byte;
+            name.len += 1;
+        }
+
+        name
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or("")
+    }
+
+    /// Whether this is a `.c8p` cartridge rather than a single ROM.
+    pub fn is_cartridge(&self) -> bool {
+        let name = &self.bytes[..self.len as usize];
+        name.len() >= 4 && name[name.len() - 4..].eq_ignore_ascii_case(b".C8P")
+    }
+}
+
+/// The `.ch8` and `.c8p` files in the root directory of the card.
+pub struct RomList {
+    names: [RomName; MAX_ROMS],
+    len: usize,
+}
+
+impl RomList {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&RomName> {
+        self.names[..self.len].get(index)
+    }
+}
+
+pub struct SdCard<SPI, CS>
+where
+    SPI: FullDuplex<u8>,
+    CS: OutputPin,
+{
+    controller: Controller<SdMmcSpi<SPI, CS>, NoClock>,
+    volume: Volume,
+}
+
+impl<SPI, CS> SdCard<SPI, CS>
+where
+    SPI: FullDuplex<u8>,
+    CS: OutputPin,
+{
+    /// Initialise the card and open its first partition. The bus should be
+    /// running at 400kHz or less, as required while a card initialises.
+    pub fn mount(spi: SPI, cs: CS) -> Result<Self> {
+        let mut controller = Controller::new(SdMmcSpi::new(spi, cs), NoClock);
+
+        controller
+            .device()
+            .init()
+            .map_err(embedded_sdmmc::Error::DeviceError)?;
+
+        let volume = controller.get_volume(VolumeIdx(0))?;
+
+        Ok(Self { controller, volume })
+    }
+
+    /// Every `.ch8` and `.c8p` file in the root directory, in directory
+    /// order.
+    pub fn list_roms(&mut self) -> Result<RomList> {
+        let mut list = RomList {
+            names: [RomName::EMPTY; MAX_ROMS],
+            len: 0,
+        };
+
+        let dir = self.controller.open_root_dir(&self.volume)?;
+        let result = self.controller.iterate_dir(&self.volume, &dir, |entry| {
+            let ext = entry.name.extension();
+            let is_rom = !entry.attributes.is_directory()
+                && (ext.eq_ignore_ascii_case(b"CH8") || ext.eq_ignore_ascii_case(b"C8P"));
+
+            if is_rom && list.len < MAX_ROMS {
+                list.names[list.len] = RomName::new(entry.name.base_name(), entry.name.extension());
+                list.len += 1;
+            }
+        });
+        self.controller.close_dir(&self.volume, dir);
+
+        result?;
+        Ok(list)
+    }
+
+    /// Read the ROM or cartridge called `name` into `buf`, returning its
+    /// length.
+    pub fn load_rom(&mut self, name: &RomName, buf: &mut [u8]) -> Result<usize> {
+        let dir = self.controller.open_root_dir(&self.volume)?;
+        let file =
+            self.controller
+                .open_file_in_dir(&mut self.volume, &dir, name.as_str(), Mode::ReadOnly);
+        self.controller.close_dir(&self.volume, dir);
+
+        let mut file = file?;
+        let result = if file.length() as usize > buf.len() {
+            Err(Error::RomTooLarge)
+        } else {
+            self.read(&mut file, buf)
+        };
+        self.controller.close_file(&self.volume, file)?;
+
+        result
+    }
+
+    fn read(&mut self, file: &mut embedded_sdmmc::File, buf: &mut [u8]) -> Result<usize> {
+        let mut len = 0;
+
+        while !file.eof() {
+            len += self.controller.read(&self.volume, file, &mut buf[len..])?;
+        }
+
+        Ok(len)
+    }
+}
@@ -0,0 +1,223 @@
+//! User settings kept in the last 4K sector of flash, which `memory.x`
+//! leaves out of the program's flash region.
+//!
+//! Flash can't be read while it is being erased or programmed, and the
+//! program runs from flash through the XIP cache. `save` therefore does the
+//! write from a function placed in RAM, with interrupts disabled, calling the
+//! bootrom's flash routines through pointers looked up beforehand. Afterwards
+//! XIP is brought back up by running a copy of the boot2 stage from RAM, as
+//! the pico-sdk does, so flash reads are as fast as before.
+
+use crate::{
+    governor::CycleGovernor,
+    keypad::{self, Keymap},
+    quirks::Quirks,
+    screen::Sh1106Config,
+};
+
+const MAGIC: [u8; 4] = *b"C8PS";
+const VERSION: u8 = 3;
+
+/// Bytes of encoded settings, including the CRC.
+const LEN: usize = 36;
+
+const FLASH_SIZE: u32 = 2048 * 1024;
+const SECTOR_SIZE: u32 = 4096;
+const PAGE_SIZE: usize = 256;
+const SECTOR_ERASE_CMD: u8 = 0x20;
+
+/// Offset of the settings sector from the start of flash.
+const OFFSET: u32 = FLASH_SIZE - SECTOR_SIZE;
+
+/// Where flash appears in the address space through XIP.
+const XIP_BASE: u32 = 0x1000_0000;
+
+/// Words in the boot2 stage at the start of flash.
+const BOOT2_WORDS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub contrast: u8,
+    /// Instructions per second, as passed to `CycleGovernor::set_rate`.
+    pub rate: u32,
+    /// Instructions per 60Hz frame, as passed to
+    /// `CycleGovernor::set_cycles_per_frame`. Used in place of `rate` unless
+    /// it is 0.
+    pub cycles_per_frame: u16,
+    pub quirks: Quirks,
+    /// The index of the last ROM picked from the built-in ROM table.
+    pub rom: u8,
+    /// As set up by `calibrate::run`, unless a cartridge brings its own.
+    pub keymap: Keymap,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            contrast: Sh1106Config::default().contrast,
+            rate: CycleGovernor::DEFAULT_RATE,
+            cycles_per_frame: 0,
+            quirks: Quirks::default(),
+            rom: 0,
+            keymap: keypad::KEYMAP,
+        }
+    }
+}
+
+impl Settings {
+    fn encode(&self) -> [u8; LEN] {
+        let mut bytes = [0; LEN];
+
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        bytes[5] = self.contrast;
+        bytes[6] = self.rom;
+        bytes[7] = self.quirks.bits();
+        bytes[8..12].copy_from_slice(&self.rate.to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.cycles_per_frame.to_le_bytes());
+
+        for (byte, &key) in bytes[16..32].iter_mut().zip(self.keymap.iter().flatten()) {
+            *byte = key;
+        }
+
+        let crc = crc32(&bytes[..LEN - 4]);
+        bytes[LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// `None` for a blank or corrupt sector, or one written by a different
+    /// version of the firmware.
+    fn decode(bytes: &[u8; LEN]) -> Option<Self> {
+        let crc = u32::from_le_bytes([bytes[32], bytes[33], bytes[34], bytes[35]]);
+
+        if bytes[..4] != MAGIC || bytes[4] != VERSION || crc32(&bytes[..LEN - 4]) != crc {
+            return None;
+        }
+
+        let mut keymap = [[0; 4]; 4];
+
+        for (key, &byte) in keymap.iter_mut().flatten().zip(&bytes[16..32]) {
+            *key = byte;
+        }
+
+        keypad::validate_keymap(&keymap).ok()?;
+
+        Some(Self {
+            contrast: bytes[5],
+            rom: bytes[6],
+            quirks: Quirks::from_bits(bytes[7]),
+            rate: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            cycles_per_frame: u16::from_le_bytes([bytes[12], bytes[13]]),
+            keymap,
+        })
+    }
+}
+
+/// Read the saved settings, or the defaults if none have been saved.
+pub fn load() -> Settings {
+    let mut bytes = [0; LEN];
+    let base = (XIP_BASE + OFFSET) as *const u8;
+
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        // Safety: the settings sector is always mapped and never written
+        // while the program is running from flash.
+        *byte = unsafe { base.add(i).read_volatile() };
+    }
+
+    Settings::decode(&bytes).unwrap_or_default()
+}
+
+/// Write `settings` to flash, if they differ from what is saved. The sector
+/// is erased first, which stops everything for around 50ms, so call this
+/// when settings change rather than regularly.
+pub fn save(settings: &Settings) {
+    if load() == *settings {
+        return;
+    }
+
+    let mut page = [0xFF; PAGE_SIZE];
+    page[..LEN].copy_from_slice(&settings.encode());
+
+    let rom = RomFns::lookup();
+    let mut boot2 = [0u32; BOOT2_WORDS];
+
+    for (i, word) in boot2.iter_mut().enumerate() {
+        // Safety: boot2 is always mapped at the start of flash.
+        *word = unsafe { (XIP_BASE as *const u32).add(i).read_volatile() };
+    }
+
+    cortex_m::interrupt::free(|_| {
+        // Safety: interrupts are disabled, so nothing else runs from flash
+        // until the write has finished, and the second core is unused.
+        unsafe { write_sector(&rom, &boot2, page.as_ptr()) }
+    });
+}
+
+/// Erase the settings sector and program its first page from `data`. Runs
+/// from RAM and touches nothing in flash, including other functions.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn write_sector(rom: &RomFns, boot2: &[u32; BOOT2_WORDS], data: *const u8) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    (rom.flash_range_erase)(OFFSET, SECTOR_SIZE as usize, SECTOR_SIZE, SECTOR_ERASE_CMD);
+    (rom.flash_range_program)(OFFSET, data, PAGE_SIZE);
+    (rom.flash_flush_cache)();
+
+    // The Thumb bit must be set to call into the copy.
+    let boot2: extern "C" fn() = core::mem::transmute(boot2.as_ptr() as usize + 1);
+    boot2();
+}
+
+/// The bootrom's flash routines. See section 2.8.3 of the RP2040 datasheet.
+struct RomFns {
+    connect_internal_flash: extern "C" fn(),
+    flash_exit_xip: extern "C" fn(),
+    flash_range_erase: extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: extern "C" fn(),
+}
+
+impl RomFns {
+    fn lookup() -> Self {
+        // Safety: the bootrom is always mapped at address 0, and these codes
+        // name functions with the signatures given in the datasheet.
+        unsafe {
+            Self {
+                connect_internal_flash: core::mem::transmute(rom_func(*b"IF")),
+                flash_exit_xip: core::mem::transmute(rom_func(*b"EX")),
+                flash_range_erase: core::mem::transmute(rom_func(*b"RE")),
+                flash_range_program: core::mem::transmute(rom_func(*b"RP")),
+                flash_flush_cache: core::mem::transmute(rom_func(*b"FC")),
+            }
+        }
+    }
+}
+
+/// Look up a bootrom function by its two character code.
+unsafe fn rom_func(code: [u8; 2]) -> usize {
+    type Lookup = extern "C" fn(*const u16, u32) -> usize;
+
+    let table = (0x14 as *const u16).read() as *const u16;
+    let lookup: Lookup = core::mem::transmute((0x18 as *const u16).read() as usize);
+
+    lookup(table, u16::from_le_bytes(code) as u32)
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in bytes {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
@@ -0,0 +1,66 @@
+//! The screen shown at power-up, which fades in and out again before the
+//! emulator starts. Since it lights up every part of the drawing path, a
+//! board which shows it has a working panel.
+
+use crate::screen::{self, DisplayBus, Sh1106Display, CHAR_ADVANCE, GLYPH_HEIGHT};
+use chip8::pal::{Delay, Screen};
+use embedded_hal::digital::v2::OutputPin;
+
+type Result<T = ()> = core::result::Result<T, screen::Error>;
+
+const TITLE: &str = "CHIP-8";
+const VERSION: &str = concat!("v", env!("CARGO_PKG_VERSION"));
+
+/// Delay between contrast steps while fading, under half a second for a fade
+/// to the default contrast.
+const FADE_STEP_US: u32 = 3_000;
+
+/// A 16x16 chip, as the left and right halves of each row so that it can be
+/// drawn as two sprites.
+const LOGO_LEFT: [u8; 16] = [
+    0x12, 0x12, 0x3F, 0x20, 0xE8, 0x20, 0x20, 0xE0, 0x20, 0x20, 0xE0, 0x20, 0x20, 0x3F, 0x12, 0x12,
+];
+const LOGO_RIGHT: [u8; 16] = [
+    0x48, 0x48, 0xFC, 0x04, 0x07, 0x04, 0x04, 0x07, 0x04, 0x04, 0x07, 0x04, 0x04, 0xFC, 0x48, 0x48,
+];
+
+/// Fade the logo, title and version in at the contrast the screen is set to,
+/// hold them for `hold_us` and fade them out again, leaving the screen clear
+/// at its original contrast. Call it straight after `init`, as it leaves
+/// drawing immediate rather than deferred.
+pub fn show<BUS, RS, D, const COLS: usize, const ROWS: usize>(
+    screen: &mut Sh1106Display<BUS, RS, COLS, ROWS>,
+    delay: &mut D,
+    hold_us: u32,
+) -> Result
+where
+    BUS: DisplayBus,
+    RS: OutputPin,
+    D: Delay,
+{
+    let contrast = screen.contrast();
+    let line = GLYPH_HEIGHT + 2;
+    let top = screen
+        .height()
+        .saturating_sub(LOGO_LEFT.len() as u8 + 2 * line)
+        / 2;
+    let centre = |text: &str| (screen.width() - text.len() as u8 * CHAR_ADVANCE) / 2;
+    let (title_x, version_x) = (centre(TITLE), centre(VERSION));
+    let logo_x = (screen.width() - 16) / 2;
+
+    screen.set_contrast(0)?;
+    screen.set_deferred(true);
+    screen.xor_rows(logo_x, top, &LOGO_LEFT)?;
+    screen.xor_rows(logo_x + 8, top, &LOGO_RIGHT)?;
+    screen.draw_str(title_x, top + LOGO_LEFT.len() as u8 + 2, TITLE)?;
+    screen.draw_str(version_x, top + LOGO_LEFT.len() as u8 + 2 + line, VERSION)?;
+    screen.flush()?;
+    screen.set_deferred(false);
+
+    screen.fade_to(contrast, delay, FADE_STEP_US)?;
+    delay.delay_us(hold_us).map_err(|_| screen::Error::Delay)?;
+    screen.fade_to(0, delay, FADE_STEP_US)?;
+
+    screen.clear()?;
+    screen.set_contrast(contrast)
+}
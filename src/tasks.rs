@@ -0,0 +1,362 @@
+//! An async alternative to the blocking run loop in `main`, enabled by the
+//! `embassy` feature. The keypad scan, the 60Hz tick and the screen flush
+//! each run as their own task on a cooperative executor, and the emulator
+//! task hears from them over channels. The synchronous loop remains the
+//! default.
+//!
+//! No embassy HAL is used, so there is no embassy time driver either. Tasks
+//! measure time with `TickTimer` and yield to each other while they wait.
+//! The ROM menus, the perf overlay, the debugger, the contrast keys and the
+//! board buttons are only available from `main`.
+
+use crate::{
+    buzzer::Buzzer,
+    control::{Control, Controls, RunState},
+    delay::Delay,
+    emulator::{self, Emulator},
+    governor::{CycleGovernor, DisplayWait},
+    idle::{self, Brightness, IdleDimmer},
+    led::StatusLed,
+    loader::{Event, UsbLoader},
+    screen::{CHAR_ADVANCE, GLYPH_HEIGHT, PLANE_0},
+    timer::{TickTimer, TICK_HZ},
+    App, Keys, Oled, SCREEN_RETRIES, WATCHDOG_TIMEOUT_US,
+};
+use chip8::pal::{self, Keypad};
+use cortex_m::singleton;
+use embassy_executor::{Executor, Spawner};
+use embassy_futures::yield_now;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, mutex::Mutex};
+use embedded_hal::watchdog::{Watchdog as _, WatchdogEnable as _};
+use embedded_time::duration::Microseconds;
+use rp_pico::hal::{
+    gpio::{bank0::Gpio25, Pin, PushPullOutput},
+    pwm, usb, Watchdog,
+};
+
+#[cfg(feature = "speed-knob")]
+use crate::Knob;
+
+type SharedScreen = Mutex<CriticalSectionRawMutex, Oled>;
+
+/// Key masks from `keypad_task`, sent whenever the held keys change.
+static KEYS: Channel<CriticalSectionRawMutex, u16, 4> = Channel::new();
+
+/// The number of 60Hz ticks since the last message, from `tick_task`.
+static TICKS: Channel<CriticalSectionRawMutex, u64, 4> = Channel::new();
+
+/// Tells `flush_task` a frame has passed.
+static FRAMES: Channel<CriticalSectionRawMutex, (), 1> = Channel::new();
+
+/// Busy-waits on the 1MHz timer, so the keypad task can be given a delay
+/// while the SysTick delay stays with the emulator.
+struct TimerDelay(&'static TickTimer);
+
+impl pal::Delay for TimerDelay {
+    type Error = pal::Error;
+
+    fn delay_us(&mut self, us: u32) -> Result<(), Self::Error> {
+        let until = self.0.now_us() + us as u64;
+        while self.0.now_us() < until {}
+        Ok(())
+    }
+}
+
+/// A `Keypad` which reports the last mask received from `keypad_task`. Only
+/// single keys are reported, as with the GPIO keypad.
+struct ChannelKeypad {
+    keys: u16,
+}
+
+impl Keypad for ChannelKeypad {
+    type Error = pal::Error;
+
+    fn key_is_pressed(&self) -> Result<bool, Self::Error> {
+        Ok(self.keys != 0)
+    }
+
+    fn read_key<D: pal::Delay>(&mut self, _: &mut D) -> Result<Option<u8>, Self::Error> {
+        #[cfg(not(feature = "turbo"))]
+        let keys = self.keys;
+        #[cfg(feature = "turbo")]
+        let keys = self.keys & !(1 << crate::control::TURBO_KEY);
+
+        Ok(match keys.count_ones() {
+            1 => Some(keys.trailing_zeros() as u8),
+            _ => None,
+        })
+    }
+}
+
+/// Yield to the other tasks until the timer reaches `us`.
+async fn wait_until(timer: &TickTimer, us: u64) {
+    while timer.now_us() < us {
+        yield_now().await;
+    }
+}
+
+/// Run the emulator on the executor, never returning.
+pub fn run(app: App) -> ! {
+    let App {
+        mut screen,
+        keypad,
+        buzzer,
+        delay,
+        timer,
+        emulator,
+        loader,
+        led,
+        mut watchdog,
+        dma: _,
+        settings: _,
+        rate,
+        cycles_per_frame,
+        #[cfg(feature = "buttons")]
+            buttons: _,
+        #[cfg(feature = "speed-knob")]
+        knob,
+        #[cfg(feature = "temp-overlay")]
+            temp: _,
+        #[cfg(all(feature = "temp-overlay", not(feature = "speed-knob")))]
+            adc: _,
+    } = app;
+
+    // Drawing only touches the framebuffer, `flush_task` sends it.
+    screen.set_deferred(true);
+    watchdog.pause_on_debug(true);
+    watchdog.start(Microseconds(WATCHDOG_TIMEOUT_US));
+
+    let timer: &'static TickTimer = singleton!(: TickTimer = timer).unwrap();
+    let screen: &'static SharedScreen = singleton!(: SharedScreen = Mutex::new(screen)).unwrap();
+    let executor = singleton!(: Executor = Executor::new()).unwrap();
+
+    executor.run(|spawner: Spawner| {
+        spawner.spawn(keypad_task(keypad, timer)).unwrap();
+        spawner.spawn(tick_task(timer)).unwrap();
+        spawner.spawn(flush_task(screen)).unwrap();
+        spawner
+            .spawn(emulator_task(Machine {
+                screen,
+                buzzer,
+                delay,
+                timer,
+                emulator,
+                loader,
+                led,
+                watchdog,
+                rate,
+                cycles_per_frame,
+                #[cfg(feature = "speed-knob")]
+                knob,
+            }))
+            .unwrap();
+    })
+}
+
+/// Scan the keypad once per tick, sending the mask when it changes.
+#[embassy_executor::task]
+async fn keypad_task(mut keypad: Keys, timer: &'static TickTimer) {
+    let mut delay = TimerDelay(timer);
+    let mut last = 0;
+
+    loop {
+        let keys = match keypad.key_is_pressed() {
+            Ok(true) => keypad.scan(&mut delay).unwrap_or(0),
+            _ => 0,
+        };
+
+        if keys != last {
+            last = keys;
+            KEYS.send(keys).await;
+        }
+
+        let next = (timer.ticks() + 1) * 1_000_000 / TICK_HZ;
+        wait_until(timer, next).await;
+    }
+}
+
+/// Count 60Hz ticks, passing them to the emulator and the flush task.
+#[embassy_executor::task]
+async fn tick_task(timer: &'static TickTimer) {
+    let mut tick = timer.ticks();
+
+    loop {
+        wait_until(timer, (tick + 1) * 1_000_000 / TICK_HZ).await;
+
+        let now = timer.ticks();
+        TICKS.send(now - tick).await;
+        tick = now;
+
+        // A frame already waiting covers this one too.
+        FRAMES.try_send(()).ok();
+    }
+}
+
+/// Send whatever has been drawn to the panel, once per frame.
+#[embassy_executor::task]
+async fn flush_task(screen: &'static SharedScreen) {
+    loop {
+        FRAMES.recv().await;
+        screen.lock().await.flush().ok();
+    }
+}
+
+/// Everything `emulator_task` owns.
+struct Machine {
+    screen: &'static SharedScreen,
+    buzzer: Buzzer<pwm::Pwm7>,
+    delay: Delay,
+    timer: &'static TickTimer,
+    emulator: Emulator,
+    loader: UsbLoader<'static, usb::UsbBus>,
+    led: StatusLed<Pin<Gpio25, PushPullOutput>>,
+    watchdog: Watchdog,
+    rate: u32,
+    cycles_per_frame: u16,
+    #[cfg(feature = "speed-knob")]
+    knob: Knob,
+}
+
+/// Runs instructions at the governed rate between messages from the other
+/// tasks, yielding after each pass.
+#[embassy_executor::task]
+async fn emulator_task(mut m: Machine) {
+    let mut governor = CycleGovernor::new();
+    governor.set_rate(m.rate);
+    governor.set_cycles_per_frame(m.cycles_per_frame);
+    let mut display_wait = DisplayWait::new();
+    let mut controls = Controls::new();
+    let mut keypad = ChannelKeypad { keys: 0 };
+    let mut dimmer = IdleDimmer::new();
+    let bright = m.screen.lock().await.contrast();
+    let mut paused_frame = *m.screen.lock().await.buffer();
+    let mut screen_retries = SCREEN_RETRIES;
+
+    loop {
+        // Fed from the emulator task, as the other tasks would carry on
+        // regardless if it stopped yielding.
+        m.watchdog.feed();
+
+        match m.loader.poll(&mut m.emulator) {
+            Some(Event::RomLoaded) => {
+                let mut screen = m.screen.lock().await;
+                screen.set_plane_mask(PLANE_0);
+                screen.set_hires(false).ok();
+                screen.clear().ok();
+                controls = Controls::new();
+            }
+            Some(Event::Screenshot) => {
+                let screen = m.screen.lock().await;
+                let rows = &screen.buffer()[..screen.height() as usize];
+                m.loader.send_pbm(screen.width(), rows);
+            }
+            None => {}
+        }
+
+        while let Ok(keys) = KEYS.try_recv() {
+            keypad.keys = keys;
+        }
+
+        let mut elapsed = 0;
+        while let Ok(ticks) = TICKS.try_recv() {
+            elapsed += ticks;
+        }
+
+        // As in `main`, controls are checked once per tick so that a held
+        // reset combination is timed even when the keys don't change.
+        if elapsed > 0 {
+            if m.loader.receiving() {
+                m.led.rom_loading();
+            } else {
+                m.led.heartbeat(elapsed);
+            }
+
+            match dimmer.update(keypad.keys, m.timer.now_us()) {
+                Some(Brightness::Dim) => {
+                    let mut screen = m.screen.lock().await;
+                    screen
+                        .fade_to(idle::DIM_CONTRAST, &mut m.delay, idle::FADE_STEP_US)
+                        .ok();
+                }
+                Some(Brightness::Wake) => {
+                    m.screen.lock().await.set_contrast(bright).ok();
+                }
+                None => {}
+            }
+
+            match controls.update(keypad.keys, m.timer.now_us()) {
+                Some(Control::Pause) => {
+                    let mut screen = m.screen.lock().await;
+                    paused_frame = *screen.buffer();
+                    m.buzzer.off();
+
+                    screen
+                        .fill_rect(0, 0, 5 * CHAR_ADVANCE + 1, GLYPH_HEIGHT + 2, false)
+                        .ok();
+                    screen.draw_str(1, 1, "PAUSE").ok();
+                }
+                Some(Control::Resume) => {
+                    *m.screen.lock().await.buffer_mut() = paused_frame;
+                }
+                Some(Control::Reset) => {
+                    let mut screen = m.screen.lock().await;
+                    m.emulator.reset();
+                    screen.set_plane_mask(PLANE_0);
+                    screen.set_hires(false).ok();
+                    screen.clear().ok();
+                }
+                None => {}
+            }
+
+            #[cfg(feature = "turbo")]
+            governor.set_turbo(controls.turbo());
+        }
+
+        if controls.state() == RunState::Paused {
+            yield_now().await;
+            continue;
+        }
+
+        if elapsed > 0 {
+            #[cfg(feature = "speed-knob")]
+            {
+                let rate = m.knob.rate();
+
+                if rate != governor.rate() {
+                    governor.set_rate(rate);
+                }
+            }
+
+            for _ in 0..elapsed {
+                m.emulator.tick_timers();
+            }
+
+            if m.emulator.sound_active() {
+                m.buzzer.on();
+            } else {
+                m.buzzer.off();
+            }
+        }
+
+        let draw = m.emulator.quirks().display_wait && m.emulator.draws_next();
+
+        if !(draw && display_wait.blocked(elapsed > 0)) && governor.ready(m.timer.now_us()) {
+            let mut screen = m.screen.lock().await;
+
+            if draw {
+                display_wait.drawn();
+            }
+
+            match m.emulator.step(&mut *screen, &mut keypad, &mut m.delay) {
+                Ok(()) => screen_retries = SCREEN_RETRIES,
+                Err(emulator::Error::Chip8(pal::Error::Screen)) if screen_retries > 0 => {
+                    screen_retries -= 1;
+                    screen.recover().ok();
+                }
+                Err(_) => panic!("emulator step failed"),
+            }
+        }
+
+        yield_now().await;
+    }
+}
@@ -0,0 +1,92 @@
+//! The RP2040's die temperature, read through the ADC's internal sensor and
+//! shown in a corner of the screen with the `temp-overlay` feature.
+
+use embedded_hal::adc::{Channel, OneShot};
+use rp_pico::hal::adc::TempSense;
+
+/// How often `TempSensor::update` takes a reading.
+pub const INTERVAL_US: u64 = 1_000_000;
+
+/// Characters in the longest reading, `-40C`.
+pub const TEXT_LEN: usize = 4;
+
+/// The ADC's reference voltage and full scale.
+const VREF_UV: i32 = 3_300_000;
+const ADC_STEPS: i32 = 4_096;
+
+/// The sensor reads 0.706V at 27C, falling by 1.721mV per degree. See
+/// section 4.9.5 of the RP2040 datasheet.
+const V27_UV: i32 = 706_000;
+const SLOPE_UV: i32 = 1_721;
+
+pub struct TempSensor {
+    channel: TempSense,
+    last_us: Option<u64>,
+}
+
+impl TempSensor {
+    /// Takes the channel returned by `Adc::enable_temp_sensor`.
+    pub fn new(channel: TempSense) -> Self {
+        Self {
+            channel,
+            last_us: None,
+        }
+    }
+
+    /// Read the die temperature in whole degrees. A failed read gives 27C,
+    /// where the sensor is calibrated.
+    pub fn read_celsius<A, ADC>(&mut self, adc: &mut A) -> i16
+    where
+        A: OneShot<ADC, u16, TempSense>,
+        TempSense: Channel<ADC>,
+    {
+        let raw = match adc.read(&mut self.channel) {
+            Ok(raw) => raw as i64,
+            Err(_) => return 27,
+        };
+
+        let uv = (raw * VREF_UV as i64 / ADC_STEPS as i64) as i32;
+        (27 - (uv - V27_UV) / SLOPE_UV) as i16
+    }
+
+    /// Take a reading if a full interval has passed since the last one, or
+    /// straight away the first time.
+    pub fn update<A, ADC>(&mut self, adc: &mut A, now_us: u64) -> Option<i16>
+    where
+        A: OneShot<ADC, u16, TempSense>,
+        TempSense: Channel<ADC>,
+    {
+        match self.last_us {
+            Some(last_us) if now_us.wrapping_sub(last_us) < INTERVAL_US => None,
+            _ => {
+                self.last_us = Some(now_us);
+                Some(self.read_celsius(adc))
+            }
+        }
+    }
+}
+
+/// Write `celsius` into `buf` as, for example, `31C`, returning the
+/// characters used.
+pub fn text(celsius: i16, buf: &mut [u8; TEXT_LEN]) -> &str {
+    let mut start = buf.len() - 1;
+    let mut rest = celsius.unsigned_abs().min(99);
+    buf[start] = b'C';
+
+    loop {
+        start -= 1;
+        buf[start] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+
+        if rest == 0 {
+            break;
+        }
+    }
+
+    if celsius < 0 {
+        start -= 1;
+        buf[start] = b'-';
+    }
+
+    core::str::from_utf8(&buf[start..]).unwrap_or("")
+}
@@ -0,0 +1,31 @@
+use rp_pico::hal::{
+    pac::{RESETS, TIMER},
+    Timer,
+};
+
+/// Rate of the CHIP-8 delay and sound timers.
+pub const TICK_HZ: u64 = 60;
+
+/// Counts 60Hz ticks using the RP2040's free-running 1MHz timer, so timing
+/// stays correct however long each instruction takes.
+pub struct TickTimer {
+    timer: Timer,
+}
+
+impl TickTimer {
+    pub fn new(timer: TIMER, resets: &mut RESETS) -> Self {
+        Self {
+            timer: Timer::new(timer, resets),
+        }
+    }
+
+    /// Microseconds since boot.
+    pub fn now_us(&self) -> u64 {
+        self.timer.get_counter()
+    }
+
+    /// 60Hz ticks since boot.
+    pub fn ticks(&self) -> u64 {
+        self.now_us() * TICK_HZ / 1_000_000
+    }
+}
@@ -0,0 +1,120 @@
+//! A record of the last `LEN` instructions executed, enabled by the `trace`
+//! feature, for working out what the CPU was doing before a crash.
+//!
+//! The ring buffer is a `static` in the `.uninit` section, which isn't
+//! zeroed at boot, so it survives a watchdog reset as well as being
+//! reachable from the panic handler, which can't borrow from `main`. A magic
+//! word tells a trace left by the last run from whatever was in RAM at power
+//! on. Only the run loop writes to it, from the one core, so no locking is
+//! needed.
+
+use core::{mem::MaybeUninit, ptr::addr_of_mut};
+
+/// Instructions kept. Each takes four bytes.
+pub const LEN: usize = 12;
+
+const MAGIC: u32 = 0x7472_6163;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub pc: u16,
+    pub opcode: u16,
+}
+
+impl Entry {
+    /// Characters in `text`.
+    pub const TEXT_LEN: usize = 9;
+
+    /// As `PPPP OOOO` in hex, ready to draw on screen.
+    pub fn text(&self) -> [u8; Self::TEXT_LEN] {
+        const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+        let mut text = [b' '; Self::TEXT_LEN];
+
+        for (word, at) in [(self.pc, 0), (self.opcode, 5)] {
+            for (n, digit) in text[at..at + 4].iter_mut().enumerate() {
+                *digit = HEX[(word >> (12 - 4 * n) & 0xF) as usize];
+            }
+        }
+
+        text
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Trace {
+    magic: u32,
+    next: usize,
+    entries: [Entry; LEN],
+}
+
+impl Trace {
+    const fn new() -> Self {
+        Self {
+            magic: MAGIC,
+            next: 0,
+            entries: [Entry { pc: 0, opcode: 0 }; LEN],
+        }
+    }
+
+    fn valid(&self) -> bool {
+        self.magic == MAGIC && self.next < 2 * LEN
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.entries[self.next % LEN] = entry;
+
+        // Counts up to `2 * LEN` rather than wrapping at `LEN`, so a buffer
+        // which hasn't filled yet can be told from one which has.
+        self.next = if self.next + 1 == 2 * LEN {
+            LEN
+        } else {
+            self.next + 1
+        };
+    }
+
+    /// The recorded instructions, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        let (start, len) = if self.next < LEN {
+            (0, self.next)
+        } else {
+            (self.next % LEN, LEN)
+        };
+
+        self.entries.iter().cycle().skip(start).take(len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next == 0
+    }
+}
+
+#[link_section = ".uninit.TRACE"]
+static mut TRACE: MaybeUninit<Trace> = MaybeUninit::uninit();
+
+/// Take the trace left by the run before a reset, if there is one, and start
+/// a new one. Call this once at boot, before anything is recorded.
+pub fn start() -> Option<Trace> {
+    // Safety: nothing else runs yet, and every bit pattern is a valid
+    // `Trace`, so reading it before it is checked is sound.
+    let trace = unsafe { &mut *addr_of_mut!(TRACE) };
+    let last = unsafe { trace.assume_init_read() };
+    trace.write(Trace::new());
+
+    Some(last).filter(|last| last.valid() && !last.is_empty())
+}
+
+/// Record an instruction just before it runs.
+#[inline]
+pub fn record(pc: u16, opcode: u16) {
+    // Safety: `start` has initialised it, and only the run loop calls this.
+    unsafe { (*addr_of_mut!(TRACE)).assume_init_mut() }.push(Entry { pc, opcode });
+}
+
+/// A copy of the trace so far, for the panic handler.
+pub fn snapshot() -> Option<Trace> {
+    // Safety: as for `start`. A panic may interrupt `record` half way, which
+    // leaves at worst one stale entry.
+    let trace = unsafe { (*addr_of_mut!(TRACE)).assume_init_read() };
+    Some(trace).filter(Trace::valid)
+}
@@ -0,0 +1,72 @@
+use crate::{emulator, keypad, menu, screen};
+use chip8::pal;
+
+pub type Result<T = ()> = core::result::Result<T, AppError>;
+
+/// Every way setup or the run loop can fail, so both can share one `Result`
+/// and each failure maps to a single diagnostic.
+#[derive(Debug, Clone, Copy)]
+pub enum AppError {
+    Clocks,
+    Usb,
+    Dma,
+    NoRoms,
+    RomTooLarge,
+    Screen(screen::Error),
+    Keypad(keypad::Error),
+    Chip8(pal::Error),
+}
+
+impl AppError {
+    /// The number of flashes which follow SOS on the LED.
+    pub fn code(&self) -> u8 {
+        match self {
+            AppError::Clocks => 1,
+            AppError::Screen(_) => 2,
+            AppError::Keypad(keypad::Error::StuckKey(_)) => 8,
+            AppError::Keypad(_) => 3,
+            AppError::NoRoms => 4,
+            AppError::RomTooLarge => 5,
+            AppError::Usb => 6,
+            AppError::Chip8(_) => 7,
+            AppError::Dma => 9,
+        }
+    }
+}
+
+impl From<screen::Error> for AppError {
+    fn from(err: screen::Error) -> Self {
+        AppError::Screen(err)
+    }
+}
+
+impl From<keypad::Error> for AppError {
+    fn from(err: keypad::Error) -> Self {
+        AppError::Keypad(err)
+    }
+}
+
+impl From<pal::Error> for AppError {
+    fn from(err: pal::Error) -> Self {
+        AppError::Chip8(err)
+    }
+}
+
+impl From<menu::Error> for AppError {
+    fn from(err: menu::Error) -> Self {
+        match err {
+            menu::Error::NoRoms => AppError::NoRoms,
+            menu::Error::Screen(err) => err.into(),
+            menu::Error::Keypad(err) => err.into(),
+        }
+    }
+}
+
+impl From<emulator::Error> for AppError {
+    fn from(err: emulator::Error) -> Self {
+        match err {
+            emulator::Error::RomTooLarge => AppError::RomTooLarge,
+            emulator::Error::Chip8(err) => err.into(),
+        }
+    }
+}
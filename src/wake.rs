@@ -0,0 +1,92 @@
+//! Sleeping the core until a key is pressed, for when the run loop has
+//! nothing to do but wait for one.
+//!
+//! The saving is in the core alone. `wfi` stops it fetching instructions and
+//! gates its clock, but the system clock, PLLs and peripherals keep running
+//! so that USB and the timer carry on. That takes a few milliamps off the
+//! busy-polling loop, which keeps the core at full draw however little there
+//! is to do, rather than the near-zero of dormant mode. The panel, even
+//! dimmed, is unaffected.
+
+use crate::{keypad, Keys};
+use chip8::pal::Keypad as _;
+use cortex_m::{asm, interrupt, peripheral::NVIC};
+use rp_pico::hal::{gpio::DynPin, pac::Interrupt};
+
+/// IO_BANK0 raw interrupt and processor 0 enable registers, eight GPIOs to a
+/// register. The HAL doesn't expose interrupts on `DynPin`.
+const INTR0: usize = 0x4001_40F0;
+const PROC0_INTE0: usize = 0x4001_4100;
+
+/// Atomic set and clear aliases of a peripheral register.
+const SET: usize = 0x2000;
+const CLEAR: usize = 0x3000;
+
+const EDGE_LOW: u32 = 0b0100;
+const EDGE_HIGH: u32 = 0b1000;
+
+/// The register offset and bit shift for `pin`'s interrupt flags.
+fn flags(pin: &DynPin) -> (usize, u32) {
+    let num = pin.id().num as usize;
+    (num / 8 * 4, num as u32 % 8 * 4)
+}
+
+fn write(addr: usize, bits: u32) {
+    // Safety: only called with IO_BANK0 interrupt registers and their
+    // aliases, which nothing else in the firmware touches.
+    unsafe { (addr as *mut u32).write_volatile(bits) }
+}
+
+impl keypad::WakePin for DynPin {
+    fn listen_edge(&mut self, rising: bool) {
+        let (offset, shift) = flags(self);
+        let edge = if rising { EDGE_HIGH } else { EDGE_LOW };
+
+        write(INTR0 + offset, (EDGE_LOW | EDGE_HIGH) << shift);
+        write(PROC0_INTE0 + offset + SET, edge << shift);
+    }
+
+    fn unlisten(&mut self) {
+        let (offset, shift) = flags(self);
+
+        write(
+            PROC0_INTE0 + offset + CLEAR,
+            (EDGE_LOW | EDGE_HIGH) << shift,
+        );
+        write(INTR0 + offset, (EDGE_LOW | EDGE_HIGH) << shift);
+    }
+}
+
+/// Sleep until a key is pressed or USB needs attention, returning straight
+/// away if a key is already held. The interrupts which wake the core are
+/// never taken, so no handlers are needed. Nothing else wakes it, so stop
+/// the watchdog first.
+pub fn sleep(keypad: &mut Keys) -> Result<(), keypad::Error> {
+    keypad.arm_wake_interrupt()?;
+
+    let slept = interrupt::free(|_| {
+        if keypad.key_is_pressed()? {
+            return Ok(());
+        }
+
+        // Safety: with interrupts disabled, a pending interrupt ends the
+        // `wfi` without its handler running, and both are masked again
+        // before interrupts are enabled.
+        unsafe {
+            NVIC::unmask(Interrupt::IO_IRQ_BANK0);
+            NVIC::unmask(Interrupt::USBCTRL_IRQ);
+        }
+
+        asm::wfi();
+
+        NVIC::mask(Interrupt::IO_IRQ_BANK0);
+        NVIC::mask(Interrupt::USBCTRL_IRQ);
+        Ok(())
+    });
+
+    keypad.disarm_wake_interrupt();
+    NVIC::unpend(Interrupt::IO_IRQ_BANK0);
+    NVIC::unpend(Interrupt::USBCTRL_IRQ);
+
+    slept
+}